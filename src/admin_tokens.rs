@@ -0,0 +1,306 @@
+use crate::cache::get_persistent_db;
+use crate::utils::hash_token;
+use chrono::Utc;
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+
+/// Prefix of scoped admin tokens, sent as `Authorization: Bearer p2o-admin-...`
+pub const ADMIN_TOKEN_PREFIX: &str = "p2o-admin-";
+
+const ADMIN_TOKENS_TREE: &str = "admin_tokens";
+const ADMIN_AUDIT_TREE: &str = "admin_audit";
+
+/// Permission carried by an admin token
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdminScope {
+    #[serde(rename = "config:read")]
+    ConfigRead,
+    #[serde(rename = "config:write")]
+    ConfigWrite,
+    #[serde(rename = "cache:manage")]
+    CacheManage,
+}
+
+impl AdminScope {
+    /// Whether holding this scope allows an action that needs `required`; writing config implies reading it
+    pub fn grants(self, required: AdminScope) -> bool {
+        self == required || (self == AdminScope::ConfigWrite && required == AdminScope::ConfigRead)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AdminScope::ConfigRead => "config:read",
+            AdminScope::ConfigWrite => "config:write",
+            AdminScope::CacheManage => "cache:manage",
+        }
+    }
+}
+
+/// Scoped admin token, stored under the SHA-256 hash of the token itself
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AdminTokenRecord {
+    pub id: String,
+    pub name: String,
+    pub token_hash: String,
+    pub token_prefix: String,
+    pub scopes: Vec<AdminScope>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub revoked: bool,
+    #[serde(default)]
+    pub last_used_at: Option<i64>,
+}
+
+impl AdminTokenRecord {
+    /// Check whether the token may be used at the given unix time
+    pub fn check_usable(&self, now: i64) -> Result<(), &'static str> {
+        if self.revoked {
+            return Err("Admin token has been revoked");
+        }
+        if self.expires_at.is_some_and(|expires_at| now >= expires_at) {
+            return Err("Admin token has expired");
+        }
+        Ok(())
+    }
+
+    pub fn allows(&self, required: AdminScope) -> bool {
+        self.scopes.iter().any(|scope| scope.grants(required))
+    }
+}
+
+/// One use of an admin token
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub timestamp: i64,
+    pub token_id: String,
+    pub token_name: String,
+    pub method: String,
+    pub path: String,
+    pub scope: String,
+    pub status: u16,
+    pub client: String,
+}
+
+fn open_tree(name: &str) -> Result<sled::Tree, String> {
+    get_persistent_db()
+        .open_tree(name)
+        .map_err(|e| format!("Unable to open {} tree: {}", name, e))
+}
+
+fn save_admin_token(record: &AdminTokenRecord) -> Result<(), String> {
+    let tree = open_tree(ADMIN_TOKENS_TREE)?;
+    let bytes = serde_json::to_vec(record)
+        .map_err(|e| format!("Failed to serialize admin token: {}", e))?;
+    tree.insert(record.token_hash.as_bytes(), bytes)
+        .map_err(|e| format!("Failed to save admin token: {}", e))?;
+    get_persistent_db().flush().ok();
+    Ok(())
+}
+
+/// Create a new admin token, returns the raw token (shown once) and the stored record
+pub fn create_admin_token(
+    name: &str,
+    scopes: Vec<AdminScope>,
+    expires_at: Option<i64>,
+) -> Result<(String, AdminTokenRecord), String> {
+    let raw_token = format!("{}{}", ADMIN_TOKEN_PREFIX, nanoid!(40));
+    let record = AdminTokenRecord {
+        id: format!("adm_{}", nanoid!(12)),
+        name: name.to_string(),
+        token_hash: hash_token(&raw_token),
+        token_prefix: raw_token
+            .chars()
+            .take(ADMIN_TOKEN_PREFIX.len() + 4)
+            .collect(),
+        scopes,
+        created_at: Utc::now().timestamp(),
+        expires_at,
+        revoked: false,
+        last_used_at: None,
+    };
+    save_admin_token(&record)?;
+    info!(
+        "🎫 Admin token created | ID: {} | Name: {} | Scopes: {:?}",
+        record.id, record.name, record.scopes
+    );
+    Ok((raw_token, record))
+}
+
+/// List all admin tokens, newest first
+pub fn list_admin_tokens() -> Vec<AdminTokenRecord> {
+    let tree = match open_tree(ADMIN_TOKENS_TREE) {
+        Ok(tree) => tree,
+        Err(e) => {
+            error!("❌ {}", e);
+            return Vec::new();
+        }
+    };
+    let mut records: Vec<AdminTokenRecord> = tree
+        .iter()
+        .values()
+        .filter_map(|value| value.ok())
+        .filter_map(|bytes| serde_json::from_slice(&bytes).ok())
+        .collect();
+    records.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    records
+}
+
+/// Revoke an admin token by id, returns false when no token has that id
+pub fn revoke_admin_token(id: &str) -> Result<bool, String> {
+    let Some(mut record) = list_admin_tokens().into_iter().find(|r| r.id == id) else {
+        return Ok(false);
+    };
+    record.revoked = true;
+    save_admin_token(&record)?;
+    info!(
+        "🚫 Admin token revoked | ID: {} | Name: {}",
+        record.id, record.name
+    );
+    Ok(true)
+}
+
+/// Look up the record of a raw admin token
+pub fn find_admin_token(raw_token: &str) -> Option<AdminTokenRecord> {
+    let tree = match open_tree(ADMIN_TOKENS_TREE) {
+        Ok(tree) => tree,
+        Err(e) => {
+            error!("❌ {}", e);
+            return None;
+        }
+    };
+    match tree.get(hash_token(raw_token).as_bytes()) {
+        Ok(Some(bytes)) => serde_json::from_slice(&bytes).ok(),
+        Ok(None) => None,
+        Err(e) => {
+            error!("❌ Failed to read admin token: {}", e);
+            None
+        }
+    }
+}
+
+// Set last_used_at on the stored record, so a revoke since the record was read is kept
+fn touch_admin_token(tree: &sled::Tree, token_hash: &str, timestamp: i64) -> Result<(), String> {
+    tree.update_and_fetch(token_hash.as_bytes(), |old| {
+        let mut record: AdminTokenRecord = serde_json::from_slice(old?).ok()?;
+        record.last_used_at = Some(timestamp);
+        serde_json::to_vec(&record).ok()
+    })
+    .map_err(|e| format!("Failed to update admin token: {}", e))?;
+    Ok(())
+}
+
+/// Append a token use to the audit trail and update the token's last use
+pub fn record_audit(record: &AdminTokenRecord, entry: AuditEntry) {
+    if let Err(e) = open_tree(ADMIN_TOKENS_TREE)
+        .and_then(|tree| touch_admin_token(&tree, &record.token_hash, entry.timestamp))
+    {
+        error!("❌ {}", e);
+    }
+
+    let tree = match open_tree(ADMIN_AUDIT_TREE) {
+        Ok(tree) => tree,
+        Err(e) => {
+            error!("❌ {}", e);
+            return;
+        }
+    };
+    // Big-endian nanosecond keys keep the trail in chronological order
+    let key = Utc::now()
+        .timestamp_nanos_opt()
+        .unwrap_or_default()
+        .to_be_bytes();
+    let bytes = match serde_json::to_vec(&entry) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("❌ Failed to serialize audit entry: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = tree.insert(key, bytes) {
+        error!("❌ Failed to write audit entry: {}", e);
+        return;
+    }
+    get_persistent_db().flush().ok();
+    debug!(
+        "📝 Admin audit | Token: {} | {} {} | Status: {}",
+        entry.token_id, entry.method, entry.path, entry.status
+    );
+}
+
+/// Most recent audit entries, newest first
+pub fn recent_audit(limit: usize) -> Vec<AuditEntry> {
+    let tree = match open_tree(ADMIN_AUDIT_TREE) {
+        Ok(tree) => tree,
+        Err(e) => {
+            error!("❌ {}", e);
+            return Vec::new();
+        }
+    };
+    tree.iter()
+        .values()
+        .rev()
+        .filter_map(|value| value.ok())
+        .filter_map(|bytes| serde_json::from_slice(&bytes).ok())
+        .take(limit)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_scopes_gate_actions() {
+        let record = AdminTokenRecord {
+            id: "adm_test".to_string(),
+            name: "deploy".to_string(),
+            token_hash: hash_token("p2o-admin-test"),
+            token_prefix: "p2o-admin-test".to_string(),
+            scopes: serde_json::from_str(r#"["config:write"]"#).unwrap(),
+            created_at: 1_000,
+            expires_at: Some(3_000),
+            revoked: false,
+            last_used_at: None,
+        };
+        assert!(record.allows(AdminScope::ConfigRead));
+        assert!(record.allows(AdminScope::ConfigWrite));
+        assert!(!record.allows(AdminScope::CacheManage));
+        assert!(record.check_usable(2_000).is_ok());
+        assert!(record.check_usable(3_000).is_err());
+    }
+
+    #[test]
+    fn audit_keeps_a_concurrent_revocation() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree(ADMIN_TOKENS_TREE).unwrap();
+        let record = AdminTokenRecord {
+            id: "adm_test".to_string(),
+            name: "deploy".to_string(),
+            token_hash: hash_token("p2o-admin-test"),
+            token_prefix: "p2o-admin-test".to_string(),
+            scopes: vec![AdminScope::ConfigWrite],
+            created_at: 1_000,
+            expires_at: None,
+            revoked: false,
+            last_used_at: None,
+        };
+        // The admin revokes the token while one of its config:write requests is in flight
+        let revoked = AdminTokenRecord {
+            revoked: true,
+            ..record.clone()
+        };
+        tree.insert(
+            record.token_hash.as_bytes(),
+            serde_json::to_vec(&revoked).unwrap(),
+        )
+        .unwrap();
+        touch_admin_token(&tree, &record.token_hash, 1_234).unwrap();
+
+        let stored: AdminTokenRecord =
+            serde_json::from_slice(&tree.get(record.token_hash.as_bytes()).unwrap().unwrap())
+                .unwrap();
+        assert!(stored.revoked);
+        assert_eq!(stored.last_used_at, Some(1_234));
+    }
+}
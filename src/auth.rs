@@ -0,0 +1,300 @@
+use crate::utils::hash_token;
+use argon2::Argon2;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use nanoid::nanoid;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Cookie holding the admin session id
+pub const SESSION_COOKIE: &str = "poe2openai_admin_session";
+/// Header carrying the session's CSRF token on state-changing admin requests
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+const DEFAULT_USERNAME: &str = "admin";
+const DEFAULT_PASSWORD: &str = "123456";
+const DEFAULT_SESSION_TTL_SECONDS: u64 = 12 * 60 * 60;
+const DEFAULT_MAX_LOGIN_ATTEMPTS: u32 = 5;
+const DEFAULT_LOCKOUT_SECONDS: u64 = 15 * 60;
+
+fn env_number<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+fn password_hash_env() -> Option<String> {
+    std::env::var("ADMIN_PASSWORD_HASH")
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Whether the admin login is still the built-in admin/123456
+pub fn uses_default_credentials() -> bool {
+    password_hash_env().is_none()
+        && std::env::var("ADMIN_PASSWORD")
+            .ok()
+            .is_none_or(|v| v == DEFAULT_PASSWORD)
+}
+
+/// Whether the admin password is configured as plaintext rather than a hash
+pub fn uses_plaintext_password() -> bool {
+    password_hash_env().is_none() && std::env::var("ADMIN_PASSWORD").is_ok()
+}
+
+/// Argon2id PHC string for ADMIN_PASSWORD_HASH
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("Failed to hash password: {}", e))
+}
+
+// Argon2 PHC strings and bcrypt hashes ($2a$/$2b$/$2y$) are both accepted
+fn verify_password_hash(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$2") {
+        return bcrypt::verify(password, hash).unwrap_or_else(|e| {
+            warn!("⚠️ ADMIN_PASSWORD_HASH is not a valid bcrypt hash: {}", e);
+            false
+        });
+    }
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(e) => {
+            warn!("⚠️ ADMIN_PASSWORD_HASH is not a valid PHC string: {}", e);
+            false
+        }
+    }
+}
+
+/// Check admin credentials against ADMIN_USERNAME and ADMIN_PASSWORD_HASH (or plaintext ADMIN_PASSWORD)
+pub async fn verify_admin_credentials(username: &str, password: &str) -> bool {
+    let valid_username =
+        std::env::var("ADMIN_USERNAME").unwrap_or_else(|_| DEFAULT_USERNAME.to_string());
+    let username_ok = hash_token(username) == hash_token(&valid_username);
+    let password_ok = match password_hash_env() {
+        Some(hash) => {
+            // Hash verification is deliberately slow, keep it off the async workers
+            let password = password.to_string();
+            tokio::task::spawn_blocking(move || verify_password_hash(&password, &hash))
+                .await
+                .unwrap_or(false)
+        }
+        None => {
+            let valid_password =
+                std::env::var("ADMIN_PASSWORD").unwrap_or_else(|_| DEFAULT_PASSWORD.to_string());
+            // Compare digests so the comparison time does not depend on the password
+            hash_token(password) == hash_token(&valid_password)
+        }
+    };
+    username_ok && password_ok
+}
+
+/// Logged-in admin session
+#[derive(Clone, Debug)]
+pub struct AdminSession {
+    pub username: String,
+    pub csrf_token: String,
+    expires_at: Instant,
+}
+
+// Sessions are keyed by the hash of their id, so the store never holds usable cookies
+static SESSIONS: OnceLock<Mutex<HashMap<String, AdminSession>>> = OnceLock::new();
+
+fn sessions() -> &'static Mutex<HashMap<String, AdminSession>> {
+    SESSIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub fn session_ttl() -> Duration {
+    Duration::from_secs(env_number(
+        "ADMIN_SESSION_TTL_SECONDS",
+        DEFAULT_SESSION_TTL_SECONDS,
+    ))
+}
+
+/// Start a session, returns the cookie value
+pub fn create_session(username: &str) -> String {
+    let session_id = nanoid!(48);
+    let now = Instant::now();
+    let mut sessions = sessions().lock().unwrap();
+    sessions.retain(|_, session| session.expires_at > now);
+    sessions.insert(
+        hash_token(&session_id),
+        AdminSession {
+            username: username.to_string(),
+            csrf_token: nanoid!(32),
+            expires_at: now + session_ttl(),
+        },
+    );
+    debug!("🔐 Admin session created | Active: {}", sessions.len());
+    session_id
+}
+
+/// Session behind a cookie value, if it has not expired
+pub fn find_session(session_id: &str) -> Option<AdminSession> {
+    let key = hash_token(session_id);
+    let mut sessions = sessions().lock().unwrap();
+    match sessions.get(&key) {
+        Some(session) if session.expires_at > Instant::now() => Some(session.clone()),
+        Some(_) => {
+            sessions.remove(&key);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Whether a login form was posted from this site: `source` is the Origin (or Referer) of the
+/// request, `host` the host it was sent to. Login forms without either are rejected (login CSRF).
+pub fn is_same_origin(source: Option<&str>, host: Option<&str>) -> bool {
+    let source_host = source
+        .filter(|source| *source != "null")
+        .and_then(|source| source.split_once("://"))
+        .and_then(|(_, rest)| rest.split(['/', '?', '#']).next());
+    match (source_host, host) {
+        (Some(source_host), Some(host)) => source_host.eq_ignore_ascii_case(host.trim()),
+        _ => false,
+    }
+}
+
+pub fn end_session(session_id: &str) {
+    sessions().lock().unwrap().remove(&hash_token(session_id));
+}
+
+// Failed logins of one client IP
+#[derive(Default)]
+struct LoginAttempts {
+    failures: u32,
+    first_failure: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+/// Brute-force protection: too many failures within the window lock the client out for the window
+#[derive(Default)]
+struct LoginGuard {
+    clients: HashMap<String, LoginAttempts>,
+}
+
+impl LoginGuard {
+    fn locked_for(&self, client: &str, now: Instant) -> Option<Duration> {
+        self.clients
+            .get(client)
+            .and_then(|attempts| attempts.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| until.duration_since(now))
+    }
+
+    // Returns true when this failure locked the client out
+    fn record_failure(
+        &mut self,
+        client: &str,
+        now: Instant,
+        max_attempts: u32,
+        window: Duration,
+    ) -> bool {
+        self.clients.retain(|_, a| {
+            a.first_failure
+                .is_some_and(|first| now.duration_since(first) < window * 2)
+        });
+        let attempts = self.clients.entry(client.to_string()).or_default();
+        if attempts
+            .first_failure
+            .is_none_or(|first| now.duration_since(first) >= window)
+        {
+            *attempts = LoginAttempts {
+                first_failure: Some(now),
+                ..Default::default()
+            };
+        }
+        attempts.failures += 1;
+        if attempts.failures >= max_attempts.max(1) {
+            attempts.locked_until = Some(now + window);
+            return true;
+        }
+        false
+    }
+
+    fn record_success(&mut self, client: &str) {
+        self.clients.remove(client);
+    }
+}
+
+static LOGIN_GUARD: OnceLock<Mutex<LoginGuard>> = OnceLock::new();
+
+fn login_guard() -> &'static Mutex<LoginGuard> {
+    LOGIN_GUARD.get_or_init(|| Mutex::new(LoginGuard::default()))
+}
+
+/// Remaining lockout of a client IP after repeated failed logins
+pub fn login_locked_for(client: &str) -> Option<Duration> {
+    login_guard()
+        .lock()
+        .unwrap()
+        .locked_for(client, Instant::now())
+}
+
+pub fn record_login_failure(client: &str) {
+    let max_attempts = env_number("ADMIN_MAX_LOGIN_ATTEMPTS", DEFAULT_MAX_LOGIN_ATTEMPTS);
+    let window = Duration::from_secs(env_number("ADMIN_LOCKOUT_SECONDS", DEFAULT_LOCKOUT_SECONDS));
+    let locked =
+        login_guard()
+            .lock()
+            .unwrap()
+            .record_failure(client, Instant::now(), max_attempts, window);
+    if locked {
+        warn!(
+            "🚫 Admin login locked after {} failed attempts | Client: {} | Lockout: {:?}",
+            max_attempts, client, window
+        );
+    } else {
+        warn!("🔒 Failed admin login | Client: {}", client);
+    }
+}
+
+pub fn record_login_success(client: &str) {
+    login_guard().lock().unwrap().record_success(client);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_guard_locks_after_repeated_failures() {
+        let mut guard = LoginGuard::default();
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+        assert!(!guard.record_failure("10.0.0.1", start, 3, window));
+        assert!(!guard.record_failure("10.0.0.1", start, 3, window));
+        assert!(guard.record_failure("10.0.0.1", start, 3, window));
+        assert!(guard.locked_for("10.0.0.1", start).is_some());
+        assert!(guard.locked_for("10.0.0.2", start).is_none());
+        assert!(guard.locked_for("10.0.0.1", start + window).is_none());
+
+        assert!(is_same_origin(
+            Some("https://proxy.example.com"),
+            Some("proxy.example.com")
+        ));
+        assert!(is_same_origin(
+            Some("http://127.0.0.1:8080/admin/login"),
+            Some("127.0.0.1:8080")
+        ));
+        assert!(!is_same_origin(
+            Some("https://evil.example"),
+            Some("proxy.example.com")
+        ));
+        assert!(!is_same_origin(Some("null"), Some("proxy.example.com")));
+        assert!(!is_same_origin(None, Some("proxy.example.com")));
+
+        let hash = hash_password("correct horse").unwrap();
+        assert!(verify_password_hash("correct horse", &hash));
+        assert!(!verify_password_hash("wrong horse", &hash));
+    }
+}
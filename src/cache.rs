@@ -1,5 +1,5 @@
-use crate::types::Config;
-use crate::utils::load_config_from_yaml;
+use crate::types::{Config, StoredResponse};
+use crate::utils::{get_config_path, load_config_from_yaml};
 use std::sync::Arc;
 use std::sync::OnceLock;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
@@ -19,6 +19,18 @@ pub fn get_sled_db() -> &'static sled::Db {
     })
 }
 
+/// Global persistent Sled DB, stored under CONFIG_DIR
+pub static PERSISTENT_DB: OnceLock<sled::Db> = OnceLock::new();
+
+/// Get on-disk sled::Db for data that must survive restarts, only initialized once
+pub fn get_persistent_db() -> &'static sled::Db {
+    PERSISTENT_DB.get_or_init(|| {
+        let path = get_config_path("poe2openai.db");
+        info!("💾 Opening persistent database: {}", path.display());
+        sled::open(&path).expect("Unable to open persistent sled database")
+    })
+}
+
 /// Store config in sled
 pub fn save_config_sled(key: &str, config: &Config) -> Result<(), String> {
     let db = get_sled_db();
@@ -86,8 +98,7 @@ pub async fn get_cached_config() -> Arc<Config> {
                         enable: Some(false),
                         models: std::collections::HashMap::new(),
                         custom_models: None,
-                        api_token: None,
-                        use_v1_api: None,
+                        ..Default::default()
                     })
                 }
             }
@@ -340,6 +351,48 @@ pub fn estimate_base64_size(data_url: &str) -> usize {
     0
 }
 
+/// Entries and total size of the URL and base64 upload caches
+pub fn url_cache_stats() -> (usize, usize) {
+    let db = get_sled_db();
+    let mut entries = 0;
+    let mut size_bytes = 0;
+    for tree_name in ["urls", "base64"] {
+        let Ok(tree) = db.open_tree(tree_name) else {
+            continue;
+        };
+        for (_, value) in tree.iter().flatten() {
+            entries += 1;
+            // Values are "expires_secs:poe_url:size_bytes"
+            size_bytes += String::from_utf8_lossy(&value)
+                .rsplit(':')
+                .next()
+                .and_then(|size| size.parse::<usize>().ok())
+                .unwrap_or(0);
+        }
+    }
+    (entries, size_bytes)
+}
+
+/// Drop every cached upload, returns the number of removed entries
+pub fn clear_url_cache() -> usize {
+    let db = get_sled_db();
+    let mut removed = 0;
+    for tree_name in ["urls", "base64"] {
+        match db.open_tree(tree_name) {
+            Ok(tree) => {
+                removed += tree.len();
+                if let Err(e) = tree.clear() {
+                    error!("❌ Failed to clear {} cache: {}", tree_name, e);
+                }
+            }
+            Err(e) => error!("❌ Unable to open {} cache: {}", tree_name, e),
+        }
+    }
+    db.flush().ok();
+    info!("🗑️ Upload cache cleared | Entries: {}", removed);
+    removed
+}
+
 // Check and control cache size
 fn check_and_control_cache_size() {
     let db = get_sled_db();
@@ -417,3 +470,72 @@ fn check_and_control_cache_size() {
         }
     }
 }
+
+// Save a Responses API result so previous_response_id can rebuild history
+pub fn save_stored_response(id: &str, stored: &StoredResponse) {
+    let db = get_persistent_db();
+    let bytes = match serde_json::to_vec(stored) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("❌ Failed to serialize stored response {}: {}", id, e);
+            return;
+        }
+    };
+    match db.open_tree("responses") {
+        Ok(tree) => match tree.insert(id.as_bytes(), bytes) {
+            Ok(_) => {
+                db.flush().ok();
+                debug!("✅ Stored response saved: {}", id);
+            }
+            Err(e) => error!("❌ Failed to save stored response {}: {}", id, e),
+        },
+        Err(e) => error!("❌ Unable to open responses tree: {}", e),
+    }
+}
+
+// Load a stored Responses API result
+pub fn load_stored_response(id: &str) -> Option<StoredResponse> {
+    let db = get_persistent_db();
+    let tree = match db.open_tree("responses") {
+        Ok(tree) => tree,
+        Err(e) => {
+            error!("❌ Unable to open responses tree: {}", e);
+            return None;
+        }
+    };
+    match tree.get(id.as_bytes()) {
+        Ok(Some(bytes)) => match serde_json::from_slice::<StoredResponse>(&bytes) {
+            Ok(stored) => Some(stored),
+            Err(e) => {
+                error!("❌ Stored response parsing failed {}: {}", id, e);
+                None
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            error!("❌ Failed to read stored response {}: {}", id, e);
+            None
+        }
+    }
+}
+
+// Delete a stored Responses API result, returns whether it existed
+pub fn delete_stored_response(id: &str) -> bool {
+    let db = get_persistent_db();
+    match db.open_tree("responses") {
+        Ok(tree) => match tree.remove(id.as_bytes()) {
+            Ok(existing) => {
+                db.flush().ok();
+                existing.is_some()
+            }
+            Err(e) => {
+                error!("❌ Failed to delete stored response {}: {}", id, e);
+                false
+            }
+        },
+        Err(e) => {
+            error!("❌ Unable to open responses tree: {}", e);
+            false
+        }
+    }
+}
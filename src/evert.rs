@@ -1,9 +1,17 @@
 use crate::types::*;
-use crate::utils::{convert_poe_error_to_openai, format_bytes_length};
-use poe_api_process::{ChatEventType, ChatResponse, ChatResponseData};
+use crate::utils::{
+    convert_poe_error_to_openai, count_completion_tokens, format_bytes_length, truncate_to_tokens,
+};
+use futures_util::future;
+use futures_util::stream::{self, Stream, StreamExt};
+use poe_api_process::{ChatEventType, ChatResponse, ChatResponseData, PoeError};
 use salvo::prelude::*;
 use std::collections::HashMap;
-use tracing::{debug, error};
+use std::pin::Pin;
+use tracing::{debug, error, warn};
+
+// Boxed Poe event stream as returned by PoeClientWrapper::stream_request
+pub type EventStream = Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>;
 
 // Event accumulation context, used to collect state during event processing
 #[derive(Debug, Clone, Default)]
@@ -28,6 +36,8 @@ pub struct EventContext {
     pub current_reasoning_line: String,
     pub pending_text: String,
     pub metadata: HashMap<String, usize>, // Used to track length of sent content
+    // Holds back streamed text that may be the start of a stop sequence
+    pub stop_matcher: StopMatcher,
 }
 
 impl EventContext {
@@ -545,4 +555,423 @@ impl EventHandlerManager {
             ChatEventType::Done => self.done_handler.handle(event, ctx),
         }
     }
+
+    // Handle an event and translate the result into protocol-neutral deltas
+    pub fn handle_deltas(&self, event: &ChatResponse, ctx: &mut EventContext) -> Vec<EventDelta> {
+        let chunk_content_opt = self.handle(event, ctx);
+
+        if let Some((status, error_response)) = &ctx.error {
+            return vec![EventDelta::Error(*status, error_response.clone())];
+        }
+
+        let mut deltas = Vec::new();
+        match event.event {
+            ChatEventType::Text => {
+                if let Some(chunk_content) = chunk_content_opt {
+                    if chunk_content == "__REASONING_DETECTED__" {
+                        // Only emit reasoning added since the last delta
+                        let current_reasoning_len = ctx.reasoning_content.len();
+                        let last_sent_reasoning_len =
+                            ctx.get("last_sent_reasoning_len").unwrap_or(0);
+                        if current_reasoning_len > last_sent_reasoning_len {
+                            let new_reasoning =
+                                ctx.reasoning_content[last_sent_reasoning_len..].to_string();
+                            if !new_reasoning.trim().is_empty() {
+                                ctx.insert("last_sent_reasoning_len", current_reasoning_len);
+                                deltas.push(EventDelta::Reasoning(new_reasoning));
+                            }
+                        }
+                    } else {
+                        deltas.push(EventDelta::Content(replace_file_references(
+                            &chunk_content,
+                            &ctx.file_refs,
+                        )));
+                    }
+                }
+            }
+            ChatEventType::Json => {
+                if !ctx.pending_tool_calls.is_empty() {
+                    deltas.push(EventDelta::ToolCalls(std::mem::take(
+                        &mut ctx.pending_tool_calls,
+                    )));
+                }
+            }
+            ChatEventType::Done => deltas.push(EventDelta::Done),
+            _ => {
+                if let Some(chunk_content) = chunk_content_opt {
+                    deltas.push(EventDelta::Content(chunk_content));
+                }
+            }
+        }
+        deltas
+    }
+}
+
+// Protocol-neutral output of a single Poe event, rendered by each API adapter
+#[derive(Debug, Clone)]
+pub enum EventDelta {
+    Reasoning(String),
+    Content(String),
+    ToolCalls(Vec<ChunkToolCall>),
+    Error(StatusCode, OpenAIErrorResponse),
+    Done,
+}
+
+// Keep only the first tool call of a reply (parallel_tool_calls = false).
+// Later calls are dropped so the returned index 0 call is the only ID clients can answer.
+pub fn first_tool_call_only(event_stream: EventStream) -> EventStream {
+    let mut emitted = false;
+    Box::pin(event_stream.filter_map(move |result| {
+        let result = match result {
+            Ok(ChatResponse {
+                event: ChatEventType::Json,
+                data: Some(ChatResponseData::ToolCalls(tool_calls)),
+            }) => {
+                let mut calls = tool_calls.into_iter();
+                let kept = if emitted { None } else { calls.next() };
+                for dropped in calls {
+                    debug!(
+                        "🔧 parallel_tool_calls=false, dropping tool call | ID: {} | Function: {}",
+                        dropped.id, dropped.function.name
+                    );
+                }
+                kept.map(|tool_call| {
+                    emitted = true;
+                    Ok(ChatResponse {
+                        event: ChatEventType::Json,
+                        data: Some(ChatResponseData::ToolCalls(vec![tool_call])),
+                    })
+                })
+            }
+            other => Some(other),
+        };
+        future::ready(result)
+    }))
+}
+
+// Convert a Poe event stream into deltas, ending after Done or the first error
+pub fn delta_stream(event_stream: EventStream) -> Pin<Box<dyn Stream<Item = EventDelta> + Send>> {
+    let deltas = stream::unfold(
+        (
+            event_stream,
+            EventContext::default(),
+            EventHandlerManager::new(),
+            false,
+        ),
+        |(mut event_stream, mut ctx, handler_manager, finished)| async move {
+            if finished {
+                return None;
+            }
+            let deltas = match event_stream.next().await {
+                Some(Ok(event)) => handler_manager.handle_deltas(&event, &mut ctx),
+                Some(Err(e)) => {
+                    error!("❌ Streaming processing error: {}", e);
+                    let (status, error_response) =
+                        convert_poe_error_to_openai(&e.to_string(), false);
+                    vec![EventDelta::Error(status, error_response)]
+                }
+                None => {
+                    debug!("⏹️ Event stream ended without Done event");
+                    vec![EventDelta::Done]
+                }
+            };
+            let finished = deltas
+                .iter()
+                .any(|delta| matches!(delta, EventDelta::Done | EventDelta::Error(..)));
+            Some((
+                stream::iter(deltas),
+                (event_stream, ctx, handler_manager, finished),
+            ))
+        },
+    );
+    Box::pin(deltas.flatten())
+}
+
+// Drain all events into a context (non-streaming mode)
+pub async fn collect_events(
+    mut event_stream: EventStream,
+) -> Result<EventContext, (StatusCode, OpenAIErrorResponse)> {
+    let handler_manager = EventHandlerManager::new();
+    let mut ctx = EventContext::default();
+
+    while let Some(result) = event_stream.next().await {
+        match result {
+            Ok(event) => {
+                handler_manager.handle(&event, &mut ctx);
+                // Check for errors
+                if let Some((status, error_response)) = ctx.error.take() {
+                    error!("❌ Processing error: {:?}", error_response);
+                    return Err((status, error_response));
+                }
+                // Check if completed
+                if ctx.done {
+                    debug!("✅ Received completion event");
+                    break;
+                }
+            }
+            Err(e) => {
+                error!("❌ Processing error: {}", e);
+                return Err(convert_poe_error_to_openai(&e.to_string(), false));
+            }
+        }
+    }
+
+    Ok(ctx)
+}
+
+// Flush remaining pending text and resolve file references into the final content
+pub fn finalize_content(ctx: &mut EventContext) -> String {
+    if !ctx.pending_text.trim().is_empty() {
+        let (reasoning_output, content_output) = ThinkingProcessor::process_text_chunk(ctx, "");
+        if let Some(final_reasoning) = reasoning_output {
+            ctx.reasoning_content.push_str(&final_reasoning);
+        }
+        if let Some(final_content) = content_output {
+            ctx.content.push_str(&final_content);
+        }
+    }
+
+    match &ctx.replace_buffer {
+        Some(replace_content) => replace_file_references(replace_content, &ctx.file_refs),
+        None => replace_file_references(&ctx.content, &ctx.file_refs),
+    }
+}
+
+// Process file references, replacing [ref_id] with (url)
+pub fn replace_file_references(
+    content: &str,
+    file_refs: &HashMap<String, poe_api_process::types::FileData>,
+) -> String {
+    if file_refs.is_empty() {
+        return content.to_string();
+    }
+    let mut processed = content.to_string();
+    let mut has_replaced = false;
+
+    for (ref_id, file_data) in file_refs {
+        let img_marker = format!("[{}]", ref_id);
+        if processed.contains(&img_marker) {
+            let replacement = format!("({})", file_data.url);
+            processed = processed.replace(&img_marker, &replacement);
+            debug!(
+                "🖼️ Replaced image reference | ID: {} | URL: {}",
+                ref_id, file_data.url
+            );
+            has_replaced = true;
+        }
+    }
+
+    if has_replaced {
+        debug!("✅ Successfully replaced image references");
+    } else if processed.contains('[') && processed.contains(']') {
+        warn!(
+            "⚠️ Text contains potential image reference format, but no corresponding reference found: {}",
+            processed
+        );
+    }
+
+    processed
+}
+
+// Stop sequence matcher that works across chunk boundaries.
+// Text that could be the start of a stop sequence is held back until it can be decided.
+#[derive(Debug, Clone, Default)]
+pub struct StopMatcher {
+    stops: Vec<String>,
+    held: String,
+    // Stop sequence that ended the text
+    matched: Option<String>,
+}
+
+impl StopMatcher {
+    pub fn new(stops: &[String]) -> Self {
+        Self {
+            stops: stops.iter().filter(|s| !s.is_empty()).cloned().collect(),
+            held: String::new(),
+            matched: None,
+        }
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.matched.is_some()
+    }
+
+    pub fn matched(&self) -> Option<&str> {
+        self.matched.as_deref()
+    }
+
+    // Feed a chunk, returns the text that is safe to emit
+    pub fn push(&mut self, chunk: &str) -> String {
+        if self.stopped() {
+            return String::new();
+        }
+        if self.stops.is_empty() {
+            return chunk.to_string();
+        }
+        self.held.push_str(chunk);
+
+        if let Some((pos, stop)) = self
+            .stops
+            .iter()
+            .filter_map(|stop| self.held.find(stop.as_str()).map(|pos| (pos, stop)))
+            .min_by_key(|(pos, _)| *pos)
+        {
+            debug!("🛑 Stop sequence matched at position {}", pos);
+            self.matched = Some(stop.clone());
+            let output = self.held[..pos].to_string();
+            self.held.clear();
+            return output;
+        }
+
+        // Keep the longest suffix that is a prefix of some stop sequence
+        let keep = self
+            .stops
+            .iter()
+            .flat_map(|stop| {
+                (1..stop.len())
+                    .filter(|&k| stop.is_char_boundary(k) && self.held.ends_with(&stop[..k]))
+                    .max()
+            })
+            .max()
+            .unwrap_or(0);
+        let split = self.held.len() - keep;
+        let output = self.held[..split].to_string();
+        self.held.drain(..split);
+        output
+    }
+
+    // Release held-back text once the stream has ended
+    pub fn finish(&mut self) -> String {
+        if self.stopped() {
+            return String::new();
+        }
+        std::mem::take(&mut self.held)
+    }
+
+    // Apply stop sequences to a complete text, returns the truncated text and whether a stop matched
+    pub fn truncate(text: &str, stops: &[String]) -> (String, bool) {
+        let mut matcher = Self::new(stops);
+        let mut output = matcher.push(text);
+        output.push_str(&matcher.finish());
+        (output, matcher.stopped())
+    }
+}
+
+// Why the proxy ended a reply before the bot did
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputCut {
+    StopSequence(String),
+    MaxTokens,
+}
+
+// Stop sequences and the output token limit for the adapters that render deltas themselves,
+// with the same rules as chat completions: a reply of exactly max_tokens tokens is complete.
+#[derive(Debug, Clone, Default)]
+pub struct OutputLimit {
+    matcher: StopMatcher,
+    max_tokens: Option<u32>,
+    sent_tokens: u32,
+    cut: Option<OutputCut>,
+}
+
+impl OutputLimit {
+    pub fn new(stops: &[String], max_tokens: Option<u32>) -> Self {
+        Self {
+            matcher: StopMatcher::new(stops),
+            max_tokens,
+            ..Default::default()
+        }
+    }
+
+    // Set once the reply was cut, nothing more may be sent after that
+    pub fn cut(&self) -> Option<&OutputCut> {
+        self.cut.as_ref()
+    }
+
+    // Feed streamed content, returns the text that may be sent
+    pub fn push(&mut self, text: &str) -> String {
+        if self.cut.is_some() {
+            return String::new();
+        }
+        let text = self.matcher.push(text);
+        let text = self.limit_tokens(&text);
+        if let Some(stop) = self.matcher.matched().filter(|_| self.cut.is_none()) {
+            self.cut = Some(OutputCut::StopSequence(stop.to_string()));
+        }
+        text
+    }
+
+    // Release text held back by the stop matcher once the bot is done
+    pub fn finish(&mut self) -> String {
+        if self.cut.is_some() {
+            return String::new();
+        }
+        let text = self.matcher.finish();
+        self.limit_tokens(&text)
+    }
+
+    // Limit a complete (non-streamed) reply
+    pub fn apply(mut self, text: &str) -> (String, Option<OutputCut>) {
+        let mut output = self.push(text);
+        output.push_str(&self.finish());
+        (output, self.cut)
+    }
+
+    fn limit_tokens(&mut self, text: &str) -> String {
+        let Some(limit) = self.max_tokens else {
+            return text.to_string();
+        };
+        let tokens = count_completion_tokens(text);
+        if self.sent_tokens + tokens <= limit {
+            self.sent_tokens += tokens;
+            return text.to_string();
+        }
+        debug!(
+            "✂️ max_tokens reached, ending reply | Limit: {} | Sent: {}",
+            limit, self.sent_tokens
+        );
+        let text = truncate_to_tokens(text, limit.saturating_sub(self.sent_tokens));
+        self.sent_tokens = limit;
+        self.cut = Some(OutputCut::MaxTokens);
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_matcher_holds_text_across_chunks() {
+        let mut matcher = StopMatcher::new(&["END".to_string()]);
+        assert_eq!(matcher.push("Hello E"), "Hello ");
+        assert_eq!(matcher.push("N"), "");
+        assert_eq!(matcher.push("Dless"), "");
+        assert!(matcher.stopped());
+
+        let mut matcher = StopMatcher::new(&["END".to_string()]);
+        assert_eq!(matcher.push("Hi E"), "Hi ");
+        assert_eq!(matcher.push("dge"), "Edge");
+        assert_eq!(matcher.finish(), "");
+    }
+
+    #[test]
+    fn output_limit_reports_the_cut() {
+        let mut limit = OutputLimit::new(&["END".to_string()], None);
+        assert_eq!(limit.push("Hello E"), "Hello ");
+        assert_eq!(limit.push("NDless"), "");
+        assert_eq!(
+            limit.cut(),
+            Some(&OutputCut::StopSequence("END".to_string()))
+        );
+        assert_eq!(limit.push("more"), "");
+
+        let (text, cut) = OutputLimit::new(&[], Some(2)).apply("one two three four");
+        assert_eq!(count_completion_tokens(&text), 2);
+        assert_eq!(cut, Some(OutputCut::MaxTokens));
+
+        let (text, cut) = OutputLimit::new(&[], Some(100)).apply("short");
+        assert_eq!(text, "short");
+        assert_eq!(cut, None);
+    }
 }
@@ -0,0 +1,246 @@
+use crate::types::{OpenAIError, OpenAIErrorResponse};
+use ipnet::IpNet;
+use salvo::http::StatusCode;
+use salvo::prelude::*;
+use serde_json::json;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+use tracing::{Instrument, debug, info_span, warn};
+
+/// CIDR lists read once from the environment
+struct AccessLists {
+    // Peers whose X-Forwarded-For / Forwarded headers are believed
+    trusted_proxies: Vec<IpNet>,
+    api_allow: Vec<IpNet>,
+    api_deny: Vec<IpNet>,
+    admin_allow: Vec<IpNet>,
+    admin_deny: Vec<IpNet>,
+}
+
+static ACCESS_LISTS: OnceLock<AccessLists> = OnceLock::new();
+
+// Comma-separated CIDRs, a bare address counts as a single host
+fn parse_cidrs(value: &str) -> Vec<IpNet> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let net = entry
+                .parse::<IpNet>()
+                .ok()
+                .or_else(|| entry.parse::<IpAddr>().ok().map(IpNet::from));
+            if net.is_none() {
+                warn!("⚠️ Ignoring invalid CIDR: {}", entry);
+            }
+            net
+        })
+        .collect()
+}
+
+fn access_lists() -> &'static AccessLists {
+    ACCESS_LISTS.get_or_init(|| {
+        let env_cidrs = |key: &str| parse_cidrs(&std::env::var(key).unwrap_or_default());
+        AccessLists {
+            trusted_proxies: env_cidrs("TRUSTED_PROXIES"),
+            api_allow: env_cidrs("API_ALLOW_CIDRS"),
+            api_deny: env_cidrs("API_DENY_CIDRS"),
+            admin_allow: env_cidrs("ADMIN_ALLOW_CIDRS"),
+            admin_deny: env_cidrs("ADMIN_DENY_CIDRS"),
+        }
+    })
+}
+
+fn in_any(nets: &[IpNet], ip: IpAddr) -> bool {
+    nets.iter().any(|net| net.contains(&ip))
+}
+
+// Accepts `1.2.3.4`, `1.2.3.4:80`, `[::1]:80`, `[::1]` and quoted forms
+fn parse_forwarded_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            value
+                .strip_prefix('[')
+                .and_then(|v| v.strip_suffix(']'))
+                .and_then(|v| v.parse().ok())
+        })
+        .map(|ip| ip.to_canonical())
+}
+
+// Addresses added by the proxies, nearest hop last. X-Forwarded-For wins over Forwarded
+fn forwarded_chain(req: &Request) -> Vec<IpAddr> {
+    let elements = |name: &str| -> Vec<String> {
+        req.headers()
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|v| v.to_string())
+            .collect()
+    };
+    let x_forwarded_for: Vec<IpAddr> = elements("x-forwarded-for")
+        .iter()
+        .filter_map(|hop| parse_forwarded_ip(hop))
+        .collect();
+    if !x_forwarded_for.is_empty() {
+        return x_forwarded_for;
+    }
+    elements("forwarded")
+        .iter()
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                if key.trim().eq_ignore_ascii_case("for") {
+                    parse_forwarded_ip(value)
+                } else {
+                    None
+                }
+            })
+        })
+        .collect()
+}
+
+fn resolve_from_chain(peer: IpAddr, chain: &[IpAddr], trusted_proxies: &[IpNet]) -> IpAddr {
+    if !in_any(trusted_proxies, peer) {
+        return peer;
+    }
+    // Walk back from the nearest hop, the first address that is not a trusted proxy is the client
+    chain
+        .iter()
+        .rev()
+        .find(|ip| !in_any(trusted_proxies, **ip))
+        .or(chain.first())
+        .copied()
+        .unwrap_or(peer)
+}
+
+/// Real client IP: the peer address, or the forwarded address when the peer is a trusted proxy
+pub(crate) fn resolve_client_ip(req: &Request) -> Option<IpAddr> {
+    let peer = req.remote_addr().clone().into_std()?.ip().to_canonical();
+    let trusted_proxies = &access_lists().trusted_proxies;
+    Some(resolve_from_chain(
+        peer,
+        &forwarded_chain(req),
+        trusted_proxies,
+    ))
+}
+
+pub(crate) fn client_ip(req: &Request) -> String {
+    resolve_client_ip(req)
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn is_allowed(ip: Option<IpAddr>, allow: &[IpNet], deny: &[IpNet]) -> bool {
+    match ip {
+        Some(ip) => !in_any(deny, ip) && (allow.is_empty() || in_any(allow, ip)),
+        // Peers without an IP (e.g. Unix sockets) only pass when there is no allowlist
+        None => allow.is_empty(),
+    }
+}
+
+// Run the rest of the chain with the client IP attached to every log line
+async fn call_next_with_ip(
+    ip: Option<IpAddr>,
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+    ctrl: &mut FlowCtrl,
+) {
+    let client_ip = ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+    debug!("🌐 Client IP resolved: {}", client_ip);
+    ctrl.call_next(req, depot, res)
+        .instrument(info_span!("client", ip = %client_ip))
+        .await;
+}
+
+/// Enforce API_ALLOW_CIDRS / API_DENY_CIDRS on the API routes
+#[handler]
+pub async fn api_access_middleware(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+    ctrl: &mut FlowCtrl,
+) {
+    let lists = access_lists();
+    let ip = resolve_client_ip(req);
+    if !is_allowed(ip, &lists.api_allow, &lists.api_deny) {
+        warn!(
+            "🚫 API request from blocked IP | IP: {:?} | Path: {}",
+            ip,
+            req.uri().path()
+        );
+        res.status_code(StatusCode::FORBIDDEN);
+        res.render(Json(OpenAIErrorResponse {
+            error: OpenAIError {
+                message: "Your IP address is not allowed to access this API".to_string(),
+                r#type: "permission_error".to_string(),
+                code: "ip_not_allowed".to_string(),
+                param: None,
+            },
+        }));
+        ctrl.skip_rest();
+        return;
+    }
+    call_next_with_ip(ip, req, depot, res, ctrl).await;
+}
+
+/// Enforce ADMIN_ALLOW_CIDRS / ADMIN_DENY_CIDRS on the admin page and admin API
+#[handler]
+pub async fn admin_access_middleware(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+    ctrl: &mut FlowCtrl,
+) {
+    let lists = access_lists();
+    let ip = resolve_client_ip(req);
+    if !is_allowed(ip, &lists.admin_allow, &lists.admin_deny) {
+        warn!(
+            "🚫 Admin request from blocked IP | IP: {:?} | Path: {}",
+            ip,
+            req.uri().path()
+        );
+        res.status_code(StatusCode::FORBIDDEN);
+        res.render(Json(json!({ "error": "Your IP address is not allowed" })));
+        ctrl.skip_rest();
+        return;
+    }
+    call_next_with_ip(ip, req, depot, res, ctrl).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_address_is_only_trusted_behind_known_proxies() {
+        let proxies = parse_cidrs("10.0.0.0/8, 192.168.1.1, not-a-cidr");
+        assert_eq!(proxies.len(), 2);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        // Client spoofs 1.1.1.1, nginx appends the real address 203.0.113.7
+        let chain = [ip("1.1.1.1"), ip("203.0.113.7"), ip("10.0.0.2")];
+
+        assert_eq!(
+            resolve_from_chain(ip("10.0.0.1"), &chain, &proxies),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            resolve_from_chain(ip("198.51.100.9"), &chain, &proxies),
+            ip("198.51.100.9")
+        );
+        assert_eq!(
+            parse_forwarded_ip("\"[2001:db8::1]:4711\""),
+            Some(ip("2001:db8::1"))
+        );
+
+        let deny = parse_cidrs("203.0.113.0/24");
+        assert!(!is_allowed(Some(ip("203.0.113.7")), &[], &deny));
+        assert!(is_allowed(Some(ip("198.51.100.9")), &[], &deny));
+        assert!(!is_allowed(Some(ip("198.51.100.9")), &proxies, &[]));
+    }
+}
@@ -1,25 +1,205 @@
-use crate::cache::{remove_config_sled, save_config_sled};
-use crate::types::Config;
-use crate::utils::{get_config_path, pretty_json_truncated, redact_headers, redact_json_fields};
+use super::access::{admin_access_middleware, client_ip};
+use crate::admin_tokens::{
+    ADMIN_TOKEN_PREFIX, AdminScope, AdminTokenRecord, AuditEntry, create_admin_token,
+    find_admin_token, list_admin_tokens, recent_audit, record_audit, revoke_admin_token,
+};
+use crate::auth::{
+    AdminSession, CSRF_HEADER, SESSION_COOKIE, create_session, end_session, find_session,
+    is_same_origin, login_locked_for, record_login_failure, record_login_success, session_ttl,
+    verify_admin_credentials,
+};
+use crate::cache::{
+    clear_url_cache, get_cached_config, remove_config_sled, save_config_sled, url_cache_stats,
+};
+use crate::keys::{ApiKeyRecord, create_api_key, list_api_keys, revoke_api_key, update_api_key};
+use crate::policy::{
+    CredentialPolicy, delete_policy, is_credential_hash, list_policies, save_policy,
+};
+use crate::types::{Config, CreateAdminTokenRequest, CreateApiKeyRequest, UpdateApiKeyRequest};
+use crate::utils::{
+    get_config_path, hash_token, pretty_json_truncated, redact_headers, redact_json_fields,
+};
+use crate::{pool, usage};
 use askama::Template;
-use salvo::basic_auth::{BasicAuth, BasicAuthValidator};
+use base64::prelude::*;
+use chrono::Utc;
+use salvo::http::cookie::{Cookie, SameSite};
+use salvo::http::{Method, header};
 use salvo::prelude::*;
 use serde_json::json;
 use std::fs;
-use tracing::{debug, error, info};
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
 
 #[derive(Template)]
 #[template(path = "admin.html")]
-struct AdminTemplate;
+struct AdminTemplate {
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    error: Option<String>,
+}
 
 #[handler]
-async fn admin_page(res: &mut Response) {
-    let template = AdminTemplate;
+async fn admin_page(depot: &mut Depot, res: &mut Response) {
+    // The page calls the admin API with its session, Basic auth alone gets the login form
+    let Ok(session) = depot.obtain::<AdminSession>() else {
+        res.render(Redirect::other("/admin/login"));
+        return;
+    };
+    let template = AdminTemplate {
+        csrf_token: session.csrf_token.clone(),
+    };
     res.render(Text::Html(template.render().unwrap()));
 }
 
+fn render_login(res: &mut Response, status: StatusCode, error: Option<String>) {
+    res.status_code(status);
+    res.render(Text::Html(LoginTemplate { error }.render().unwrap()));
+}
+
+fn session_cookie(value: String, max_age: Duration) -> Cookie<'static> {
+    let secure = std::env::var("ADMIN_COOKIE_SECURE").is_ok_and(|v| v == "true");
+    Cookie::build((SESSION_COOKIE, value))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .secure(secure)
+        .max_age(salvo::http::cookie::time::Duration::seconds(
+            max_age.as_secs() as i64,
+        ))
+        .build()
+}
+
+#[handler]
+async fn login_page(req: &mut Request, res: &mut Response) {
+    if req
+        .cookie(SESSION_COOKIE)
+        .and_then(|cookie| find_session(cookie.value()))
+        .is_some()
+    {
+        res.render(Redirect::other("/admin"));
+        return;
+    }
+    render_login(res, StatusCode::OK, None);
+}
+
+#[handler]
+async fn login(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [POST] {} ------", req.uri());
+    let client = client_ip(req);
+    if let Some(remaining) = login_locked_for(&client) {
+        warn!(
+            "🚫 Admin login rejected, client locked out | Client: {}",
+            client
+        );
+        res.headers_mut()
+            .insert(header::RETRY_AFTER, remaining.as_secs().max(1).into());
+        render_login(
+            res,
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(format!(
+                "Too many failed attempts, try again in {} minutes",
+                remaining.as_secs().div_ceil(60)
+            )),
+        );
+        return;
+    }
+
+    // A cross-site form could otherwise log the browser into the attacker's session
+    let header_value =
+        |name: header::HeaderName| req.headers().get(name).and_then(|v| v.to_str().ok());
+    let source = header_value(header::ORIGIN).or_else(|| header_value(header::REFERER));
+    let host = req
+        .headers()
+        .get("x-forwarded-host")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| header_value(header::HOST));
+    if !is_same_origin(source, host) {
+        warn!(
+            "🛡️ Admin login rejected, not posted from this site | Origin: {:?} | Client: {}",
+            source, client
+        );
+        render_login(
+            res,
+            StatusCode::FORBIDDEN,
+            Some("Login must be submitted from the login page".to_string()),
+        );
+        return;
+    }
+
+    let username = req.form::<String>("username").await.unwrap_or_default();
+    let password = req.form::<String>("password").await.unwrap_or_default();
+    if !verify_admin_credentials(&username, &password).await {
+        record_login_failure(&client);
+        render_login(
+            res,
+            StatusCode::UNAUTHORIZED,
+            Some("Invalid username or password".to_string()),
+        );
+        return;
+    }
+
+    record_login_success(&client);
+    let session_id = create_session(&username);
+    res.add_cookie(session_cookie(session_id, session_ttl()));
+    info!(
+        "🔐 Admin logged in | User: {} | Client: {}",
+        username, client
+    );
+    res.render(Redirect::other("/admin"));
+}
+
+#[handler]
+async fn logout(req: &mut Request, res: &mut Response) {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        end_session(cookie.value());
+    }
+    res.add_cookie(session_cookie(String::new(), Duration::ZERO));
+    info!("🔓 Admin logged out");
+    res.render(Redirect::other("/admin/login"));
+}
+
+// Poe tokens are shown as their first and last four characters
+fn mask_token(token: &str) -> String {
+    if token.len() > 8 && token.is_ascii() {
+        format!("{}…{}", &token[..4], &token[token.len() - 4..])
+    } else {
+        "****".to_string()
+    }
+}
+
+// Settings only the admin login may change: upstream Poe tokens and CORS
+fn protected_settings(config: &Config) -> serde_json::Value {
+    json!({
+        "api_token": config.api_token,
+        "token_pool": config.token_pool,
+        "cors": config.cors,
+    })
+}
+
+// Mask the Poe tokens of a serialized config, admin tokens never see them
+fn mask_config_tokens(value: &mut serde_json::Value) {
+    if let Some(token) = value.get_mut("api_token").filter(|token| token.is_string()) {
+        *token = json!(mask_token(token.as_str().unwrap_or_default()));
+    }
+    if let Some(tokens) = value
+        .pointer_mut("/token_pool/tokens")
+        .and_then(|tokens| tokens.as_array_mut())
+    {
+        for entry in tokens {
+            if let Some(token) = entry.get_mut("token") {
+                *token = json!(mask_token(token.as_str().unwrap_or_default()));
+            }
+        }
+    }
+}
+
 #[handler]
-async fn get_config(req: &mut Request, res: &mut Response) {
+async fn get_config(req: &mut Request, depot: &mut Depot, res: &mut Response) {
     // Structure request/response logging with separator
     debug!("------ Incoming Request [GET] {} ------", req.uri());
 
@@ -47,11 +227,17 @@ async fn get_config(req: &mut Request, res: &mut Response) {
 
     debug!("------ Outgoing Response [200] /api/admin/config ------");
 
+    if depot.obtain::<AdminTokenRecord>().is_ok() {
+        let mut value = response_value;
+        mask_config_tokens(&mut value);
+        res.render(Json(value));
+        return;
+    }
     res.render(Json(config));
 }
 
 #[handler]
-async fn save_config(req: &mut Request, res: &mut Response) {
+async fn save_config(req: &mut Request, depot: &mut Depot, res: &mut Response) {
     // Structure request/response logging with separator
     debug!("------ Incoming Request [POST] {} ------", req.uri());
 
@@ -66,7 +252,28 @@ async fn save_config(req: &mut Request, res: &mut Response) {
     );
 
     match req.parse_json::<Config>().await {
-        Ok(config) => {
+        Ok(mut config) => {
+            // Admin tokens automate the model rules, they may send back the settings they read but not change them
+            if depot.obtain::<AdminTokenRecord>().is_ok() {
+                let current = load_config().unwrap_or_default();
+                let stored = protected_settings(&current);
+                let mut masked = stored.clone();
+                mask_config_tokens(&mut masked);
+                let incoming = protected_settings(&config);
+                if incoming != stored && incoming != masked {
+                    warn!("🚫 Admin token tried to change api_token, token_pool or cors");
+                    debug!("------ Outgoing Response [403] /api/admin/config ------");
+                    res.status_code(StatusCode::FORBIDDEN);
+                    res.render(Json(json!({
+                        "error": "Admin tokens cannot change api_token, token_pool or cors"
+                    })));
+                    return;
+                }
+                config.api_token = current.api_token;
+                config.token_pool = current.token_pool;
+                config.cors = current.cors;
+            }
+
             // Log the incoming config (sanitized)
             let config_value = serde_json::to_value(&config).unwrap_or_else(|_| json!(null));
             let redacted_config = redact_json_fields(&config_value);
@@ -108,6 +315,354 @@ async fn save_config(req: &mut Request, res: &mut Response) {
     }
 }
 
+// Admin view of an API key, the Poe token is masked
+fn api_key_json(record: &ApiKeyRecord) -> serde_json::Value {
+    let masked_token = if record.poe_token.is_empty() {
+        "pool".to_string()
+    } else {
+        mask_token(&record.poe_token)
+    };
+    json!({
+        "id": record.id,
+        "name": record.name,
+        "key_prefix": record.key_prefix,
+        // Policies are attached to this hash
+        "credential": record.key_hash,
+        "poe_token": masked_token,
+        "created_at": record.created_at,
+        "expires_at": record.expires_at,
+        "revoked": record.revoked,
+        "limits": record.limits,
+        "budget": record.budget,
+    })
+}
+
+#[handler]
+async fn list_keys(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [GET] {} ------", req.uri());
+    let keys: Vec<serde_json::Value> = list_api_keys().iter().map(api_key_json).collect();
+    debug!("------ Outgoing Response [200] /api/admin/keys ------");
+    res.render(Json(json!({ "object": "list", "data": keys })));
+}
+
+#[handler]
+async fn create_key(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [POST] {} ------", req.uri());
+    let request = match req.parse_json::<CreateApiKeyRequest>().await {
+        Ok(request) => request,
+        Err(e) => {
+            error!("❌ Failed to parse API key request: {}", e);
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(json!({ "error": e.to_string() })));
+            return;
+        }
+    };
+    if request.name.trim().is_empty() {
+        res.status_code(StatusCode::BAD_REQUEST);
+        res.render(Json(json!({ "error": "name is required" })));
+        return;
+    }
+    let poe_token = request.poe_token.as_deref().unwrap_or_default().trim();
+    let expires_at = request.expires_at.or_else(|| {
+        request
+            .expires_in_days
+            .map(|days| chrono::Utc::now().timestamp() + i64::from(days) * 86400)
+    });
+
+    match create_api_key(
+        request.name.trim(),
+        poe_token,
+        expires_at,
+        request.limits,
+        request.budget,
+    ) {
+        Ok((raw_key, record)) => {
+            let mut body = api_key_json(&record);
+            // The raw key is only returned once
+            body["key"] = json!(raw_key);
+            debug!("------ Outgoing Response [201] /api/admin/keys ------");
+            res.status_code(StatusCode::CREATED);
+            res.render(Json(body));
+        }
+        Err(e) => {
+            error!("❌ Failed to create API key: {}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(json!({ "error": e })));
+        }
+    }
+}
+
+#[handler]
+async fn revoke_key(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [DELETE] {} ------", req.uri());
+    let id = req.param::<String>("id").unwrap_or_default();
+    match revoke_api_key(&id) {
+        Ok(true) => res.render(Json(json!({ "id": id, "revoked": true }))),
+        Ok(false) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(
+                json!({ "error": format!("API key {} not found", id) }),
+            ));
+        }
+        Err(e) => {
+            error!("❌ Failed to revoke API key {}: {}", id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(json!({ "error": e })));
+        }
+    }
+}
+
+#[handler]
+async fn update_key(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [PATCH] {} ------", req.uri());
+    let id = req.param::<String>("id").unwrap_or_default();
+    let request = match req.parse_json::<UpdateApiKeyRequest>().await {
+        Ok(request) => request,
+        Err(e) => {
+            error!("❌ Failed to parse API key update: {}", e);
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(json!({ "error": e.to_string() })));
+            return;
+        }
+    };
+    match update_api_key(&id, request.limits, request.budget) {
+        Ok(Some(record)) => res.render(Json(api_key_json(&record))),
+        Ok(None) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(
+                json!({ "error": format!("API key {} not found", id) }),
+            ));
+        }
+        Err(e) => {
+            error!("❌ Failed to update API key {}: {}", id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(json!({ "error": e })));
+        }
+    }
+}
+
+#[handler]
+async fn usage_summary(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [GET] {} ------", req.uri());
+    let mut report = usage::usage_report();
+    // Every proxy key is listed with its budget, clients without a key only when they have usage
+    let mut data: Vec<serde_json::Value> = list_api_keys()
+        .iter()
+        .map(|record| {
+            let client = format!("key:{}", record.id);
+            let usage = report.remove(&client).unwrap_or_else(usage::empty_usage);
+            json!({
+                "client": client,
+                "key_id": record.id,
+                "name": record.name,
+                "revoked": record.revoked,
+                "budget": record.budget,
+                "daily": usage.daily,
+                "monthly": usage.monthly,
+            })
+        })
+        .collect();
+    data.extend(report.into_iter().map(|(client, usage)| {
+        json!({
+            "client": client,
+            "daily": usage.daily,
+            "monthly": usage.monthly,
+        })
+    }));
+    debug!("------ Outgoing Response [200] /api/admin/usage ------");
+    res.render(Json(json!({ "object": "list", "data": data })));
+}
+
+#[handler]
+async fn list_policy_entries(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [GET] {} ------", req.uri());
+    let data: Vec<serde_json::Value> = list_policies()
+        .into_iter()
+        .map(|(credential, policy)| json!({ "credential": credential, "policy": policy }))
+        .collect();
+    debug!("------ Outgoing Response [200] /api/admin/policies ------");
+    res.render(Json(json!({ "object": "list", "data": data })));
+}
+
+// Policies are addressed by credential hash: the SHA-256 hex digest of the bearer token
+fn policy_credential(req: &Request, res: &mut Response) -> Option<String> {
+    let credential = req
+        .param::<String>("credential")
+        .unwrap_or_default()
+        .to_lowercase();
+    if is_credential_hash(&credential) {
+        return Some(credential);
+    }
+    res.status_code(StatusCode::BAD_REQUEST);
+    res.render(Json(
+        json!({ "error": "credential must be the SHA-256 hex digest of the bearer token" }),
+    ));
+    None
+}
+
+#[handler]
+async fn put_policy(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [PUT] {} ------", req.uri());
+    let Some(credential) = policy_credential(req, res) else {
+        return;
+    };
+    let policy = match req.parse_json::<CredentialPolicy>().await {
+        Ok(policy) => policy,
+        Err(e) => {
+            error!("❌ Failed to parse policy: {}", e);
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(json!({ "error": e.to_string() })));
+            return;
+        }
+    };
+    match save_policy(&credential, &policy) {
+        Ok(()) => res.render(Json(json!({ "credential": credential, "policy": policy }))),
+        Err(e) => {
+            error!("❌ {}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(json!({ "error": e })));
+        }
+    }
+}
+
+#[handler]
+async fn remove_policy(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [DELETE] {} ------", req.uri());
+    let Some(credential) = policy_credential(req, res) else {
+        return;
+    };
+    match delete_policy(&credential) {
+        Ok(true) => res.render(Json(json!({ "credential": credential, "deleted": true }))),
+        Ok(false) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(json!({ "error": "No policy for this credential" })));
+        }
+        Err(e) => {
+            error!("❌ {}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(json!({ "error": e })));
+        }
+    }
+}
+
+#[handler]
+async fn pool_health(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [GET] {} ------", req.uri());
+    let config = get_cached_config().await;
+    debug!("------ Outgoing Response [200] /api/admin/pool ------");
+    res.render(Json(pool::health(&config)));
+}
+
+// Admin view of an admin token, without its hash
+fn admin_token_json(record: &AdminTokenRecord) -> serde_json::Value {
+    json!({
+        "id": record.id,
+        "name": record.name,
+        "token_prefix": record.token_prefix,
+        "scopes": record.scopes,
+        "created_at": record.created_at,
+        "expires_at": record.expires_at,
+        "revoked": record.revoked,
+        "last_used_at": record.last_used_at,
+    })
+}
+
+#[handler]
+async fn list_tokens(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [GET] {} ------", req.uri());
+    let tokens: Vec<serde_json::Value> = list_admin_tokens().iter().map(admin_token_json).collect();
+    debug!("------ Outgoing Response [200] /api/admin/tokens ------");
+    res.render(Json(json!({ "object": "list", "data": tokens })));
+}
+
+#[handler]
+async fn create_token(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [POST] {} ------", req.uri());
+    let request = match req.parse_json::<CreateAdminTokenRequest>().await {
+        Ok(request) => request,
+        Err(e) => {
+            error!("❌ Failed to parse admin token request: {}", e);
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(json!({ "error": e.to_string() })));
+            return;
+        }
+    };
+    if request.name.trim().is_empty() || request.scopes.is_empty() {
+        res.status_code(StatusCode::BAD_REQUEST);
+        res.render(Json(json!({ "error": "name and scopes are required" })));
+        return;
+    }
+    let expires_at = request.expires_at.or_else(|| {
+        request
+            .expires_in_days
+            .map(|days| Utc::now().timestamp() + i64::from(days) * 86400)
+    });
+
+    match create_admin_token(request.name.trim(), request.scopes, expires_at) {
+        Ok((raw_token, record)) => {
+            let mut body = admin_token_json(&record);
+            // The raw token is only returned once
+            body["token"] = json!(raw_token);
+            debug!("------ Outgoing Response [201] /api/admin/tokens ------");
+            res.status_code(StatusCode::CREATED);
+            res.render(Json(body));
+        }
+        Err(e) => {
+            error!("❌ Failed to create admin token: {}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(json!({ "error": e })));
+        }
+    }
+}
+
+#[handler]
+async fn revoke_token(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [DELETE] {} ------", req.uri());
+    let id = req.param::<String>("id").unwrap_or_default();
+    match revoke_admin_token(&id) {
+        Ok(true) => res.render(Json(json!({ "id": id, "revoked": true }))),
+        Ok(false) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(
+                json!({ "error": format!("Admin token {} not found", id) }),
+            ));
+        }
+        Err(e) => {
+            error!("❌ Failed to revoke admin token {}: {}", id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(json!({ "error": e })));
+        }
+    }
+}
+
+#[handler]
+async fn audit_log(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [GET] {} ------", req.uri());
+    let limit = req.query::<usize>("limit").unwrap_or(100).clamp(1, 1000);
+    let entries = recent_audit(limit);
+    debug!("------ Outgoing Response [200] /api/admin/audit ------");
+    res.render(Json(json!({ "object": "list", "data": entries })));
+}
+
+#[handler]
+async fn cache_stats(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [GET] {} ------", req.uri());
+    let (entries, size_bytes) = url_cache_stats();
+    debug!("------ Outgoing Response [200] /api/admin/cache ------");
+    res.render(Json(
+        json!({ "entries": entries, "size_bytes": size_bytes }),
+    ));
+}
+
+#[handler]
+async fn clear_cache(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [DELETE] {} ------", req.uri());
+    let removed = clear_url_cache();
+    invalidate_config_cache();
+    debug!("------ Outgoing Response [200] /api/admin/cache ------");
+    res.render(Json(json!({ "status": "success", "removed": removed })));
+}
+
 fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
     let config_path = get_config_path("models.yaml");
     if config_path.exists() {
@@ -127,8 +682,7 @@ fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
             enable: Some(false),
             models: std::collections::HashMap::new(),
             custom_models: Some(Vec::new()),
-            api_token: None,
-            use_v1_api: None,
+            ..Default::default()
         })
     }
 }
@@ -145,26 +699,199 @@ fn invalidate_config_cache() {
     remove_config_sled("models.yaml");
 }
 
-pub struct AdminAuthValidator;
+fn basic_credentials(req: &Request) -> Option<(String, String)> {
+    let encoded = req
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(BASE64_STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
 
-impl BasicAuthValidator for AdminAuthValidator {
-    async fn validate(&self, username: &str, password: &str, _depot: &mut Depot) -> bool {
-        let valid_username =
-            std::env::var("ADMIN_USERNAME").unwrap_or_else(|_| "admin".to_string());
-        let valid_password =
-            std::env::var("ADMIN_PASSWORD").unwrap_or_else(|_| "123456".to_string());
-        username == valid_username && password == valid_password
+// Scope an admin token needs for a route, None for routes that require the admin login
+fn required_scope(method: &Method, path: &str) -> Option<AdminScope> {
+    match (path.trim_end_matches('/'), method) {
+        ("/api/admin/config", &Method::GET) => Some(AdminScope::ConfigRead),
+        ("/api/admin/config", &Method::POST) => Some(AdminScope::ConfigWrite),
+        ("/api/admin/cache", _) => Some(AdminScope::CacheManage),
+        _ => None,
     }
 }
 
+fn reject(res: &mut Response, ctrl: &mut FlowCtrl, status: StatusCode, message: &str) {
+    res.status_code(status);
+    res.render(Json(json!({ "error": message })));
+    ctrl.skip_rest();
+}
+
+/// Admin authentication: a session cookie from the login form, a scoped admin token, or HTTP Basic auth for scripts
+#[handler]
+async fn admin_auth(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+    if let Some(session) = req
+        .cookie(SESSION_COOKIE)
+        .and_then(|cookie| find_session(cookie.value()))
+    {
+        // Browsers attach the cookie to cross-site requests too, so writes must echo the page's token
+        let read_only = matches!(*req.method(), Method::GET | Method::HEAD);
+        let token_ok = req
+            .header::<String>(CSRF_HEADER)
+            .is_some_and(|token| hash_token(&token) == hash_token(&session.csrf_token));
+        if !read_only && !token_ok {
+            warn!(
+                "🛡️ Admin request rejected, missing or invalid CSRF token | Path: {}",
+                req.uri().path()
+            );
+            reject(
+                res,
+                ctrl,
+                StatusCode::FORBIDDEN,
+                "Missing or invalid CSRF token",
+            );
+            return;
+        }
+        depot.inject(session);
+        ctrl.call_next(req, depot, res).await;
+        return;
+    }
+
+    let client = client_ip(req);
+    if let Some(remaining) = login_locked_for(&client) {
+        res.headers_mut()
+            .insert(header::RETRY_AFTER, remaining.as_secs().max(1).into());
+        reject(
+            res,
+            ctrl,
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many failed login attempts",
+        );
+        return;
+    }
+    let admin_token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .filter(|token| token.starts_with(ADMIN_TOKEN_PREFIX))
+        .map(|token| token.trim().to_string());
+    if let Some(raw_token) = admin_token {
+        let Some(record) = find_admin_token(&raw_token) else {
+            record_login_failure(&client);
+            reject(res, ctrl, StatusCode::UNAUTHORIZED, "Invalid admin token");
+            return;
+        };
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+        let required = required_scope(&method, &path);
+        let now = Utc::now().timestamp();
+        match record.check_usable(now) {
+            Err(reason) => {
+                warn!("🚫 Admin token rejected | ID: {} | {}", record.id, reason);
+                reject(res, ctrl, StatusCode::UNAUTHORIZED, reason);
+            }
+            Ok(()) if !required.is_some_and(|scope| record.allows(scope)) => {
+                warn!(
+                    "🚫 Admin token lacks scope | ID: {} | {} {}",
+                    record.id, method, path
+                );
+                reject(
+                    res,
+                    ctrl,
+                    StatusCode::FORBIDDEN,
+                    "Admin token is not allowed to access this endpoint",
+                );
+            }
+            Ok(()) => {
+                depot.inject(record.clone());
+                ctrl.call_next(req, depot, res).await
+            }
+        }
+        // Every use is recorded, including rejected ones
+        record_audit(
+            &record,
+            AuditEntry {
+                timestamp: now,
+                token_id: record.id.clone(),
+                token_name: record.name.clone(),
+                method: method.to_string(),
+                path,
+                scope: required.map_or("none", AdminScope::as_str).to_string(),
+                status: res.status_code.unwrap_or(StatusCode::OK).as_u16(),
+                client,
+            },
+        );
+        return;
+    }
+
+    match basic_credentials(req) {
+        Some((username, password)) => {
+            if verify_admin_credentials(&username, &password).await {
+                record_login_success(&client);
+                ctrl.call_next(req, depot, res).await;
+                return;
+            }
+            record_login_failure(&client);
+        }
+        None if req.uri().path() == "/admin" => {
+            res.render(Redirect::other("/admin/login"));
+            ctrl.skip_rest();
+            return;
+        }
+        None => {}
+    }
+    // No WWW-Authenticate challenge, so browsers never cache Basic credentials for the admin API
+    reject(
+        res,
+        ctrl,
+        StatusCode::UNAUTHORIZED,
+        "Authentication required",
+    );
+}
+
 pub fn admin_routes() -> Router {
-    let auth_handler = BasicAuth::new(AdminAuthValidator);
-    Router::new()
-        .hoop(auth_handler) // Add authentication middleware
+    let protected = Router::new()
+        .hoop(admin_auth) // Add authentication middleware
         .push(Router::with_path("admin").get(admin_page))
+        .push(Router::with_path("admin/logout").post(logout))
         .push(
             Router::with_path("api/admin/config")
                 .get(get_config)
                 .post(save_config),
         )
+        .push(
+            Router::with_path("api/admin/keys")
+                .get(list_keys)
+                .post(create_key),
+        )
+        .push(
+            Router::with_path("api/admin/keys/{id}")
+                .patch(update_key)
+                .delete(revoke_key),
+        )
+        .push(Router::with_path("api/admin/pool").get(pool_health))
+        .push(Router::with_path("api/admin/usage").get(usage_summary))
+        .push(Router::with_path("api/admin/policies").get(list_policy_entries))
+        .push(
+            Router::with_path("api/admin/policies/{credential}")
+                .put(put_policy)
+                .delete(remove_policy),
+        )
+        .push(
+            Router::with_path("api/admin/tokens")
+                .get(list_tokens)
+                .post(create_token),
+        )
+        .push(Router::with_path("api/admin/tokens/{id}").delete(revoke_token))
+        .push(Router::with_path("api/admin/audit").get(audit_log))
+        .push(
+            Router::with_path("api/admin/cache")
+                .get(cache_stats)
+                .delete(clear_cache),
+        );
+    Router::new()
+        .hoop(admin_access_middleware)
+        .push(Router::with_path("admin/login").get(login_page).post(login))
+        .push(protected)
 }
@@ -0,0 +1,812 @@
+use super::chat::{StartedChat, extract_access_key, start_chat};
+use super::limit::UsageMeter;
+use crate::cache::get_cached_config;
+use crate::evert::{
+    EventDelta, OutputCut, OutputLimit, collect_events, delta_stream, finalize_content,
+};
+use crate::policy::CredentialPolicy;
+use crate::types::*;
+use crate::utils::{
+    count_completion_tokens, format_duration, pretty_json_truncated, redact_headers,
+    redact_json_fields,
+};
+use futures_util::future;
+use futures_util::stream::{self, StreamExt};
+use nanoid::nanoid;
+use poe_api_process::types::{ChatTool, ChatToolCall};
+use salvo::http::header;
+use salvo::prelude::*;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::time::Instant;
+use tracing::{debug, error, info, warn};
+
+#[handler]
+pub async fn anthropic_messages(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let start_time = Instant::now();
+
+    // Structure request/response logging with separator
+    debug!("------ Incoming Request [POST] {} ------", req.uri());
+
+    // Log inbound request metadata with redacted headers
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let redacted_headers = redact_headers(req.headers());
+
+    debug!(
+        "📝 Request metadata | Method: {} | Path: {} | Headers: {:?}",
+        method, path, redacted_headers
+    );
+
+    let max_size: usize = std::env::var("MAX_REQUEST_SIZE")
+        .unwrap_or_else(|_| "1073741824".to_string())
+        .parse()
+        .unwrap_or(1024 * 1024 * 1024);
+
+    let config = get_cached_config().await;
+
+    // Anthropic clients send x-api-key, fall back to Bearer authorization
+    let access_key = match extract_access_key(req) {
+        Ok(key) => key,
+        Err(message) => {
+            error!("❌ {}", message);
+            render_anthropic_error(res, StatusCode::UNAUTHORIZED, message);
+            return;
+        }
+    };
+
+    // Parse request body
+    let anthropic_request = match req.payload_with_max_size(max_size).await {
+        Ok(bytes) => match serde_json::from_slice::<AnthropicMessagesRequest>(bytes) {
+            Ok(parsed) => {
+                debug!(
+                    "📊 Messages request parsed | Model: {} | Messages: {} | Stream: {:?}",
+                    parsed.model,
+                    parsed.messages.len(),
+                    parsed.stream
+                );
+                let request_value = serde_json::to_value(&parsed).unwrap_or_else(|_| json!(null));
+                let redacted_request = redact_json_fields(&request_value);
+                debug!(
+                    "📋 Request body (sanitized, truncated):\n{}",
+                    pretty_json_truncated(&redacted_request, 64 * 1024)
+                );
+                parsed
+            }
+            Err(e) => {
+                error!("❌ JSON parsing failed: {}", e);
+                render_anthropic_error(
+                    res,
+                    StatusCode::BAD_REQUEST,
+                    &format!("JSON parsing failed: {}", e),
+                );
+                return;
+            }
+        },
+        Err(e) => {
+            error!("❌ Request size exceeded limit or read failed: {}", e);
+            render_anthropic_error(
+                res,
+                StatusCode::PAYLOAD_TOO_LARGE,
+                &format!(
+                    "Request size exceeded limit ({} bytes) or read failed: {}",
+                    max_size, e
+                ),
+            );
+            return;
+        }
+    };
+
+    let stream = anthropic_request.stream.unwrap_or(false);
+    let chat_request = anthropic_to_chat_request(&anthropic_request);
+    let usage_meter = depot.obtain::<UsageMeter>().cloned().unwrap_or_default();
+    let policy = depot.obtain::<CredentialPolicy>().ok();
+
+    match start_chat(&config, &access_key, &chat_request, policy).await {
+        Ok(started) => {
+            let id = format!("msg_{}", nanoid!(24));
+            let limit = started.output_limit(&chat_request);
+            if stream {
+                handle_stream_response(res, started, id, usage_meter, limit);
+            } else {
+                handle_non_stream_response(res, started, id, usage_meter, limit).await;
+            }
+        }
+        Err((status, error_response)) => {
+            render_anthropic_error(res, status, &error_response.error.message);
+        }
+    }
+
+    let duration = start_time.elapsed();
+    info!(
+        "✅ Messages request processing completed | Duration: {}",
+        format_duration(duration)
+    );
+}
+
+fn handle_stream_response(
+    res: &mut Response,
+    started: StartedChat,
+    id: String,
+    usage_meter: UsageMeter,
+    limit: OutputLimit,
+) {
+    info!(
+        "🌊 Starting Anthropic streaming response | ID: {} | Model: {}",
+        id, started.display_model
+    );
+
+    res.headers_mut()
+        .insert(header::CONTENT_TYPE, "text/event-stream".parse().unwrap());
+    res.headers_mut()
+        .insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
+    res.headers_mut()
+        .insert(header::CONNECTION, "keep-alive".parse().unwrap());
+
+    let mut state = AnthropicStreamState::new(id, started.display_model, started.prompt_tokens);
+    state.usage_meter = usage_meter;
+    state.limit = limit;
+    state.refusal = started.refusal;
+    let message_start = state.message_start();
+    let events = delta_stream(started.event_stream).scan(state, |state, delta| {
+        future::ready(if state.finished {
+            None
+        } else {
+            Some(Ok::<String, Infallible>(state.render(delta)))
+        })
+    });
+
+    let body = stream::once(future::ready(Ok::<String, Infallible>(message_start)))
+        .chain(events)
+        .filter(|result| {
+            future::ready(match result {
+                Ok(s) => !s.is_empty(),
+                Err(_) => true,
+            })
+        });
+    res.stream(body);
+}
+
+async fn handle_non_stream_response(
+    res: &mut Response,
+    started: StartedChat,
+    id: String,
+    usage_meter: UsageMeter,
+    limit: OutputLimit,
+) {
+    let model = started.display_model;
+    let input_tokens = started.prompt_tokens;
+    let mut ctx = match collect_events(started.event_stream).await {
+        Ok(ctx) => ctx,
+        Err((status, error_response)) => {
+            render_anthropic_error(res, status, &error_response.error.message);
+            return;
+        }
+    };
+
+    let (mut text, cut) = limit.apply(&finalize_content(&mut ctx));
+    // A failed structured output check is reported as a refusal with its reason as the text
+    let (stop_reason, stop_sequence) = match started.refusal {
+        Some(refusal) => {
+            text = refusal;
+            ("refusal", None)
+        }
+        None => stop_reason(cut.as_ref(), !ctx.tool_calls.is_empty()),
+    };
+    let output_tokens = count_completion_tokens(&text);
+    usage_meter.record(&model, input_tokens, output_tokens);
+
+    let mut content = Vec::new();
+    if !ctx.reasoning_content.trim().is_empty() {
+        content.push(AnthropicContentBlock::Thinking {
+            thinking: ctx.reasoning_content.clone(),
+            signature: String::new(),
+        });
+    }
+    if !text.is_empty() {
+        content.push(AnthropicContentBlock::Text { text });
+    }
+    for tool_call in &ctx.tool_calls {
+        content.push(AnthropicContentBlock::ToolUse {
+            id: tool_call.id.clone(),
+            name: tool_call.function.name.clone(),
+            input: parse_tool_input(&tool_call.function.arguments),
+        });
+    }
+
+    let response = AnthropicMessagesResponse {
+        id,
+        r#type: "message".to_string(),
+        role: "assistant".to_string(),
+        model,
+        content,
+        stop_reason: Some(stop_reason.to_string()),
+        stop_sequence,
+        usage: AnthropicUsage {
+            input_tokens,
+            output_tokens,
+        },
+    };
+
+    let response_value = serde_json::to_value(&response).unwrap_or_else(|_| json!(null));
+    debug!(
+        "📤 Response body (sanitized, truncated):\n{}",
+        pretty_json_truncated(&redact_json_fields(&response_value), 64 * 1024)
+    );
+    debug!("------ Outgoing Response [200] /v1/messages ------");
+
+    res.render(Json(response));
+}
+
+// Tracks open content blocks while translating deltas into Anthropic SSE events
+struct AnthropicStreamState {
+    id: String,
+    model: String,
+    input_tokens: u32,
+    block_index: u32,
+    open_block: Option<&'static str>,
+    output_text: String,
+    has_tool_use: bool,
+    usage_meter: UsageMeter,
+    limit: OutputLimit,
+    refusal: Option<String>,
+    finished: bool,
+}
+
+impl AnthropicStreamState {
+    fn new(id: String, model: String, input_tokens: u32) -> Self {
+        Self {
+            id,
+            model,
+            input_tokens,
+            block_index: 0,
+            open_block: None,
+            output_text: String::new(),
+            has_tool_use: false,
+            usage_meter: UsageMeter::default(),
+            limit: OutputLimit::default(),
+            refusal: None,
+            finished: false,
+        }
+    }
+
+    fn message_start(&self) -> String {
+        sse_event(
+            "message_start",
+            &json!({
+                "type": "message_start",
+                "message": {
+                    "id": self.id,
+                    "type": "message",
+                    "role": "assistant",
+                    "model": self.model,
+                    "content": [],
+                    "stop_reason": null,
+                    "stop_sequence": null,
+                    "usage": {"input_tokens": self.input_tokens, "output_tokens": 0}
+                }
+            }),
+        )
+    }
+
+    fn render(&mut self, delta: EventDelta) -> String {
+        match delta {
+            EventDelta::Reasoning(thinking) => {
+                let mut output = self.ensure_block("thinking");
+                output.push_str(&sse_event(
+                    "content_block_delta",
+                    &json!({
+                        "type": "content_block_delta",
+                        "index": self.block_index,
+                        "delta": {"type": "thinking_delta", "thinking": thinking}
+                    }),
+                ));
+                output
+            }
+            EventDelta::Content(text) => {
+                let text = self.limit.push(&text);
+                let mut output = self.text_delta(text);
+                if self.limit.cut().is_some() {
+                    output.push_str(&self.message_end());
+                }
+                output
+            }
+            EventDelta::ToolCalls(tool_calls) => {
+                let mut output = self.close_block();
+                for tool_call in tool_calls {
+                    self.has_tool_use = true;
+                    output.push_str(&sse_event(
+                        "content_block_start",
+                        &json!({
+                            "type": "content_block_start",
+                            "index": self.block_index,
+                            "content_block": {
+                                "type": "tool_use",
+                                "id": tool_call.call.id,
+                                "name": tool_call.call.function.name,
+                                "input": {}
+                            }
+                        }),
+                    ));
+                    output.push_str(&sse_event(
+                        "content_block_delta",
+                        &json!({
+                            "type": "content_block_delta",
+                            "index": self.block_index,
+                            "delta": {
+                                "type": "input_json_delta",
+                                "partial_json": tool_call.call.function.arguments
+                            }
+                        }),
+                    ));
+                    output.push_str(&sse_event(
+                        "content_block_stop",
+                        &json!({"type": "content_block_stop", "index": self.block_index}),
+                    ));
+                    self.block_index += 1;
+                }
+                output
+            }
+            EventDelta::Error(status, error_response) => {
+                debug!("❌ Detected error, interrupting Anthropic stream");
+                self.finished = true;
+                let error = anthropic_error(status, &error_response.error.message);
+                sse_event("error", &serde_json::to_value(&error).unwrap())
+            }
+            EventDelta::Done => {
+                let text = self.limit.finish();
+                let mut output = self.text_delta(text);
+                output.push_str(&self.message_end());
+                output
+            }
+        }
+    }
+
+    fn text_delta(&mut self, text: String) -> String {
+        if text.is_empty() {
+            return String::new();
+        }
+        let mut output = self.ensure_block("text");
+        self.output_text.push_str(&text);
+        output.push_str(&sse_event(
+            "content_block_delta",
+            &json!({
+                "type": "content_block_delta",
+                "index": self.block_index,
+                "delta": {"type": "text_delta", "text": text}
+            }),
+        ));
+        output
+    }
+
+    // Close the open block and finish the message, nothing is sent after this
+    fn message_end(&mut self) -> String {
+        self.finished = true;
+        let (mut output, (stop_reason, stop_sequence)) = match self.refusal.take() {
+            Some(refusal) => (self.text_delta(refusal), ("refusal", None)),
+            None => (
+                String::new(),
+                stop_reason(self.limit.cut(), self.has_tool_use),
+            ),
+        };
+        output.push_str(&self.close_block());
+        let output_tokens = count_completion_tokens(&self.output_text);
+        debug!(
+            "📊 Token usage statistics | input_tokens: {} | output_tokens: {}",
+            self.input_tokens, output_tokens
+        );
+        self.usage_meter
+            .record(&self.model, self.input_tokens, output_tokens);
+        output.push_str(&sse_event(
+            "message_delta",
+            &json!({
+                "type": "message_delta",
+                "delta": {"stop_reason": stop_reason, "stop_sequence": stop_sequence},
+                "usage": {"output_tokens": output_tokens}
+            }),
+        ));
+        output.push_str(&sse_event("message_stop", &json!({"type": "message_stop"})));
+        output
+    }
+
+    // Open a block of the given kind, closing any different block first
+    fn ensure_block(&mut self, kind: &'static str) -> String {
+        if self.open_block == Some(kind) {
+            return String::new();
+        }
+        let mut output = self.close_block();
+        let content_block = if kind == "thinking" {
+            json!({"type": "thinking", "thinking": ""})
+        } else {
+            json!({"type": "text", "text": ""})
+        };
+        output.push_str(&sse_event(
+            "content_block_start",
+            &json!({
+                "type": "content_block_start",
+                "index": self.block_index,
+                "content_block": content_block
+            }),
+        ));
+        self.open_block = Some(kind);
+        output
+    }
+
+    fn close_block(&mut self) -> String {
+        if self.open_block.take().is_none() {
+            return String::new();
+        }
+        let output = sse_event(
+            "content_block_stop",
+            &json!({"type": "content_block_stop", "index": self.block_index}),
+        );
+        self.block_index += 1;
+        output
+    }
+}
+
+// Anthropic stop_reason and stop_sequence for how the reply ended
+fn stop_reason(cut: Option<&OutputCut>, has_tool_use: bool) -> (&'static str, Option<String>) {
+    match cut {
+        Some(OutputCut::MaxTokens) => ("max_tokens", None),
+        Some(OutputCut::StopSequence(stop)) => ("stop_sequence", Some(stop.clone())),
+        None if has_tool_use => ("tool_use", None),
+        None => ("end_turn", None),
+    }
+}
+
+fn sse_event(name: &str, data: &Value) -> String {
+    format!("event: {}\ndata: {}\n\n", name, data)
+}
+
+fn anthropic_error(status: StatusCode, message: &str) -> AnthropicErrorResponse {
+    let error_type = match status.as_u16() {
+        400 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        413 => "request_too_large",
+        429 => "rate_limit_error",
+        529 => "overloaded_error",
+        _ => "api_error",
+    };
+    AnthropicErrorResponse {
+        r#type: "error".to_string(),
+        error: AnthropicError {
+            r#type: error_type.to_string(),
+            message: message.to_string(),
+        },
+    }
+}
+
+fn render_anthropic_error(res: &mut Response, status: StatusCode, message: &str) {
+    debug!(
+        "------ Outgoing Response [{}] /v1/messages ------",
+        status.as_u16()
+    );
+    res.status_code(status);
+    res.render(Json(anthropic_error(status, message)));
+}
+
+// Tool arguments are a JSON string in OpenAI format but an object in Anthropic format
+fn parse_tool_input(arguments: &str) -> Value {
+    if arguments.trim().is_empty() {
+        return json!({});
+    }
+    serde_json::from_str(arguments).unwrap_or_else(|e| {
+        warn!(
+            "⚠️ Tool arguments are not valid JSON, wrapping as string: {}",
+            e
+        );
+        json!({ "arguments": arguments })
+    })
+}
+
+/// Convert an Anthropic Messages request into the internal chat completion request
+pub(crate) fn anthropic_to_chat_request(
+    request: &AnthropicMessagesRequest,
+) -> ChatCompletionRequest {
+    let mut messages = Vec::new();
+
+    if let Some(system) = &request.system {
+        let system_text = anthropic_content_text(system);
+        if !system_text.is_empty() {
+            messages.push(Message {
+                role: "system".to_string(),
+                content: Some(OpenAiContent::Text(system_text)),
+                ..Default::default()
+            });
+        }
+    }
+
+    for message in &request.messages {
+        match &message.content {
+            AnthropicContent::Text(text) => messages.push(Message {
+                role: message.role.clone(),
+                content: Some(OpenAiContent::Text(text.clone())),
+                ..Default::default()
+            }),
+            AnthropicContent::Blocks(blocks) => {
+                messages.extend(convert_blocks(&message.role, blocks));
+            }
+        }
+    }
+
+    let tools: Option<Vec<ChatTool>> = request.tools.as_ref().map(|tools| {
+        tools
+            .iter()
+            .filter_map(|tool| {
+                let value = json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name,
+                        "description": tool.description,
+                        "parameters": tool.input_schema.clone().unwrap_or_else(|| json!({"type": "object"}))
+                    }
+                });
+                match serde_json::from_value::<ChatTool>(value) {
+                    Ok(chat_tool) => Some(chat_tool),
+                    Err(e) => {
+                        warn!("⚠️ Skipping Anthropic tool '{}': {}", tool.name, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    });
+
+    let (tool_choice, parallel_tool_calls) = match &request.tool_choice {
+        Some(choice) => (
+            anthropic_tool_choice(choice),
+            choice
+                .get("disable_parallel_tool_use")
+                .and_then(|v| v.as_bool())
+                .map(|disabled| !disabled),
+        ),
+        None => (None, None),
+    };
+
+    let thinking = request
+        .thinking
+        .as_ref()
+        .filter(|thinking| thinking.r#type == "enabled")
+        .map(|thinking| ThinkingConfig {
+            budget_tokens: thinking.budget_tokens,
+        });
+
+    ChatCompletionRequest {
+        model: request.model.clone(),
+        messages,
+        temperature: request.temperature,
+        top_p: request.top_p,
+        max_tokens: request.max_tokens,
+        stop: request.stop_sequences.clone(),
+        stream: request.stream,
+        tools,
+        tool_choice,
+        parallel_tool_calls,
+        thinking,
+        metadata: request.metadata.clone(),
+        ..Default::default()
+    }
+}
+
+// Map Anthropic tool_choice ({"type": "auto" | "any" | "tool" | "none"}) to OpenAI format
+fn anthropic_tool_choice(choice: &Value) -> Option<Value> {
+    match choice.get("type").and_then(|t| t.as_str()) {
+        Some("auto") => Some(json!("auto")),
+        Some("any") => Some(json!("required")),
+        Some("none") => Some(json!("none")),
+        Some("tool") => choice
+            .get("name")
+            .map(|name| json!({"type": "function", "function": {"name": name}})),
+        _ => None,
+    }
+}
+
+// Convert one Anthropic message's blocks into OpenAI-style messages.
+// tool_result blocks become separate tool messages placed before the remaining content.
+fn convert_blocks(role: &str, blocks: &[AnthropicContentBlock]) -> Vec<Message> {
+    let mut items: Vec<OpenAiContentItem> = Vec::new();
+    let mut tool_calls: Vec<ChatToolCall> = Vec::new();
+    let mut converted = Vec::new();
+
+    for block in blocks {
+        match block {
+            AnthropicContentBlock::Text { text } => items.push(OpenAiContentItem::Text {
+                r#type: Some("text".to_string()),
+                text: text.clone(),
+                extra: HashMap::new(),
+            }),
+            AnthropicContentBlock::Image { source }
+            | AnthropicContentBlock::Document { source } => {
+                if let Some(url) = source_to_url(source) {
+                    items.push(OpenAiContentItem::ImageUrl {
+                        r#type: Some("image_url".to_string()),
+                        image_url: ImageUrlContent {
+                            url,
+                            extra: HashMap::new(),
+                        },
+                        extra: HashMap::new(),
+                    });
+                } else {
+                    warn!("⚠️ Unsupported Anthropic source type: {}", source.r#type);
+                }
+            }
+            AnthropicContentBlock::ToolUse { id, name, input } => {
+                let arguments = if input.is_null() {
+                    "{}".to_string()
+                } else {
+                    input.to_string()
+                };
+                match serde_json::from_value::<ChatToolCall>(json!({
+                    "id": id,
+                    "type": "function",
+                    "function": {"name": name, "arguments": arguments}
+                })) {
+                    Ok(tool_call) => tool_calls.push(tool_call),
+                    Err(e) => warn!("⚠️ Skipping tool_use block {}: {}", id, e),
+                }
+            }
+            AnthropicContentBlock::ToolResult {
+                tool_use_id,
+                content,
+                is_error,
+            } => {
+                let mut result_text = content
+                    .as_ref()
+                    .map(anthropic_content_text)
+                    .unwrap_or_default();
+                if is_error.unwrap_or(false) {
+                    result_text = format!("Error: {}", result_text);
+                }
+                converted.push(Message {
+                    role: "tool".to_string(),
+                    content: Some(OpenAiContent::Text(result_text)),
+                    tool_call_id: Some(tool_use_id.clone()),
+                    ..Default::default()
+                });
+            }
+            AnthropicContentBlock::Thinking { .. }
+            | AnthropicContentBlock::RedactedThinking { .. } => {
+                debug!("🧠 Skipping thinking block from conversation history");
+            }
+            AnthropicContentBlock::Unsupported => {
+                debug!("🔍 Skipping unsupported Anthropic content block");
+            }
+        }
+    }
+
+    if !items.is_empty() || !tool_calls.is_empty() {
+        converted.push(Message {
+            role: role.to_string(),
+            content: if items.is_empty() {
+                None
+            } else {
+                Some(OpenAiContent::Multi(items))
+            },
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+            ..Default::default()
+        });
+    }
+
+    converted
+}
+
+fn source_to_url(source: &AnthropicSource) -> Option<String> {
+    match source.r#type.as_str() {
+        "base64" => source.data.as_ref().map(|data| {
+            format!(
+                "data:{};base64,{}",
+                source
+                    .media_type
+                    .as_deref()
+                    .unwrap_or("application/octet-stream"),
+                data
+            )
+        }),
+        "url" => source.url.clone(),
+        _ => None,
+    }
+}
+
+// Join the text parts of Anthropic content
+fn anthropic_content_text(content: &AnthropicContent) -> String {
+    match content {
+        AnthropicContent::Text(text) => text.clone(),
+        AnthropicContent::Blocks(blocks) => blocks
+            .iter()
+            .filter_map(|block| match block {
+                AnthropicContentBlock::Text { text } => Some(text.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_tool_use_and_tool_result_blocks() {
+        let payload = json!({
+            "model": "claude-sonnet-4",
+            "max_tokens": 1024,
+            "system": [{"type": "text", "text": "Be brief"}],
+            "messages": [
+                {"role": "user", "content": "Weather in Paris?"},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "need tool", "signature": "abc"},
+                    {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "Sunny"},
+                    {"type": "text", "text": "Thanks"}
+                ]}
+            ],
+            "tool_choice": {"type": "any", "disable_parallel_tool_use": true}
+        });
+
+        let request: AnthropicMessagesRequest =
+            serde_json::from_value(payload).expect("valid request");
+        let chat_request = anthropic_to_chat_request(&request);
+
+        let roles: Vec<&str> = chat_request
+            .messages
+            .iter()
+            .map(|m| m.role.as_str())
+            .collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "tool", "user"]);
+
+        let tool_calls = chat_request.messages[2]
+            .tool_calls
+            .as_ref()
+            .expect("tool calls present");
+        assert_eq!(tool_calls[0].id, "toolu_1");
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(
+            chat_request.messages[3].tool_call_id.as_deref(),
+            Some("toolu_1")
+        );
+        assert_eq!(chat_request.tool_choice, Some(json!("required")));
+        assert_eq!(chat_request.parallel_tool_calls, Some(false));
+        assert_eq!(chat_request.max_tokens, Some(1024));
+    }
+
+    #[test]
+    fn stream_state_closes_blocks_in_order() {
+        let mut state = AnthropicStreamState::new("msg_1".to_string(), "m".to_string(), 3);
+        let reasoning = state.render(EventDelta::Reasoning("hmm".to_string()));
+        assert!(reasoning.contains("\"type\":\"thinking\""));
+        let text = state.render(EventDelta::Content("Hi".to_string()));
+        assert!(text.starts_with("event: content_block_stop"));
+        assert!(text.contains("\"index\":1"));
+        let done = state.render(EventDelta::Done);
+        assert!(done.contains("\"stop_reason\":\"end_turn\""));
+        assert!(done.ends_with("event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n"));
+    }
+
+    #[test]
+    fn stream_state_reports_stop_sequence_and_refusal() {
+        let mut state = AnthropicStreamState::new("msg_1".to_string(), "m".to_string(), 3);
+        state.limit = OutputLimit::new(&["END".to_string()], None);
+        let text = state.render(EventDelta::Content("Hi END there".to_string()));
+        assert!(text.contains("\"text\":\"Hi \""));
+        assert!(text.contains("\"stop_reason\":\"stop_sequence\""));
+        assert!(text.contains("\"stop_sequence\":\"END\""));
+        assert!(state.finished);
+
+        let mut state = AnthropicStreamState::new("msg_2".to_string(), "m".to_string(), 3);
+        state.refusal = Some("invalid output".to_string());
+        let done = state.render(EventDelta::Done);
+        assert!(done.contains("\"text\":\"invalid output\""));
+        assert!(done.contains("\"stop_reason\":\"refusal\""));
+    }
+}
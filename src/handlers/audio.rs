@@ -0,0 +1,792 @@
+use super::chat::{extract_access_key, resolve_model, start_chat};
+use super::limit::UsageMeter;
+use crate::cache::get_cached_config;
+use crate::evert::{EventContext, collect_events, finalize_content};
+use crate::policy::CredentialPolicy;
+use crate::types::*;
+use crate::utils::{
+    count_completion_tokens, fetch_file, format_bytes_length, format_duration, is_poe_cdn_url,
+    pretty_json_truncated, redact_headers, redact_json_fields, strip_code_fences,
+};
+use base64::prelude::*;
+use regex::Regex;
+use salvo::http::header;
+use salvo::prelude::*;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Instant;
+use tracing::{debug, error, info, warn};
+
+#[handler]
+pub async fn audio_speech(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let start_time = Instant::now();
+
+    // Structure request/response logging with separator
+    debug!("------ Incoming Request [POST] {} ------", req.uri());
+
+    // Log inbound request metadata with redacted headers
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let redacted_headers = redact_headers(req.headers());
+
+    debug!(
+        "📝 Request metadata | Method: {} | Path: {} | Headers: {:?}",
+        method, path, redacted_headers
+    );
+
+    let max_size: usize = std::env::var("MAX_REQUEST_SIZE")
+        .unwrap_or_else(|_| "1073741824".to_string())
+        .parse()
+        .unwrap_or(1024 * 1024 * 1024);
+
+    let config = get_cached_config().await;
+
+    // Validate authorization
+    let access_key = match extract_access_key(req) {
+        Ok(key) => key,
+        Err(message) => {
+            error!("❌ {}", message);
+            res.status_code(StatusCode::UNAUTHORIZED);
+            res.render(Json(json!({ "error": message })));
+            return;
+        }
+    };
+
+    // Parse request body
+    let speech_request = match req.payload_with_max_size(max_size).await {
+        Ok(bytes) => match serde_json::from_slice::<SpeechRequest>(bytes) {
+            Ok(parsed) => {
+                let request_value = serde_json::to_value(&parsed).unwrap_or_else(|_| json!(null));
+                let redacted_request = redact_json_fields(&request_value);
+                debug!(
+                    "📋 Request body (sanitized, truncated):\n{}",
+                    pretty_json_truncated(&redacted_request, 64 * 1024)
+                );
+                parsed
+            }
+            Err(e) => {
+                error!("❌ JSON parsing failed: {}", e);
+                res.status_code(StatusCode::BAD_REQUEST);
+                res.render(Json(
+                    json!({ "error": format!("JSON parsing failed: {}", e) }),
+                ));
+                return;
+            }
+        },
+        Err(e) => {
+            error!("❌ Request size exceeded limit or read failed: {}", e);
+            res.status_code(StatusCode::PAYLOAD_TOO_LARGE);
+            res.render(Json(json!({
+                "error": format!("Request size exceeded limit ({} bytes) or read failed: {}", max_size, e)
+            })));
+            return;
+        }
+    };
+
+    if speech_request.input.trim().is_empty() {
+        render_audio_error(
+            res,
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            "invalid_value",
+            "input must not be empty",
+            Some("input"),
+        );
+        return;
+    }
+    if speech_request
+        .speed
+        .is_some_and(|speed| !(0.25..=4.0).contains(&speed))
+    {
+        render_audio_error(
+            res,
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            "invalid_value",
+            "speed must be between 0.25 and 4.0",
+            Some("speed"),
+        );
+        return;
+    }
+    let response_format = speech_request.response_format.as_deref().unwrap_or("mp3");
+    let Some(content_type) = speech_content_type(response_format) else {
+        render_audio_error(
+            res,
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            "invalid_value",
+            "response_format must be one of mp3, opus, aac, flac, wav, pcm",
+            Some("response_format"),
+        );
+        return;
+    };
+
+    let model = audio_bot(
+        &config,
+        speech_request.model.as_deref(),
+        "DEFAULT_TTS_MODEL",
+        "elevenlabs",
+    );
+    let chat_request = ChatCompletionRequest {
+        model: model.clone(),
+        messages: vec![Message {
+            role: "user".to_string(),
+            content: Some(OpenAiContent::Text(speech_query(&speech_request))),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    info!(
+        "🔊 Generating speech | Model: {} | Voice: {:?} | Format: {}",
+        model, speech_request.voice, response_format
+    );
+
+    // Wait for the bot to attach the audio file
+    let usage_meter = depot.obtain::<UsageMeter>().cloned().unwrap_or_default();
+    let policy = depot.obtain::<CredentialPolicy>().ok();
+    let audio_url =
+        match generate_audio_url(&config, &access_key, &chat_request, policy, &usage_meter).await {
+            Ok(url) => url,
+            Err((status, error_response)) => {
+                res.status_code(status);
+                res.render(Json(error_response));
+                return;
+            }
+        };
+
+    let upstream = match fetch_file(&audio_url).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            render_audio_error(
+                res,
+                StatusCode::BAD_GATEWAY,
+                "api_error",
+                "audio_download_failed",
+                &format!("Failed to download generated audio: {}", e),
+                None,
+            );
+            return;
+        }
+    };
+
+    // Poe bots pick their own output encoding, no transcoding is done here
+    if let Some(upstream_type) = upstream
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.starts_with(content_type))
+    {
+        warn!(
+            "⚠️ TTS bot returned {} but {} was requested",
+            upstream_type, content_type
+        );
+    }
+
+    res.headers_mut()
+        .insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+    res.stream(upstream.bytes_stream());
+
+    let duration = start_time.elapsed();
+    info!(
+        "✅ Speech generation completed | Duration: {}",
+        format_duration(duration)
+    );
+}
+
+#[handler]
+pub async fn audio_transcriptions(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    handle_audio_to_text(req, depot, res, AudioTask::Transcribe).await;
+}
+
+#[handler]
+pub async fn audio_translations(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    handle_audio_to_text(req, depot, res, AudioTask::Translate).await;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum AudioTask {
+    Transcribe,
+    Translate,
+}
+
+async fn handle_audio_to_text(
+    req: &mut Request,
+    depot: &Depot,
+    res: &mut Response,
+    task: AudioTask,
+) {
+    let start_time = Instant::now();
+
+    // Structure request/response logging with separator
+    debug!("------ Incoming Request [POST] {} ------", req.uri());
+
+    // Log inbound request metadata with redacted headers
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let redacted_headers = redact_headers(req.headers());
+
+    debug!(
+        "📝 Request metadata | Method: {} | Path: {} | Headers: {:?}",
+        method, path, redacted_headers
+    );
+
+    let max_size: usize = std::env::var("MAX_REQUEST_SIZE")
+        .unwrap_or_else(|_| "1073741824".to_string())
+        .parse()
+        .unwrap_or(1024 * 1024 * 1024);
+    req.set_secure_max_size(max_size);
+
+    let config = get_cached_config().await;
+
+    // Validate authorization
+    let access_key = match extract_access_key(req) {
+        Ok(key) => key,
+        Err(message) => {
+            error!("❌ {}", message);
+            res.status_code(StatusCode::UNAUTHORIZED);
+            res.render(Json(json!({ "error": message })));
+            return;
+        }
+    };
+
+    let AudioForm {
+        audio_bytes,
+        file_name,
+        mime_type,
+        model: model_field,
+        language,
+        prompt,
+        response_format,
+    } = match read_audio_form(req).await {
+        Ok(form) => form,
+        Err((status, error_response)) => {
+            error!("❌ Audio request failed: {}", error_response.error.message);
+            res.status_code(status);
+            res.render(Json(error_response));
+            return;
+        }
+    };
+    info!(
+        "🎙️ Received audio | Name: {} | Type: {} | Size: {}",
+        file_name,
+        mime_type,
+        format_bytes_length(audio_bytes.len())
+    );
+
+    let model = audio_bot(
+        &config,
+        model_field.as_deref(),
+        "DEFAULT_TRANSCRIPTION_MODEL",
+        "gemini-2.5-flash",
+    );
+
+    // The audio goes through the data URL upload path (base64 cache + upload_files_batch)
+    let data_url = format!(
+        "data:{};base64,{}",
+        mime_type,
+        BASE64_STANDARD.encode(&audio_bytes)
+    );
+    let chat_request = ChatCompletionRequest {
+        model: model.clone(),
+        messages: vec![Message {
+            role: "user".to_string(),
+            content: Some(OpenAiContent::Multi(vec![
+                OpenAiContentItem::Text {
+                    r#type: Some("text".to_string()),
+                    text: audio_instructions(
+                        task,
+                        &response_format,
+                        language.as_deref(),
+                        prompt.as_deref(),
+                    ),
+                    extra: HashMap::new(),
+                },
+                OpenAiContentItem::ImageUrl {
+                    r#type: Some("image_url".to_string()),
+                    image_url: ImageUrlContent {
+                        url: data_url,
+                        extra: HashMap::new(),
+                    },
+                    extra: HashMap::new(),
+                },
+            ])),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let usage_meter = depot.obtain::<UsageMeter>().cloned().unwrap_or_default();
+    let policy = depot.obtain::<CredentialPolicy>().ok();
+    let text = match start_chat(&config, &access_key, &chat_request, policy).await {
+        Ok(started) => match collect_events(started.event_stream).await {
+            Ok(mut ctx) => {
+                let text = strip_code_fences(&finalize_content(&mut ctx));
+                usage_meter.record(
+                    &started.display_model,
+                    started.prompt_tokens,
+                    count_completion_tokens(&text),
+                );
+                text
+            }
+            Err((status, error_response)) => {
+                res.status_code(status);
+                res.render(Json(error_response));
+                return;
+            }
+        },
+        Err((status, error_response)) => {
+            res.status_code(status);
+            res.render(Json(error_response));
+            return;
+        }
+    };
+
+    match response_format.as_str() {
+        "json" => res.render(Json(json!({ "text": text }))),
+        "verbose_json" => res.render(Json(verbose_transcription(
+            task,
+            language.as_deref(),
+            &text,
+        ))),
+        "vtt" => {
+            let vtt = if text.starts_with("WEBVTT") {
+                text
+            } else {
+                format!("WEBVTT\n\n{}", text)
+            };
+            res.headers_mut().insert(
+                header::CONTENT_TYPE,
+                "text/vtt; charset=utf-8".parse().unwrap(),
+            );
+            if let Err(e) = res.write_body(vtt) {
+                error!("❌ Failed to write VTT body: {}", e);
+            }
+        }
+        // text and srt are returned as plain text
+        _ => res.render(Text::Plain(text)),
+    }
+
+    let duration = start_time.elapsed();
+    info!(
+        "✅ Audio {} completed | Model: {} | Duration: {}",
+        if task == AudioTask::Transcribe {
+            "transcription"
+        } else {
+            "translation"
+        },
+        model,
+        format_duration(duration)
+    );
+}
+
+// Fields of a transcription or translation multipart/form-data upload
+struct AudioForm {
+    audio_bytes: Vec<u8>,
+    file_name: String,
+    mime_type: String,
+    model: Option<String>,
+    language: Option<String>,
+    prompt: Option<String>,
+    response_format: String,
+}
+
+// Read the uploaded audio and its options from multipart/form-data
+async fn read_audio_form(
+    req: &mut Request,
+) -> Result<AudioForm, (StatusCode, OpenAIErrorResponse)> {
+    let (file_path, file_name, mime_type) = match req.file("file").await {
+        Some(file) => (
+            file.path().clone(),
+            file.name().unwrap_or("audio").to_string(),
+            file.content_type().map(|mime| mime.to_string()),
+        ),
+        None => {
+            return Err(audio_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                "invalid_value",
+                "Missing audio file in multipart field 'file'",
+                Some("file"),
+            ));
+        }
+    };
+    let model = req.form::<String>("model").await;
+    let language = req.form::<String>("language").await;
+    let prompt = req.form::<String>("prompt").await;
+    let response_format = req
+        .form::<String>("response_format")
+        .await
+        .unwrap_or_else(|| "json".to_string());
+
+    if !["json", "text", "srt", "verbose_json", "vtt"].contains(&response_format.as_str()) {
+        return Err(audio_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            "invalid_value",
+            "response_format must be one of json, text, srt, verbose_json, vtt",
+            Some("response_format"),
+        ));
+    }
+
+    let audio_bytes = tokio::fs::read(&file_path).await.map_err(|e| {
+        audio_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            "invalid_value",
+            &format!("Failed to read uploaded audio: {}", e),
+            Some("file"),
+        )
+    })?;
+    // Browsers often send application/octet-stream, fall back to the file extension
+    let mime_type = mime_type
+        .filter(|mime| mime.starts_with("audio/") || mime.starts_with("video/"))
+        .unwrap_or_else(|| audio_mime_from_file_name(&file_name).to_string());
+
+    Ok(AudioForm {
+        audio_bytes,
+        file_name,
+        mime_type,
+        model,
+        language,
+        prompt,
+        response_format,
+    })
+}
+
+// verbose_json body; Poe bots return plain text, so no segments or duration are available
+fn verbose_transcription(task: AudioTask, language: Option<&str>, text: &str) -> serde_json::Value {
+    let language = match task {
+        AudioTask::Transcribe => language.filter(|l| !l.is_empty()).unwrap_or("unknown"),
+        AudioTask::Translate => "english",
+    };
+    json!({
+        "task": if task == AudioTask::Transcribe { "transcribe" } else { "translate" },
+        "language": language,
+        "text": text,
+        "segments": []
+    })
+}
+
+// Instruction sent with the audio attachment
+fn audio_instructions(
+    task: AudioTask,
+    response_format: &str,
+    language: Option<&str>,
+    prompt: Option<&str>,
+) -> String {
+    let mut instructions = match task {
+        AudioTask::Transcribe => {
+            "Transcribe the speech in the attached audio file verbatim.".to_string()
+        }
+        AudioTask::Translate => {
+            "Translate the speech in the attached audio file into English.".to_string()
+        }
+    };
+    if let Some(language) = language.filter(|l| !l.is_empty()) {
+        instructions.push_str(&format!(" The spoken language is {}.", language));
+    }
+    if let Some(prompt) = prompt.filter(|p| !p.is_empty()) {
+        instructions.push_str(&format!(" Context and spelling hints: {}", prompt));
+    }
+    match response_format {
+        "srt" => instructions.push_str(
+            " Format the result as SubRip (SRT) subtitles with numbered cues and timestamps.",
+        ),
+        "vtt" => instructions.push_str(
+            " Format the result as WebVTT subtitles starting with WEBVTT, with timestamps.",
+        ),
+        _ => {}
+    }
+    instructions.push_str(" Reply with the result only, without any commentary.");
+    instructions
+}
+
+fn audio_mime_from_file_name(file_name: &str) -> &'static str {
+    let extension = file_name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "mp3" | "mpga" | "mpeg" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "m4a" | "mp4" => "audio/mp4",
+        "ogg" | "oga" => "audio/ogg",
+        "webm" => "audio/webm",
+        "flac" => "audio/flac",
+        "aac" => "audio/aac",
+        "opus" => "audio/opus",
+        _ => "audio/mpeg",
+    }
+}
+
+// OpenAI audio model names are not Poe bots, route them to the configured bot
+// unless models.yaml maps them explicitly
+fn audio_bot(config: &Config, requested: Option<&str>, env_key: &str, default_bot: &str) -> String {
+    let configured = || std::env::var(env_key).unwrap_or_else(|_| default_bot.to_string());
+    match requested.filter(|m| !m.is_empty()) {
+        Some(model) => {
+            let (_, original_model) = resolve_model(config, model);
+            let lower = model.to_lowercase();
+            let is_openai_audio_model = lower.starts_with("whisper")
+                || lower.starts_with("tts-")
+                || (lower.starts_with("gpt-4o")
+                    && (lower.contains("tts") || lower.contains("transcribe")));
+            if is_openai_audio_model && original_model == model {
+                debug!("🔄 Routing OpenAI audio model {} to configured bot", model);
+                configured()
+            } else {
+                model.to_string()
+            }
+        }
+        None => configured(),
+    }
+}
+
+// Build the bot query: input text followed by voice/speed flags
+fn speech_query(request: &SpeechRequest) -> String {
+    let mut query = request.input.clone();
+    if let Some(voice) = request.voice.as_ref().filter(|v| !v.is_empty()) {
+        query.push_str(&format!(" --voice {}", voice));
+    }
+    if let Some(speed) = request.speed {
+        query.push_str(&format!(" --speed {}", speed));
+    }
+    query
+}
+
+fn speech_content_type(response_format: &str) -> Option<&'static str> {
+    match response_format {
+        "mp3" => Some("audio/mpeg"),
+        "opus" => Some("audio/opus"),
+        "aac" => Some("audio/aac"),
+        "flac" => Some("audio/flac"),
+        "wav" => Some("audio/wav"),
+        "pcm" => Some("audio/pcm"),
+        _ => None,
+    }
+}
+
+// Run the bot call and return the URL of the generated audio
+async fn generate_audio_url(
+    config: &Config,
+    access_key: &str,
+    chat_request: &ChatCompletionRequest,
+    policy: Option<&CredentialPolicy>,
+    usage_meter: &UsageMeter,
+) -> Result<String, (StatusCode, OpenAIErrorResponse)> {
+    let started = start_chat(config, access_key, chat_request, policy).await?;
+    let mut ctx = collect_events(started.event_stream).await?;
+    let audio_url = audio_url_from_reply(&mut ctx)?;
+    // Generated audio is not counted as completion tokens
+    usage_meter.record(&started.display_model, started.prompt_tokens, 0);
+    Ok(audio_url)
+}
+
+// First attached file, or a link in the reply text; a reply without either is a 502
+fn audio_url_from_reply(
+    ctx: &mut EventContext,
+) -> Result<String, (StatusCode, OpenAIErrorResponse)> {
+    if let Some(file) = ctx.file_refs.values().next() {
+        debug!(
+            "🔊 Received audio file | Name: {} | URL: {}",
+            file.name, file.url
+        );
+        return Ok(file.url.clone());
+    }
+
+    // Fall back to a Poe CDN link in the reply text, other hosts are never fetched server-side
+    let content = finalize_content(ctx);
+    let re = Regex::new(r"https?://[^\s)\]]+").unwrap();
+    match re
+        .find_iter(&content)
+        .map(|url| url.as_str())
+        .find(|url| is_poe_cdn_url(url))
+    {
+        Some(url) => Ok(url.to_string()),
+        None => {
+            warn!("⚠️ No audio found in bot reply: {}", content);
+            Err(audio_error(
+                StatusCode::BAD_GATEWAY,
+                "api_error",
+                "no_audio_generated",
+                "The TTS bot did not return an audio file",
+                None,
+            ))
+        }
+    }
+}
+
+fn audio_error(
+    status: StatusCode,
+    error_type: &str,
+    code: &str,
+    message: &str,
+    param: Option<&str>,
+) -> (StatusCode, OpenAIErrorResponse) {
+    (
+        status,
+        OpenAIErrorResponse {
+            error: OpenAIError {
+                message: message.to_string(),
+                r#type: error_type.to_string(),
+                code: code.to_string(),
+                param: param.map(|p| p.to_string()),
+            },
+        },
+    )
+}
+
+fn render_audio_error(
+    res: &mut Response,
+    status: StatusCode,
+    error_type: &str,
+    code: &str,
+    message: &str,
+    param: Option<&str>,
+) {
+    error!("❌ Audio request failed: {}", message);
+    let (status, error_response) = audio_error(status, error_type, code, message, param);
+    res.status_code(status);
+    res.render(Json(error_response));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use salvo::test::TestClient;
+
+    #[test]
+    fn speech_request_maps_voice_and_format() {
+        let request: SpeechRequest = serde_json::from_value(json!({
+            "model": "tts-1",
+            "input": "Hello there",
+            "voice": "alloy",
+            "speed": 1.5,
+            "response_format": "opus"
+        }))
+        .expect("valid request");
+        assert_eq!(
+            speech_query(&request),
+            "Hello there --voice alloy --speed 1.5"
+        );
+        assert_eq!(
+            speech_content_type(request.response_format.as_deref().unwrap()),
+            Some("audio/opus")
+        );
+        assert_eq!(speech_content_type("mp3"), Some("audio/mpeg"));
+        assert_eq!(speech_content_type("ogg"), None);
+
+        // OpenAI TTS model names go to the configured bot, Poe bot names pass through
+        let config = Config::default();
+        let env_key = "POE2OPENAI_TEST_UNSET_TTS_MODEL";
+        assert_eq!(
+            audio_bot(&config, Some("tts-1"), env_key, "elevenlabs"),
+            "elevenlabs"
+        );
+        assert_eq!(
+            audio_bot(&config, Some("Hailuo-Speech-02"), env_key, "elevenlabs"),
+            "Hailuo-Speech-02"
+        );
+    }
+
+    const BOUNDARY: &str = "poe2openai-test-boundary";
+
+    fn multipart_request(fields: &[(&str, &str)], with_file: bool) -> Request {
+        let mut body = String::new();
+        for (name, value) in fields {
+            body.push_str(&format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            ));
+        }
+        if with_file {
+            body.push_str(&format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"speech.wav\"\r\nContent-Type: application/octet-stream\r\n\r\nRIFF0000WAVE\r\n"
+            ));
+        }
+        body.push_str(&format!("--{BOUNDARY}--\r\n"));
+        TestClient::post("http://127.0.0.1:8080/v1/audio/transcriptions")
+            .add_header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+                true,
+            )
+            .bytes(body.into_bytes())
+            .build()
+    }
+
+    #[tokio::test]
+    async fn audio_form_requires_file_and_known_format() {
+        let mut req = multipart_request(&[("model", "whisper-1")], false);
+        let (status, error_response) = read_audio_form(&mut req).await.err().unwrap();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error_response.error.param.as_deref(), Some("file"));
+
+        for format in ["text", "json", "verbose_json", "srt", "vtt"] {
+            let mut req =
+                multipart_request(&[("model", "whisper-1"), ("response_format", format)], true);
+            let form = read_audio_form(&mut req).await.ok().unwrap();
+            assert_eq!(form.response_format, format);
+            assert_eq!(form.model.as_deref(), Some("whisper-1"));
+            assert_eq!(form.audio_bytes, b"RIFF0000WAVE");
+            // octet-stream uploads take their type from the file name
+            assert_eq!(form.mime_type, "audio/wav");
+        }
+
+        let mut req = multipart_request(&[("response_format", "docx")], true);
+        let (_, error_response) = read_audio_form(&mut req).await.err().unwrap();
+        assert_eq!(
+            error_response.error.param.as_deref(),
+            Some("response_format")
+        );
+
+        let verbose = verbose_transcription(AudioTask::Transcribe, Some("de"), "Hallo");
+        assert_eq!(verbose["task"], "transcribe");
+        assert_eq!(verbose["language"], "de");
+        assert_eq!(verbose["text"], "Hallo");
+    }
+
+    #[test]
+    fn translation_prompt_asks_for_english() {
+        let instructions =
+            audio_instructions(AudioTask::Translate, "srt", Some("fr"), Some("Poe, Quora"));
+        assert!(
+            instructions
+                .starts_with("Translate the speech in the attached audio file into English.")
+        );
+        assert!(instructions.contains("The spoken language is fr."));
+        assert!(instructions.contains("Context and spelling hints: Poe, Quora"));
+        assert!(instructions.contains("SubRip (SRT)"));
+        assert_eq!(
+            verbose_transcription(AudioTask::Translate, Some("fr"), "Hello")["language"],
+            "english"
+        );
+    }
+
+    #[test]
+    fn reply_without_audio_is_a_bad_gateway() {
+        let mut ctx = EventContext {
+            content: "Sorry, I can only generate text.".to_string(),
+            ..Default::default()
+        };
+        let (status, error_response) = audio_url_from_reply(&mut ctx).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(error_response.error.code, "no_audio_generated");
+
+        let mut ctx = EventContext {
+            content: "Your audio: https://pfst.cf2.poecdn.net/base/audio/speech.mp3".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            audio_url_from_reply(&mut ctx).unwrap(),
+            "https://pfst.cf2.poecdn.net/base/audio/speech.mp3"
+        );
+
+        let mut ctx = EventContext {
+            content: "See http://169.254.169.254/latest/meta-data and https://pfst.cf2.poecdn.net.evil.com/a.mp3"
+                .to_string(),
+            ..Default::default()
+        };
+        assert!(audio_url_from_reply(&mut ctx).is_err());
+    }
+}
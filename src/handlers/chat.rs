@@ -1,11 +1,20 @@
+use super::limit::UsageMeter;
 use crate::cache::get_cached_config;
-use crate::evert::{EventContext, EventHandlerManager};
+use crate::evert::{
+    EventContext, EventHandlerManager, EventStream, OutputLimit, StopMatcher, collect_events,
+    finalize_content, first_tool_call_only, replace_file_references,
+};
+use crate::keys::resolve_access_key;
 use crate::poe_client::{PoeClientWrapper, create_chat_request};
+use crate::policy::CredentialPolicy;
+use crate::pool::{self, POOL_ACCESS_KEY};
 use crate::types::*;
 use crate::utils::{
-    convert_poe_error_to_openai, count_completion_tokens, count_message_tokens,
-    format_bytes_length, format_duration, pretty_json_truncated, process_message_images,
-    redact_headers, redact_json_fields, validate_tool_sequence,
+    UnsupportedContentError, append_instructions_to_last_user_message, convert_poe_error_to_openai,
+    count_completion_tokens, count_message_tokens, format_bytes_length, format_duration,
+    parse_response_format, parse_tool_choice, pretty_json_truncated, process_message_images,
+    redact_headers, redact_json_fields, response_format_instructions, tool_choice_instructions,
+    truncate_to_tokens, validate_structured_output, validate_tool_sequence,
 };
 use chrono::Utc;
 use futures_util::future::{self};
@@ -16,14 +25,16 @@ use poe_api_process::{ChatEventType, ChatResponse, PoeError};
 use salvo::http::header;
 use salvo::prelude::*;
 use serde_json::json;
-use std::collections::HashMap;
+use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tracing::{debug, error, info, warn};
 
 #[handler]
-pub async fn chat_completions(req: &mut Request, res: &mut Response) {
+pub async fn chat_completions(req: &mut Request, depot: &mut Depot, res: &mut Response) {
     let start_time = Instant::now();
 
     // Structure request/response logging with separator
@@ -53,23 +64,12 @@ pub async fn chat_completions(req: &mut Request, res: &mut Response) {
     );
 
     // Validate authorization
-    let access_key = match req.headers().get("Authorization") {
-        Some(auth) => {
-            let auth_str = auth.to_str().unwrap_or("");
-            if let Some(stripped) = auth_str.strip_prefix("Bearer ") {
-                debug!("🔑 Validated token length: {}", stripped.len());
-                stripped.to_string()
-            } else {
-                error!("❌ Invalid authorization format");
-                res.status_code(StatusCode::UNAUTHORIZED);
-                res.render(Json(json!({ "error": "Invalid Authorization" })));
-                return;
-            }
-        }
-        None => {
-            error!("❌ Missing authorization header");
+    let access_key = match extract_access_key(req) {
+        Ok(key) => key,
+        Err(message) => {
+            error!("❌ {}", message);
             res.status_code(StatusCode::UNAUTHORIZED);
-            res.render(Json(json!({ "error": "Missing Authorization" })));
+            res.render(Json(json!({ "error": message })));
             return;
         }
     };
@@ -128,68 +128,301 @@ pub async fn chat_completions(req: &mut Request, res: &mut Response) {
         }
     };
 
-    // Find mapped original model name
-    let (display_model, original_model) = if config.enable.unwrap_or(false) {
-        let requested_model = chat_request.model.clone();
-        // Check if current request model is a mapping target
-        let mapping_entry = config.models.iter().find(|(_, cfg)| {
-            if let Some(mapping) = &cfg.mapping {
-                mapping.to_lowercase() == requested_model.to_lowercase()
-            } else {
-                false
-            }
-        });
-        if let Some((original_name, _)) = mapping_entry {
-            // If mapping found, use original model name
-            debug!(
-                "🔄 Reverse model mapping: {} -> {}",
-                requested_model, original_name
-            );
-            (requested_model, original_name.clone())
-        } else {
-            // If no mapping found, check for direct mapping configuration
-            if let Some(model_config) = config.models.get(&requested_model) {
-                if let Some(mapped_name) = &model_config.mapping {
-                    debug!(
-                        "🔄 Direct model mapping: {} -> {}",
-                        requested_model, mapped_name
-                    );
-                    (requested_model.clone(), requested_model)
-                } else {
-                    // No mapping configuration, use original name
-                    (requested_model.clone(), requested_model)
-                }
+    let stream = chat_request.stream.unwrap_or(false);
+    debug!(
+        "🔄 Request mode: {}",
+        if stream { "streaming" } else { "non-streaming" }
+    );
+
+    // Check if usage statistics should be included
+    let include_usage = chat_request
+        .stream_options
+        .as_ref()
+        .and_then(|opts| opts.include_usage)
+        .unwrap_or(false);
+    debug!("📊 Include usage statistics: {}", include_usage);
+
+    // Output settings shared by every choice, the rest is filled in once the chat starts
+    let mut base = OutputGenerator::new(String::new(), 0, include_usage);
+    base.stop = chat_request.stop.clone().unwrap_or_default();
+    base.usage_meter = depot.obtain::<UsageMeter>().cloned().unwrap_or_default();
+
+    let n = match check_choice_count(chat_request.n) {
+        Ok(n) => n,
+        Err(error_response) => {
+            error!("❌ Invalid n: {:?}", chat_request.n);
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(error_response));
+            return;
+        }
+    };
+    let policy = depot.obtain::<CredentialPolicy>().ok();
+    if n > 1 {
+        handle_multi_choice(
+            res,
+            config,
+            access_key,
+            chat_request,
+            policy.cloned(),
+            n,
+            base,
+        )
+        .await;
+        let duration = start_time.elapsed();
+        info!(
+            "✅ Request processing completed | Choices: {} | Duration: {}",
+            n,
+            format_duration(duration)
+        );
+        return;
+    }
+
+    match start_chat(&config, &access_key, &chat_request, policy).await {
+        Ok(started) => {
+            // Create output generator
+            let mut output_generator = base;
+            output_generator.model = started.display_model;
+            output_generator.prompt_tokens = started.prompt_tokens;
+            output_generator.max_tokens = started.max_tokens;
+            output_generator.refusal = started.refusal;
+
+            if stream {
+                handle_stream_response(res, started.event_stream, output_generator).await;
             } else {
-                // No related configuration at all, use original name
-                (requested_model.clone(), requested_model)
+                handle_non_stream_response(res, started.event_stream, output_generator).await;
             }
         }
+        Err((status, error_response)) => {
+            res.status_code(status);
+            res.render(Json(error_response));
+        }
+    }
+
+    let duration = start_time.elapsed();
+    info!(
+        "✅ Request processing completed | Duration: {}",
+        format_duration(duration)
+    );
+}
+
+/// Credential the client presented, read in the order its endpoint accepts them: Anthropic prefers
+/// `x-api-key`, Gemini `x-goog-api-key` and `?key=`, and every endpoint takes `Authorization: Bearer`.
+/// Authentication, rate limits, usage and policies all go through here so they agree on the client.
+pub(crate) fn presented_credential(req: &Request) -> Option<String> {
+    let header_value = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .map(|v| v.to_string())
+    };
+    let path = req.uri().path();
+    let api_key = if path == "/v1/messages" {
+        header_value("x-api-key")
+    } else if path.starts_with("/v1beta/models/") {
+        header_value("x-goog-api-key")
+            .or_else(|| req.query::<String>("key").filter(|v| !v.is_empty()))
     } else {
+        None
+    };
+    api_key.or_else(|| {
+        header_value("Authorization")
+            .and_then(|auth| auth.strip_prefix("Bearer ").map(|v| v.to_string()))
+    })
+}
+
+/// Resolve the presented credential into the Poe token
+pub(crate) fn extract_access_key(req: &Request) -> Result<String, &'static str> {
+    match presented_credential(req) {
+        Some(credential) => {
+            debug!("🔑 Validated token length: {}", credential.len());
+            resolve_access_key(&credential)
+        }
+        None if req.headers().contains_key("Authorization") => Err("Invalid Authorization"),
+        None => Err("Missing Authorization"),
+    }
+}
+
+/// Resolve the requested model through models.yaml, returning (display_model, original_model)
+pub(crate) fn resolve_model(config: &Config, requested_model: &str) -> (String, String) {
+    if !config.enable.unwrap_or(false) {
         // Configuration not enabled, use original name directly
-        (chat_request.model.clone(), chat_request.model.clone())
+        return (requested_model.to_string(), requested_model.to_string());
+    }
+
+    // Check if current request model is a mapping target
+    let mapping_entry = config.models.iter().find(|(_, cfg)| {
+        if let Some(mapping) = &cfg.mapping {
+            mapping.to_lowercase() == requested_model.to_lowercase()
+        } else {
+            false
+        }
+    });
+    if let Some((original_name, _)) = mapping_entry {
+        // If mapping found, use original model name
+        debug!(
+            "🔄 Reverse model mapping: {} -> {}",
+            requested_model, original_name
+        );
+        return (requested_model.to_string(), original_name.clone());
+    }
+
+    // If no mapping found, check for direct mapping configuration
+    if let Some(mapped_name) = config
+        .models
+        .get(requested_model)
+        .and_then(|model_config| model_config.mapping.as_ref())
+    {
+        debug!(
+            "🔄 Direct model mapping: {} -> {}",
+            requested_model, mapped_name
+        );
+    }
+    (requested_model.to_string(), requested_model.to_string())
+}
+
+/// An established Poe event stream plus the metadata needed to render it
+pub(crate) struct StartedChat {
+    pub display_model: String,
+    pub prompt_tokens: u32,
+    pub event_stream: EventStream,
+    // Set when structured output failed validation and STRUCTURED_OUTPUT_FAILURE=refusal
+    pub refusal: Option<String>,
+    // Output token limit after the credential policy filled in its default
+    pub max_tokens: Option<u32>,
+}
+
+impl StartedChat {
+    /// Stop sequences and output cap for the adapters that render the event stream themselves
+    pub fn output_limit(&self, chat_request: &ChatCompletionRequest) -> OutputLimit {
+        OutputLimit::new(
+            chat_request.stop.as_deref().unwrap_or_default(),
+            self.max_tokens,
+        )
+    }
+}
+
+/// Shared chat pipeline: credential policy, model mapping, file uploads, tool validation and request dispatch
+pub(crate) async fn start_chat(
+    config: &Config,
+    access_key: &str,
+    chat_request: &ChatCompletionRequest,
+    policy: Option<&CredentialPolicy>,
+) -> Result<StartedChat, (StatusCode, OpenAIErrorResponse)> {
+    // Model rules, parameter defaults and limits of the credential's policy, for every endpoint
+    let policed_request;
+    let chat_request = match policy {
+        Some(policy) => {
+            let mut request = chat_request.clone();
+            let (_, original_model) = resolve_model(config, &request.model);
+            policy
+                .check_model(&request.model, &original_model)
+                .and_then(|()| policy.apply_to_request(&mut request))
+                .map_err(|violation| {
+                    error!("❌ Request rejected by policy: {}", violation.message);
+                    violation.into_response()
+                })?;
+            policed_request = request;
+            &policed_request
+        }
+        None => chat_request,
     };
+
+    let response_format =
+        parse_response_format(chat_request.response_format.as_ref()).map_err(|message| {
+            error!("❌ Invalid response_format: {}", message);
+            (
+                StatusCode::BAD_REQUEST,
+                OpenAIErrorResponse {
+                    error: OpenAIError {
+                        message,
+                        r#type: "invalid_request_error".to_string(),
+                        code: "invalid_response_format".to_string(),
+                        param: Some("response_format".to_string()),
+                    },
+                },
+            )
+        })?;
+
+    let tool_choice = parse_tool_choice(
+        chat_request.tool_choice.as_ref(),
+        chat_request.tools.as_deref(),
+    )
+    .map_err(|message| {
+        error!("❌ Invalid tool_choice: {}", message);
+        (
+            StatusCode::BAD_REQUEST,
+            OpenAIErrorResponse {
+                error: OpenAIError {
+                    message,
+                    r#type: "invalid_request_error".to_string(),
+                    code: "invalid_tool_choice".to_string(),
+                    param: Some("tool_choice".to_string()),
+                },
+            },
+        )
+    })?;
+
+    // Find mapped original model name
+    let (display_model, original_model) = resolve_model(config, &chat_request.model);
     info!(
         "🤖 Using model: {} (original: {})",
         display_model, original_model
     );
 
-    // Create client
-    let client = PoeClientWrapper::new(&original_model, &access_key);
+    // Proxy keys without their own Poe token are served from the token pool
+    let upstream = if access_key == POOL_ACCESS_KEY {
+        Upstream::Pool(config)
+    } else {
+        Upstream::Token(access_key)
+    };
+
+    // Create client (file uploads use the first available pool token)
+    let upload_key = match &upstream {
+        Upstream::Token(token) => token.to_string(),
+        Upstream::Pool(config) => pool::upload_token(config).ok_or_else(pool_exhausted)?,
+    };
+    let client = PoeClientWrapper::new(&original_model, &upload_key);
 
     // Process image_url in messages
     let mut messages = chat_request.messages.clone();
     if let Err(e) = process_message_images(&client, &mut messages).await {
+        if let Some(unsupported) = e.downcast_ref::<UnsupportedContentError>() {
+            error!("❌ Unsupported content part: {}", unsupported);
+            return Err((
+                StatusCode::BAD_REQUEST,
+                OpenAIErrorResponse {
+                    error: OpenAIError {
+                        message: unsupported.to_string(),
+                        r#type: "invalid_request_error".to_string(),
+                        code: "unsupported_content".to_string(),
+                        param: Some("messages".to_string()),
+                    },
+                },
+            ));
+        }
         error!("❌ File upload processing failed: {}", e);
-        res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-        res.render(Json(OpenAIErrorResponse {
-            error: OpenAIError {
-                message: format!("File upload processing failed: {}", e),
-                r#type: "processing_error".to_string(),
-                code: "file_processing_failed".to_string(),
-                param: None,
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            OpenAIErrorResponse {
+                error: OpenAIError {
+                    message: format!("File upload processing failed: {}", e),
+                    r#type: "processing_error".to_string(),
+                    code: "file_processing_failed".to_string(),
+                    param: None,
+                },
             },
-        }));
-        return;
+        ));
+    }
+
+    // A mandatory tool call takes precedence, JSON output only applies to text replies
+    let reply_check = match (tool_choice_instructions(&tool_choice), &response_format) {
+        (Some(instructions), _) => Some(ReplyCheck::ToolCall(instructions)),
+        (None, Some(format)) => Some(ReplyCheck::Format(format)),
+        (None, None) => None,
+    };
+    if let Some(check) = &reply_check {
+        append_instructions_to_last_user_message(&mut messages, check.instructions());
     }
 
     // Calculate prompt_tokens
@@ -199,109 +432,598 @@ pub async fn chat_completions(req: &mut Request, res: &mut Response) {
     // Validate tool message sequence before processing
     if let Err(validation_error) = validate_tool_sequence(&messages) {
         error!("❌ Tool message validation failed: {}", validation_error);
-        res.status_code(StatusCode::BAD_REQUEST);
-        res.render(Json(OpenAIErrorResponse {
-            error: OpenAIError {
-                message: format!("Tool calling validation failed: {}", validation_error),
-                r#type: "invalid_request_error".to_string(),
-                code: "invalid_tool_sequence".to_string(),
-                param: Some("messages".to_string()),
+        return Err((
+            StatusCode::BAD_REQUEST,
+            OpenAIErrorResponse {
+                error: OpenAIError {
+                    message: format!("Tool calling validation failed: {}", validation_error),
+                    r#type: "invalid_request_error".to_string(),
+                    code: "invalid_tool_sequence".to_string(),
+                    param: Some("messages".to_string()),
+                },
             },
-        }));
-        return;
+        ));
     }
 
-    let stream = chat_request.stream.unwrap_or(false);
-    debug!(
-        "🔄 Request mode: {}",
-        if stream { "streaming" } else { "non-streaming" }
-    );
+    if let Some(check) = &reply_check {
+        let (event_stream, refusal) =
+            run_checked_chat(&upstream, &original_model, messages, chat_request, check).await?;
+        return Ok(StartedChat {
+            display_model,
+            prompt_tokens,
+            event_stream,
+            refusal,
+            max_tokens: chat_request.output_token_limit(),
+        });
+    }
 
-    // Create chat request
-    let chat_request_obj = create_chat_request(&original_model, messages, &chat_request).await;
+    let event_stream =
+        send_chat_request(&upstream, &original_model, messages, chat_request).await?;
+    Ok(StartedChat {
+        display_model,
+        prompt_tokens,
+        event_stream,
+        refusal: None,
+        max_tokens: chat_request.output_token_limit(),
+    })
+}
 
-    // Check if usage statistics should be included
-    let include_usage = chat_request
-        .stream_options
-        .as_ref()
-        .and_then(|opts| opts.include_usage)
-        .unwrap_or(false);
-    debug!("📊 Include usage statistics: {}", include_usage);
+// Upstream Poe credentials of a request: the caller's own token or the shared token pool
+enum Upstream<'a> {
+    Token(&'a str),
+    Pool(&'a Config),
+}
+
+// Send the chat request upstream and wait for its first event
+async fn send_chat_request(
+    upstream: &Upstream<'_>,
+    original_model: &str,
+    messages: Vec<Message>,
+    chat_request: &ChatCompletionRequest,
+) -> Result<EventStream, (StatusCode, OpenAIErrorResponse)> {
+    let event_stream = match upstream {
+        Upstream::Token(token) => {
+            let client = PoeClientWrapper::new(original_model, token);
+            send_to_poe(&client, original_model, messages, chat_request).await?
+        }
+        Upstream::Pool(config) => {
+            send_via_pool(config, original_model, messages, chat_request).await?
+        }
+    };
+    if chat_request.parallel_tool_calls == Some(false) {
+        return Ok(first_tool_call_only(event_stream));
+    }
+    Ok(event_stream)
+}
+
+// Try pool tokens in selection order, failing over to the next one when a token is out of points
+async fn send_via_pool(
+    config: &Config,
+    original_model: &str,
+    messages: Vec<Message>,
+    chat_request: &ChatCompletionRequest,
+) -> Result<EventStream, (StatusCode, OpenAIErrorResponse)> {
+    for token in pool::candidates(config) {
+        debug!("🎱 Using pool token | Name: {}", token.name);
+        pool::record_use(&token);
+        let client = PoeClientWrapper::new(original_model, &token.token);
+        match send_to_poe(&client, original_model, messages.clone(), chat_request).await {
+            Err((_, error_response)) if error_response.error.code == "insufficient_quota" => {
+                pool::mark_exhausted(config, &token);
+            }
+            result => return result,
+        }
+    }
+    Err(pool_exhausted())
+}
 
-    // Create output generator
-    let output_generator =
-        OutputGenerator::new(display_model.clone(), prompt_tokens, include_usage);
+// Every pool token is out of points or cooling down
+fn pool_exhausted() -> (StatusCode, OpenAIErrorResponse) {
+    error!("❌ No upstream Poe token with remaining points in the pool");
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        OpenAIErrorResponse {
+            error: OpenAIError {
+                message: "All upstream Poe tokens are out of points or cooling down. Please try again later.".to_string(),
+                r#type: "insufficient_quota".to_string(),
+                code: "insufficient_quota".to_string(),
+                param: None,
+            },
+        },
+    )
+}
+
+// Create the Poe request and wait for its first event
+async fn send_to_poe(
+    client: &PoeClientWrapper,
+    original_model: &str,
+    messages: Vec<Message>,
+    chat_request: &ChatCompletionRequest,
+) -> Result<EventStream, (StatusCode, OpenAIErrorResponse)> {
+    // Create chat request
+    let chat_request_obj = create_chat_request(original_model, messages, chat_request).await;
 
     match client.stream_request(chat_request_obj).await {
-        Ok(mut event_stream) => {
-            let first_event = event_stream.next().await;
-
-            if let Some(Ok(ChatResponse {
-                event: ChatEventType::Error,
-                data: Some(ChatResponseData::Error { text, allow_retry }),
-            })) = &first_event
-            {
-                let insufficient_points_msg_1 =
-                    "This bot needs more points to answer your request.";
-                let insufficient_points_msg_2 =
-                    "You do not have enough points to message this bot.";
-
-                if text.contains(insufficient_points_msg_1)
-                    || text.contains(insufficient_points_msg_2)
-                {
-                    info!("🚫 Detected Poe points insufficient error, returning 429 status code.");
-                    let status = StatusCode::TOO_MANY_REQUESTS;
-                    let body = OpenAIErrorResponse {
-                        error: OpenAIError {
-                            message: "You have exceeded your message quota for this model. Please try again later.".to_string(),
-                            r#type: "insufficient_quota".to_string(),
-                            code: "insufficient_quota".to_string(),
-                            param: None,
-                        },
-                    };
-                    res.status_code(status);
-                    res.render(Json(body));
-                    return;
-                } else {
-                    let (status, body) = convert_poe_error_to_openai(text, *allow_retry);
-                    res.status_code(status);
-                    res.render(Json(body));
-                    return;
-                }
+        Ok(event_stream) => peek_first_event(event_stream).await,
+        Err(e) => {
+            error!("❌ Failed to create streaming request: {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                OpenAIErrorResponse {
+                    error: OpenAIError {
+                        message: e.to_string(),
+                        r#type: "internal_error".to_string(),
+                        code: "internal_error".to_string(),
+                        param: None,
+                    },
+                },
+            ))
+        }
+    }
+}
+
+// Requirement a buffered reply must meet before it is returned to the client
+enum ReplyCheck<'a> {
+    // tool_choice is "required" or a named function, holds the prompt instructions
+    ToolCall(String),
+    Format(&'a ResponseFormat),
+}
+
+impl ReplyCheck<'_> {
+    fn instructions(&self) -> String {
+        match self {
+            ReplyCheck::ToolCall(instructions) => instructions.clone(),
+            ReplyCheck::Format(format) => response_format_instructions(format),
+        }
+    }
+
+    fn max_retries(&self) -> usize {
+        let (key, default) = match self {
+            ReplyCheck::ToolCall(_) => ("TOOL_CHOICE_RETRIES", 1),
+            ReplyCheck::Format(_) => ("STRUCTURED_OUTPUT_RETRIES", 2),
+        };
+        std::env::var(key)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    }
+}
+
+/// Buffer the bot reply, check it against tool_choice / response_format and re-prompt on failure.
+/// Returns a replayable event stream, plus a refusal when validation never succeeded.
+async fn run_checked_chat(
+    upstream: &Upstream<'_>,
+    original_model: &str,
+    mut messages: Vec<Message>,
+    chat_request: &ChatCompletionRequest,
+    check: &ReplyCheck<'_>,
+) -> Result<(EventStream, Option<String>), (StatusCode, OpenAIErrorResponse)> {
+    let max_retries = check.max_retries();
+    let mut last_error = String::new();
+
+    for attempt in 0..=max_retries {
+        let event_stream =
+            send_chat_request(upstream, original_model, messages.clone(), chat_request).await?;
+        let events: Vec<Result<ChatResponse, PoeError>> = event_stream.collect().await;
+
+        // Run the buffered events through a fresh context to inspect the reply
+        let handler_manager = EventHandlerManager::new();
+        let mut ctx = EventContext::default();
+        for event in events.iter().flatten() {
+            handler_manager.handle(event, &mut ctx);
+            if ctx.done || ctx.error.is_some() {
+                break;
             }
+        }
+        // Errors and tool calls are passed through untouched
+        if ctx.error.is_some() || !ctx.tool_calls.is_empty() || events.iter().any(|e| e.is_err()) {
+            return Ok((Box::pin(stream::iter(events)), None));
+        }
 
-            let reconstituted_stream: Pin<
-                Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>,
-            > = if let Some(first) = first_event {
-                Box::pin(stream::once(async { first }).chain(event_stream))
-            } else {
-                Box::pin(stream::empty())
-            };
+        let content = finalize_content(&mut ctx);
+        let failure = match check {
+            ReplyCheck::ToolCall(_) => {
+                "You replied in plain text, but a tool call is required.".to_string()
+            }
+            ReplyCheck::Format(format) => match validate_structured_output(&content, format) {
+                Ok(json_text) => {
+                    debug!("🧩 Structured output validated | Attempt: {}", attempt + 1);
+                    return Ok((replay_text_events(json_text), None));
+                }
+                Err(e) => e,
+            },
+        };
+        warn!(
+            "⚠️ Reply check failed | Attempt: {}/{} | {}",
+            attempt + 1,
+            max_retries + 1,
+            failure
+        );
+        messages.push(Message {
+            role: "assistant".to_string(),
+            content: Some(OpenAiContent::Text(content)),
+            ..Default::default()
+        });
+        messages.push(Message {
+            role: "user".to_string(),
+            content: Some(OpenAiContent::Text(format!(
+                "Your previous reply was invalid: {}\n\n{}",
+                failure,
+                check.instructions()
+            ))),
+            ..Default::default()
+        });
+        last_error = failure;
+    }
 
-            if stream {
-                handle_stream_response(res, reconstituted_stream, output_generator).await;
-            } else {
-                handle_non_stream_response(res, reconstituted_stream, output_generator).await;
+    match check {
+        ReplyCheck::ToolCall(_) => {
+            let message = format!(
+                "The model did not call a tool after {} attempt(s) although tool_choice requires it",
+                max_retries + 1
+            );
+            error!("❌ {}", message);
+            Err((
+                StatusCode::BAD_GATEWAY,
+                OpenAIErrorResponse {
+                    error: OpenAIError {
+                        message,
+                        r#type: "api_error".to_string(),
+                        code: "tool_call_required".to_string(),
+                        param: Some("tool_choice".to_string()),
+                    },
+                },
+            ))
+        }
+        ReplyCheck::Format(_) => {
+            let message = format!(
+                "Model output did not match response_format after {} attempt(s): {}",
+                max_retries + 1,
+                last_error
+            );
+            error!("❌ {}", message);
+            let on_failure = std::env::var("STRUCTURED_OUTPUT_FAILURE").unwrap_or_default();
+            if on_failure.eq_ignore_ascii_case("refusal") {
+                return Ok((replay_text_events(String::new()), Some(message)));
             }
+            Err((
+                StatusCode::BAD_GATEWAY,
+                OpenAIErrorResponse {
+                    error: OpenAIError {
+                        message,
+                        r#type: "api_error".to_string(),
+                        code: "invalid_structured_output".to_string(),
+                        param: Some("response_format".to_string()),
+                    },
+                },
+            ))
         }
-        Err(e) => {
-            error!("❌ Failed to create streaming request: {}", e);
-            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-            res.render(Json(json!({ "error": e.to_string() })));
+    }
+}
+
+// Event stream that yields the given text followed by completion
+fn replay_text_events(text: String) -> EventStream {
+    let mut events = Vec::new();
+    if !text.is_empty() {
+        events.push(Ok(ChatResponse {
+            event: ChatEventType::Text,
+            data: Some(ChatResponseData::Text { text }),
+        }));
+    }
+    events.push(Ok(ChatResponse {
+        event: ChatEventType::Done,
+        data: None,
+    }));
+    Box::pin(stream::iter(events))
+}
+
+/// Read the first event so that quota and bot errors become proper HTTP errors
+async fn peek_first_event(
+    mut event_stream: EventStream,
+) -> Result<EventStream, (StatusCode, OpenAIErrorResponse)> {
+    let first_event = event_stream.next().await;
+
+    if let Some(Ok(ChatResponse {
+        event: ChatEventType::Error,
+        data: Some(ChatResponseData::Error { text, allow_retry }),
+    })) = &first_event
+    {
+        let insufficient_points_msg_1 = "This bot needs more points to answer your request.";
+        let insufficient_points_msg_2 = "You do not have enough points to message this bot.";
+
+        if text.contains(insufficient_points_msg_1) || text.contains(insufficient_points_msg_2) {
+            info!("🚫 Detected Poe points insufficient error, returning 429 status code.");
+            return Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                OpenAIErrorResponse {
+                    error: OpenAIError {
+                        message: "You have exceeded your message quota for this model. Please try again later.".to_string(),
+                        r#type: "insufficient_quota".to_string(),
+                        code: "insufficient_quota".to_string(),
+                        param: None,
+                    },
+                },
+            ));
+        } else {
+            return Err(convert_poe_error_to_openai(text, *allow_retry));
         }
     }
 
-    let duration = start_time.elapsed();
+    let reconstituted_stream: EventStream = if let Some(first) = first_event {
+        Box::pin(stream::once(async { first }).chain(event_stream))
+    } else {
+        Box::pin(stream::empty())
+    };
+    Ok(reconstituted_stream)
+}
+
+// Upper bound for n, matching the OpenAI API
+const MAX_CHOICES: u32 = 128;
+
+// Validate n, defaulting to a single choice
+fn check_choice_count(n: Option<u32>) -> Result<u32, OpenAIErrorResponse> {
+    let n = n.unwrap_or(1);
+    if (1..=MAX_CHOICES).contains(&n) {
+        return Ok(n);
+    }
+    Err(OpenAIErrorResponse {
+        error: OpenAIError {
+            message: format!("n must be between 1 and {}", MAX_CHOICES),
+            r#type: "invalid_request_error".to_string(),
+            code: "invalid_value".to_string(),
+            param: Some("n".to_string()),
+        },
+    })
+}
+
+// Number of Poe requests a single n > 1 request may run at once
+fn choices_concurrency() -> usize {
+    std::env::var("MAX_CHOICES_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(4)
+}
+
+type ChoiceStart = Pin<
+    Box<
+        dyn Future<
+                Output = Result<
+                    (StartedChat, OwnedSemaphorePermit),
+                    (StatusCode, OpenAIErrorResponse),
+                >,
+            > + Send,
+    >,
+>;
+
+// Fan n > 1 out into concurrent Poe requests, one EventContext per choice.
+// A choice that fails fails the whole request: non-streaming requests return the error,
+// streaming requests send the error chunk and end the stream once the response has started.
+async fn handle_multi_choice(
+    res: &mut Response,
+    config: Arc<Config>,
+    access_key: String,
+    chat_request: ChatCompletionRequest,
+    policy: Option<CredentialPolicy>,
+    n: u32,
+    mut base: OutputGenerator,
+) {
+    let limit = choices_concurrency();
     info!(
-        "✅ Request processing completed | Duration: {}",
-        format_duration(duration)
+        "🔀 Fanning out request | Choices: {} | Concurrency: {}",
+        n, limit
+    );
+    let stream = chat_request.stream.unwrap_or(false);
+    let chat_request = Arc::new(chat_request);
+    let policy = policy.map(Arc::new);
+    let semaphore = Arc::new(Semaphore::new(limit));
+    let counter = Arc::new(AtomicU32::new(0));
+
+    // Each choice holds a permit until its events are consumed
+    let start_choice = |semaphore: Arc<Semaphore>| -> ChoiceStart {
+        let config = Arc::clone(&config);
+        let access_key = access_key.clone();
+        let chat_request = Arc::clone(&chat_request);
+        let policy = policy.clone();
+        Box::pin(async move {
+            let permit = semaphore.acquire_owned().await.unwrap();
+            start_chat(&config, &access_key, &chat_request, policy.as_deref())
+                .await
+                .map(|started| (started, permit))
+        })
+    };
+
+    // Start the first batch up front so that auth and quota errors become HTTP errors
+    let eager = (n as usize).min(limit);
+    let started = future::join_all((0..eager).map(|_| start_choice(Arc::clone(&semaphore)))).await;
+    let mut starts: Vec<ChoiceStart> = Vec::with_capacity(n as usize);
+    let mut model_info = None;
+    for result in started {
+        match result {
+            Ok((started, permit)) => {
+                model_info.get_or_insert((
+                    started.display_model.clone(),
+                    started.prompt_tokens,
+                    started.max_tokens,
+                ));
+                starts.push(Box::pin(future::ready(Ok((started, permit)))));
+            }
+            Err((status, error_response)) => {
+                res.status_code(status);
+                res.render(Json(error_response));
+                return;
+            }
+        }
+    }
+    for _ in eager..n as usize {
+        starts.push(start_choice(Arc::clone(&semaphore)));
+    }
+    let (model, prompt_tokens, max_tokens) = model_info.unwrap_or_default();
+    base.model = model;
+    base.prompt_tokens = prompt_tokens;
+    base.max_tokens = max_tokens;
+
+    if stream {
+        handle_multi_stream_response(res, starts, base, counter);
+    } else {
+        handle_multi_non_stream_response(res, starts, base, counter).await;
+    }
+}
+
+// Interleave the choices' chunks, then send aggregated usage and [DONE]
+fn handle_multi_stream_response(
+    res: &mut Response,
+    starts: Vec<ChoiceStart>,
+    base: OutputGenerator,
+    counter: Arc<AtomicU32>,
+) {
+    res.headers_mut()
+        .insert(header::CONTENT_TYPE, "text/event-stream".parse().unwrap());
+    res.headers_mut()
+        .insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
+    res.headers_mut()
+        .insert(header::CONNECTION, "keep-alive".parse().unwrap());
+
+    let failed = Arc::new(AtomicBool::new(false));
+    let choices =
+        starts.into_iter().enumerate().map(|(index, start)| {
+            let generator = base.for_choice(index as u32, &counter);
+            let failed = Arc::clone(&failed);
+            stream::once(start)
+                .map(move |result| -> ChoiceStream {
+                    match result {
+                        Ok((started, permit)) => {
+                            let mut generator = generator.clone();
+                            generator.refusal = started.refusal;
+                            Box::pin(generator.process_events(started.event_stream).map(
+                                move |item| {
+                                    let _ = &permit;
+                                    (item, false)
+                                },
+                            ))
+                        }
+                        Err((_, error_response)) => {
+                            error!(
+                                "❌ Choice {} failed to start: {}",
+                                index, error_response.error.message
+                            );
+                            failed.store(true, Ordering::Relaxed);
+                            let error_json = serde_json::to_string(&error_response).unwrap();
+                            Box::pin(stream::once(future::ready((
+                                Ok(format!("data: {}\n\n", error_json)),
+                                true,
+                            ))))
+                        }
+                    }
+                })
+                .flatten()
+        });
+
+    // The error chunk of a failed choice ends the stream, the other choices are dropped
+    let body = stream::select_all(choices.map(Box::pin)).scan(false, |stopped, (item, fatal)| {
+        let next = (!*stopped).then_some(item);
+        *stopped |= fatal;
+        future::ready(next)
+    });
+
+    // Usage for all choices goes into one trailing chunk with empty choices
+    let trailer = stream::once(async move {
+        let mut output = String::new();
+        if base.include_usage && !failed.load(Ordering::Relaxed) {
+            let completion_tokens = counter.load(Ordering::Relaxed);
+            let usage_chunk = json!({
+                "id": format!("chatcmpl-{}", base.id),
+                "object": "chat.completion.chunk",
+                "created": base.created,
+                "model": base.model,
+                "choices": [],
+                "usage": usage_json(base.prompt_tokens, completion_tokens)
+            });
+            output.push_str(&format!("data: {}\n\n", usage_chunk));
+        }
+        output.push_str("data: [DONE]\n\n");
+        Ok::<String, std::convert::Infallible>(output)
+    });
+    res.stream(body.chain(trailer));
+}
+
+// Collect every choice into its own EventContext and merge them into one response
+async fn handle_multi_non_stream_response(
+    res: &mut Response,
+    starts: Vec<ChoiceStart>,
+    base: OutputGenerator,
+    counter: Arc<AtomicU32>,
+) {
+    let results = future::join_all(starts.into_iter().map(|start| async move {
+        let (started, _permit) = start.await?;
+        let ctx = collect_events(started.event_stream).await?;
+        Ok((ctx, started.refusal))
+    }))
+    .await;
+
+    let mut choices = Vec::with_capacity(results.len());
+    for (index, result) in results.into_iter().enumerate() {
+        match result {
+            Ok((mut ctx, refusal)) => {
+                let mut generator = base.for_choice(index as u32, &counter);
+                generator.refusal = refusal;
+                let response = generator.create_final_response(&mut ctx);
+                choices.extend(response.choices);
+            }
+            Err((status, error_response)) => {
+                res.status_code(status);
+                res.render(Json(error_response));
+                return;
+            }
+        }
+    }
+
+    let completion_tokens = counter.load(Ordering::Relaxed);
+    debug!(
+        "📊 Aggregated token usage | Choices: {} | prompt_tokens: {} | completion_tokens: {}",
+        choices.len(),
+        base.prompt_tokens,
+        completion_tokens
     );
+    let response = ChatCompletionResponse {
+        id: format!("chatcmpl-{}", base.id),
+        object: "chat.completion".to_string(),
+        created: base.created,
+        model: base.model.clone(),
+        choices,
+        usage: base
+            .include_usage
+            .then(|| usage_json(base.prompt_tokens, completion_tokens)),
+    };
+
+    let response_value = serde_json::to_value(&response).unwrap_or_else(|_| json!(null));
+    debug!(
+        "📤 Response body (sanitized, truncated):\n{}",
+        pretty_json_truncated(&redact_json_fields(&response_value), 64 * 1024)
+    );
+    debug!("------ Outgoing Response [200] /v1/chat/completions ------");
+
+    res.render(Json(response));
+}
+
+// Prompt tokens are counted once, completion tokens are summed across choices
+fn usage_json(prompt_tokens: u32, completion_tokens: u32) -> serde_json::Value {
+    json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": prompt_tokens + completion_tokens,
+        "prompt_tokens_details": {"cached_tokens": 0}
+    })
 }
 
+// SSE chunks of one fanned-out choice, flagged true for the error chunk of a failed choice
+type ChoiceStream =
+    Pin<Box<dyn Stream<Item = (Result<String, std::convert::Infallible>, bool)> + Send>>;
+
 // Handle streaming response
 async fn handle_stream_response(
     res: &mut Response,
-    event_stream: Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>,
+    event_stream: EventStream,
     output_generator: OutputGenerator,
 ) {
     let start_time = Instant::now();
@@ -338,7 +1060,7 @@ async fn handle_stream_response(
 // Handle non-streaming response
 async fn handle_non_stream_response(
     res: &mut Response,
-    mut event_stream: Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>,
+    event_stream: EventStream,
     output_generator: OutputGenerator,
 ) {
     let start_time = Instant::now();
@@ -350,36 +1072,14 @@ async fn handle_non_stream_response(
         id, model, include_usage
     );
 
-    let handler_manager = EventHandlerManager::new();
-    let mut ctx = EventContext::default();
-
-    // Process all events
-    while let Some(result) = event_stream.next().await {
-        match result {
-            Ok(event) => {
-                handler_manager.handle(&event, &mut ctx);
-                // Check for errors
-                if let Some((status, error_response)) = &ctx.error {
-                    error!("❌ Processing error: {:?}", error_response);
-                    res.status_code(*status);
-                    res.render(Json(error_response));
-                    return;
-                }
-                // Check if completed
-                if ctx.done {
-                    debug!("✅ Received completion event");
-                    break;
-                }
-            }
-            Err(e) => {
-                error!("❌ Processing error: {}", e);
-                let (status, error_response) = convert_poe_error_to_openai(&e.to_string(), false);
-                res.status_code(status);
-                res.render(Json(error_response));
-                return;
-            }
+    let mut ctx = match collect_events(event_stream).await {
+        Ok(ctx) => ctx,
+        Err((status, error_response)) => {
+            res.status_code(status);
+            res.render(Json(error_response));
+            return;
         }
-    }
+    };
 
     // Create final response
     let response = output_generator.create_final_response(&mut ctx);
@@ -405,6 +1105,9 @@ async fn handle_non_stream_response(
     );
 }
 
+// Characters per streamed tool call arguments fragment
+const TOOL_ARGUMENT_FRAGMENT_CHARS: usize = 64;
+
 // Output generator - for converting EventContext to final output
 #[derive(Clone)]
 struct OutputGenerator {
@@ -413,6 +1116,18 @@ struct OutputGenerator {
     model: String,
     prompt_tokens: u32,
     include_usage: bool,
+    // Choice index when fanning out n > 1
+    index: u32,
+    // Shared completion token total across fanned-out choices
+    completion_counter: Option<Arc<AtomicU32>>,
+    // Structured output refusal reported instead of content
+    refusal: Option<String>,
+    // max_tokens / max_completion_tokens cap on streamed content
+    max_tokens: Option<u32>,
+    // Stop sequences enforced by the proxy, many Poe bots ignore stop_sequences
+    stop: Vec<String>,
+    // Records the reply's tokens for rate limits and usage budgets
+    usage_meter: UsageMeter,
 }
 
 impl OutputGenerator {
@@ -423,44 +1138,23 @@ impl OutputGenerator {
             model,
             prompt_tokens,
             include_usage,
+            index: 0,
+            completion_counter: None,
+            refusal: None,
+            max_tokens: None,
+            stop: Vec::new(),
+            usage_meter: UsageMeter::default(),
         }
     }
 
-    // Process file references, replacing [ref_id] with (url)
-    fn process_file_references(
-        &self,
-        content: &str,
-        file_refs: &HashMap<String, poe_api_process::types::FileData>,
-    ) -> String {
-        if file_refs.is_empty() {
-            return content.to_string();
-        }
-        let mut processed = content.to_string();
-        let mut has_replaced = false;
-
-        for (ref_id, file_data) in file_refs {
-            let img_marker = format!("[{}]", ref_id);
-            if processed.contains(&img_marker) {
-                let replacement = format!("({})", file_data.url);
-                processed = processed.replace(&img_marker, &replacement);
-                debug!(
-                    "🖼️ Replaced image reference | ID: {} | URL: {}",
-                    ref_id, file_data.url
-                );
-                has_replaced = true;
-            }
-        }
-
-        if has_replaced {
-            debug!("✅ Successfully replaced image references");
-        } else if processed.contains('[') && processed.contains(']') {
-            warn!(
-                "⚠️ Text contains potential image reference format, but no corresponding reference found: {}",
-                processed
-            );
+    // Generator for one fanned-out choice, usage is reported once for all choices
+    fn for_choice(&self, index: u32, counter: &Arc<AtomicU32>) -> Self {
+        Self {
+            index,
+            include_usage: false,
+            completion_counter: Some(Arc::clone(counter)),
+            ..self.clone()
         }
-
-        processed
     }
 
     // Calculate token usage
@@ -469,19 +1163,32 @@ impl OutputGenerator {
             Some(replace_content) => replace_content,
             None => &ctx.content,
         };
-        let completion_tokens = count_completion_tokens(content);
+        // Content cut at a stop sequence or max_tokens only counts what was sent
+        let (content, _) = StopMatcher::truncate(content, &self.stop);
+        let completion_tokens =
+            count_completion_tokens(&content).min(self.max_tokens.unwrap_or(u32::MAX));
         ctx.completion_tokens = completion_tokens;
+        // Fanned-out choices share one prompt, only the first choice records it
+        let recorded_prompt = if self.index == 0 {
+            self.prompt_tokens
+        } else {
+            0
+        };
+        self.usage_meter
+            .record(&self.model, recorded_prompt, completion_tokens);
+        if let Some(counter) = &self.completion_counter {
+            counter.fetch_add(completion_tokens, Ordering::Relaxed);
+        }
         let total_tokens = self.prompt_tokens + completion_tokens;
         (self.prompt_tokens, completion_tokens, total_tokens)
     }
 
-    // Create role chunk
     // Create role chunk
     fn create_role_chunk(&self) -> ChatCompletionChunk {
         let role_delta = Delta {
             role: Some("assistant".to_string()),
             content: None,
-            refusal: None,
+            refusal: self.refusal.clone().map(serde_json::Value::String),
             tool_calls: None,
             reasoning_content: None,
         };
@@ -491,7 +1198,7 @@ impl OutputGenerator {
             created: self.created,
             model: self.model.clone(),
             choices: vec![Choice {
-                index: 0,
+                index: self.index,
                 delta: role_delta,
                 finish_reason: None,
             }],
@@ -513,7 +1220,7 @@ impl OutputGenerator {
             created: self.created,
             model: self.model.clone(),
             choices: vec![Choice {
-                index: 0,
+                index: self.index,
                 delta: reasoning_delta,
                 finish_reason: None,
             }],
@@ -544,7 +1251,7 @@ impl OutputGenerator {
             created: self.created,
             model: self.model.clone(),
             choices: vec![Choice {
-                index: 0,
+                index: self.index,
                 delta,
                 finish_reason,
             }],
@@ -552,12 +1259,12 @@ impl OutputGenerator {
     }
 
     // Create tool call chunk
-    fn create_tool_calls_chunk(&self, tool_calls: &[ChunkToolCall]) -> ChatCompletionChunk {
+    fn create_tool_calls_chunk(&self, tool_calls: Vec<ToolCallDelta>) -> ChatCompletionChunk {
         let tool_delta = Delta {
             role: None,
             content: None,
             refusal: None,
-            tool_calls: Some(tool_calls.to_vec()),
+            tool_calls: Some(tool_calls),
             reasoning_content: None,
         };
         ChatCompletionChunk {
@@ -566,39 +1273,192 @@ impl OutputGenerator {
             created: self.created,
             model: self.model.clone(),
             choices: vec![Choice {
-                index: 0,
+                index: self.index,
                 delta: tool_delta,
                 finish_reason: None,
             }],
         }
     }
 
-    // Create final full response (non-streaming mode)
-    fn create_final_response(&self, ctx: &mut EventContext) -> ChatCompletionResponse {
-        // Process remaining pending_text
-        if !ctx.pending_text.trim().is_empty() {
-            use crate::evert::ThinkingProcessor;
-            let (reasoning_output, content_output) = ThinkingProcessor::process_text_chunk(ctx, "");
-            if let Some(final_reasoning) = reasoning_output {
-                ctx.reasoning_content.push_str(&final_reasoning);
+    // Stream tool calls the OpenAI way: a header delta with id, type and name for each call,
+    // then its arguments in fragments. Poe only delivers complete calls, so the final arguments are split.
+    fn render_tool_calls(&self, tool_calls: &[ChunkToolCall]) -> String {
+        let mut output = String::new();
+        for tool_call in tool_calls {
+            let header = ToolCallDelta {
+                index: tool_call.index,
+                id: Some(tool_call.call.id.clone()),
+                r#type: Some(tool_call.call.r#type.clone()),
+                function: FunctionCallDelta {
+                    name: Some(tool_call.call.function.name.clone()),
+                    arguments: String::new(),
+                },
+            };
+            let mut deltas = vec![header];
+            let chars: Vec<char> = tool_call.call.function.arguments.chars().collect();
+            for fragment in chars.chunks(TOOL_ARGUMENT_FRAGMENT_CHARS) {
+                deltas.push(ToolCallDelta {
+                    index: tool_call.index,
+                    id: None,
+                    r#type: None,
+                    function: FunctionCallDelta {
+                        name: None,
+                        arguments: fragment.iter().collect(),
+                    },
+                });
             }
-            if let Some(final_content) = content_output {
-                ctx.content.push_str(&final_content);
+            debug!(
+                "🔧 Streaming tool call | Index: {} | Function: {} | Fragments: {}",
+                tool_call.index,
+                tool_call.call.function.name,
+                deltas.len() - 1
+            );
+            for delta in deltas {
+                let chunk = self.create_tool_calls_chunk(vec![delta]);
+                output.push_str(&format!(
+                    "data: {}\n\n",
+                    serde_json::to_string(&chunk).unwrap()
+                ));
             }
         }
+        output
+    }
+
+    // Cut outgoing content at max_tokens, returns true once the limit is exceeded.
+    // A reply of exactly max_tokens tokens is complete, as in create_final_response.
+    fn apply_token_limit(&self, ctx: &mut EventContext, content: &str) -> (String, bool) {
+        let Some(limit) = self.max_tokens else {
+            return (content.to_string(), false);
+        };
+        let sent_tokens = ctx.get("sent_completion_tokens").unwrap_or(0) as u32;
+        let tokens = count_completion_tokens(content);
+        if sent_tokens + tokens <= limit {
+            ctx.insert("sent_completion_tokens", (sent_tokens + tokens) as usize);
+            return (content.to_string(), false);
+        }
+        debug!(
+            "✂️ max_tokens reached, ending stream | Limit: {} | Sent: {}",
+            limit, sent_tokens
+        );
+        ctx.insert("sent_completion_tokens", limit as usize);
+        (
+            truncate_to_tokens(content, limit.saturating_sub(sent_tokens)),
+            true,
+        )
+    }
+
+    // Render streamed content, ending the reply on a stop sequence or at max_tokens.
+    // Returns true when the output was finished and the upstream stream should be dropped.
+    fn render_content(&self, ctx: &mut EventContext, content: &str) -> (String, bool) {
+        let content = ctx.stop_matcher.push(content);
+        let (mut output, limited) = self.render_text(ctx, &content);
+        if limited {
+            return (output, true);
+        }
+        if ctx.stop_matcher.stopped() {
+            output.push_str(&self.render_finish(ctx, "stop"));
+            return (output, true);
+        }
+        (output, false)
+    }
+
+    // Render a content chunk (after the role chunk if not sent yet).
+    // Returns true when max_tokens was reached, the output then ends with finish_reason "length".
+    fn render_text(&self, ctx: &mut EventContext, content: &str) -> (String, bool) {
+        let mut output = String::new();
+        if !ctx.role_chunk_sent {
+            let role_chunk = self.create_role_chunk();
+            output.push_str(&format!(
+                "data: {}\n\n",
+                serde_json::to_string(&role_chunk).unwrap()
+            ));
+            ctx.role_chunk_sent = true;
+        }
+        if content.is_empty() {
+            return (output, false);
+        }
+        let (content, limited) = self.apply_token_limit(ctx, content);
+        if !content.is_empty() {
+            let chunk = self.create_stream_chunk(&content, None);
+            output.push_str(&format!(
+                "data: {}\n\n",
+                serde_json::to_string(&chunk).unwrap()
+            ));
+        }
+        if limited {
+            output.push_str(&self.render_finish(ctx, "length"));
+        }
+        (output, limited)
+    }
+
+    // Render the end of the reply, releasing text held back by the stop matcher first
+    fn render_done(&self, ctx: &mut EventContext, finish_reason: &str) -> String {
+        let held = ctx.stop_matcher.finish();
+        let (mut output, limited) = self.render_text(ctx, &held);
+        if !limited {
+            output.push_str(&self.render_finish(ctx, finish_reason));
+        }
+        output
+    }
 
-        // Process content, including file reference replacement
-        let content = if let Some(replace_content) = &ctx.replace_buffer {
-            self.process_file_references(replace_content, &ctx.file_refs)
+    // Render the final chunk with finish_reason and, if requested, usage
+    fn render_finish(&self, ctx: &mut EventContext, finish_reason: &str) -> String {
+        let (prompt_tokens, completion_tokens, total_tokens) = self.calculate_tokens(ctx);
+        let final_chunk = self.create_stream_chunk("", Some(finish_reason.to_string()));
+        let final_json = if self.include_usage {
+            debug!(
+                "📊 Token usage statistics | prompt_tokens: {} | completion_tokens: {} | total_tokens: {}",
+                prompt_tokens, completion_tokens, total_tokens
+            );
+            let mut json_value = serde_json::to_value(&final_chunk).unwrap();
+            json_value["usage"] = serde_json::json!({
+                "prompt_tokens": prompt_tokens,
+                "completion_tokens": completion_tokens,
+                "total_tokens": total_tokens,
+                "prompt_tokens_details": {"cached_tokens": 0}
+            });
+            serde_json::to_string(&json_value).unwrap()
         } else {
-            self.process_file_references(&ctx.content, &ctx.file_refs)
+            serde_json::to_string(&final_chunk).unwrap()
+        };
+
+        if !ctx.role_chunk_sent {
+            let role_chunk = self.create_role_chunk();
+            ctx.role_chunk_sent = true;
+            format!(
+                "data: {}\n\ndata: {}\n\n",
+                serde_json::to_string(&role_chunk).unwrap(),
+                final_json
+            )
+        } else {
+            format!("data: {}\n\n", final_json)
+        }
+    }
+
+    // Create final full response (non-streaming mode)
+    fn create_final_response(&self, ctx: &mut EventContext) -> ChatCompletionResponse {
+        // Process remaining pending_text and file references
+        let content = finalize_content(ctx);
+
+        // Apply stop sequences and max_tokens the same way the streaming path does
+        let (mut content, stop_matched) = StopMatcher::truncate(&content, &self.stop);
+        let limit_reached = match self.max_tokens {
+            Some(limit) if count_completion_tokens(&content) > limit => {
+                content = truncate_to_tokens(&content, limit);
+                true
+            }
+            _ => false,
         };
 
         // Calculate tokens
         let (prompt_tokens, completion_tokens, total_tokens) = self.calculate_tokens(ctx);
 
         // Determine finish_reason
-        let finish_reason = if !ctx.tool_calls.is_empty() {
+        let finish_reason = if limit_reached {
+            "length".to_string()
+        } else if stop_matched {
+            "stop".to_string()
+        } else if !ctx.tool_calls.is_empty() {
             "tool_calls".to_string()
         } else {
             "stop".to_string()
@@ -619,11 +1479,11 @@ impl OutputGenerator {
             created: self.created,
             model: self.model.clone(),
             choices: vec![CompletionChoice {
-                index: 0,
+                index: self.index,
                 message: CompletionMessage {
                     role: "assistant".to_string(),
                     content,
-                    refusal: None,
+                    refusal: self.refusal.clone().map(serde_json::Value::String),
                     tool_calls: if ctx.tool_calls.is_empty() {
                         None
                     } else {
@@ -661,7 +1521,24 @@ impl OutputGenerator {
     where
         S: Stream<Item = Result<ChatResponse, PoeError>> + Send + Unpin + 'static,
     {
-        let ctx = Arc::new(Mutex::new(EventContext::default()));
+        // Add done message
+        let done_message = "data: [DONE]\n\n".to_string();
+        self.process_events(event_stream)
+            .chain(stream::once(future::ready(Ok(done_message))))
+    }
+
+    // Convert one choice's event stream into SSE chunks, without the [DONE] trailer
+    fn process_events<S>(
+        self,
+        event_stream: S,
+    ) -> impl Stream<Item = Result<String, std::convert::Infallible>> + Send + 'static
+    where
+        S: Stream<Item = Result<ChatResponse, PoeError>> + Send + Unpin + 'static,
+    {
+        let ctx = Arc::new(Mutex::new(EventContext {
+            stop_matcher: StopMatcher::new(&self.stop),
+            ..Default::default()
+        }));
         let handler_manager = EventHandlerManager::new();
 
         // Directly use unfold logic to process event stream
@@ -772,36 +1649,15 @@ impl OutputGenerator {
                                                 }
                                             } else {
                                                 // Normal content processing
-                                                let processed = generator.process_file_references(
+                                                let processed = replace_file_references(
                                                     &chunk_content,
                                                     &ctx_guard.file_refs,
                                                 );
 
-                                                // Determine if role chunk needs to be sent
-                                                if !ctx_guard.role_chunk_sent {
-                                                    let role_chunk = generator.create_role_chunk();
-                                                    let role_json =
-                                                        serde_json::to_string(&role_chunk).unwrap();
-                                                    ctx_guard.role_chunk_sent = true;
-
-                                                    let content_chunk = generator
-                                                        .create_stream_chunk(&processed, None);
-                                                    let content_json =
-                                                        serde_json::to_string(&content_chunk)
-                                                            .unwrap();
-
-                                                    output_content = Some(format!(
-                                                        "data: {}\n\ndata: {}\n\n",
-                                                        role_json, content_json
-                                                    ));
-                                                } else {
-                                                    let chunk = generator
-                                                        .create_stream_chunk(&processed, None);
-                                                    let json =
-                                                        serde_json::to_string(&chunk).unwrap();
-                                                    output_content =
-                                                        Some(format!("data: {}\n\n", json));
-                                                }
+                                                let (output, limited) = generator
+                                                    .render_content(&mut ctx_guard, &processed);
+                                                is_done |= limited;
+                                                output_content = Some(output);
                                             }
                                         }
                                     }
@@ -812,29 +1668,10 @@ impl OutputGenerator {
                                                 "🖼️ Processing file reference, generating output with URL"
                                             );
 
-                                            // Determine if role chunk needs to be sent
-                                            if !ctx_guard.role_chunk_sent {
-                                                let role_chunk = generator.create_role_chunk();
-                                                let role_json =
-                                                    serde_json::to_string(&role_chunk).unwrap();
-                                                ctx_guard.role_chunk_sent = true;
-
-                                                let content_chunk = generator
-                                                    .create_stream_chunk(&chunk_content, None);
-                                                let content_json =
-                                                    serde_json::to_string(&content_chunk).unwrap();
-
-                                                output_content = Some(format!(
-                                                    "data: {}\n\ndata: {}\n\n",
-                                                    role_json, content_json
-                                                ));
-                                            } else {
-                                                let chunk = generator
-                                                    .create_stream_chunk(&chunk_content, None);
-                                                let json = serde_json::to_string(&chunk).unwrap();
-                                                output_content =
-                                                    Some(format!("data: {}\n\n", json));
-                                            }
+                                            let (output, limited) = generator
+                                                .render_content(&mut ctx_guard, &chunk_content);
+                                            is_done |= limited;
+                                            output_content = Some(output);
                                         }
                                     }
                                     ChatEventType::ReplaceResponse => {
@@ -844,38 +1681,17 @@ impl OutputGenerator {
                                                 "🔄 ReplaceResponse contains image references, sending directly"
                                             );
 
-                                            // Determine if role chunk needs to be sent
-                                            if !ctx_guard.role_chunk_sent {
-                                                let role_chunk = generator.create_role_chunk();
-                                                let role_json =
-                                                    serde_json::to_string(&role_chunk).unwrap();
-                                                ctx_guard.role_chunk_sent = true;
-
-                                                let content_chunk = generator
-                                                    .create_stream_chunk(&chunk_content, None);
-                                                let content_json =
-                                                    serde_json::to_string(&content_chunk).unwrap();
-
-                                                output_content = Some(format!(
-                                                    "data: {}\n\ndata: {}\n\n",
-                                                    role_json, content_json
-                                                ));
-                                            } else {
-                                                let chunk = generator
-                                                    .create_stream_chunk(&chunk_content, None);
-                                                let json = serde_json::to_string(&chunk).unwrap();
-                                                output_content =
-                                                    Some(format!("data: {}\n\n", json));
-                                            }
+                                            let (output, limited) = generator
+                                                .render_content(&mut ctx_guard, &chunk_content);
+                                            is_done |= limited;
+                                            output_content = Some(output);
                                         }
                                     }
                                     ChatEventType::Json => {
                                         if !ctx_guard.pending_tool_calls.is_empty() {
                                             debug!("🔧 Processing tool calls");
-                                            let tool_chunk = generator.create_tool_calls_chunk(
-                                                &ctx_guard.pending_tool_calls,
-                                            );
-                                            let json = serde_json::to_string(&tool_chunk).unwrap();
+                                            let tool_output = generator
+                                                .render_tool_calls(&ctx_guard.pending_tool_calls);
 
                                             if !ctx_guard.role_chunk_sent {
                                                 let role_chunk = generator.create_role_chunk();
@@ -883,155 +1699,50 @@ impl OutputGenerator {
                                                     serde_json::to_string(&role_chunk).unwrap();
                                                 ctx_guard.role_chunk_sent = true;
                                                 output_content = Some(format!(
-                                                    "data: {}\n\ndata: {}\n\n",
-                                                    role_json, json
+                                                    "data: {}\n\n{}",
+                                                    role_json, tool_output
                                                 ));
                                             } else {
-                                                output_content =
-                                                    Some(format!("data: {}\n\n", json));
+                                                output_content = Some(tool_output);
                                             }
                                             ctx_guard.pending_tool_calls.clear();
                                         }
                                     }
                                     ChatEventType::Done => {
-                                        // If Done event returns content, it means there are unprocessed image references
-                                        if let Some(chunk_content) = chunk_content_opt {
-                                            if chunk_content != "done" && !ctx_guard.image_urls_sent
-                                            {
-                                                debug!(
-                                                    "✅ Done event contains unprocessed image references, sending final content"
-                                                );
-                                                let chunk = generator.create_stream_chunk(
-                                                    &chunk_content,
-                                                    Some("stop".to_string()),
-                                                );
-                                                let json = serde_json::to_string(&chunk).unwrap();
-                                                output_content =
-                                                    Some(format!("data: {}\n\n", json));
-                                                ctx_guard.image_urls_sent = true; // Mark as sent
-                                            } else {
-                                                // General completion event
-                                                let (
-                                                    prompt_tokens,
-                                                    completion_tokens,
-                                                    total_tokens,
-                                                ) = generator.calculate_tokens(&mut ctx_guard);
-                                                let finish_reason =
-                                                    if !ctx_guard.tool_calls.is_empty() {
-                                                        "tool_calls"
-                                                    } else {
-                                                        "stop"
-                                                    };
-                                                let final_chunk = generator.create_stream_chunk(
-                                                    "",
-                                                    Some(finish_reason.to_string()),
-                                                );
-                                                let final_json = if generator.include_usage {
-                                                    debug!(
-                                                        "📊 Token usage statistics | prompt_tokens: {} | completion_tokens: {} | total_tokens: {}",
-                                                        prompt_tokens,
-                                                        completion_tokens,
-                                                        total_tokens
-                                                    );
-                                                    let mut json_value =
-                                                        serde_json::to_value(&final_chunk).unwrap();
-                                                    json_value["usage"] = serde_json::json!({
-                                                        "prompt_tokens": prompt_tokens,
-                                                        "completion_tokens": completion_tokens,
-                                                        "total_tokens": total_tokens,
-                                                        "prompt_tokens_details": {"cached_tokens": 0}
-                                                    });
-                                                    serde_json::to_string(&json_value).unwrap()
-                                                } else {
-                                                    serde_json::to_string(&final_chunk).unwrap()
-                                                };
-
-                                                if !ctx_guard.role_chunk_sent {
-                                                    let role_chunk = generator.create_role_chunk();
-                                                    let role_json =
-                                                        serde_json::to_string(&role_chunk).unwrap();
-                                                    ctx_guard.role_chunk_sent = true;
-                                                    output_content = Some(format!(
-                                                        "data: {}\n\ndata: {}\n\n",
-                                                        role_json, final_json
-                                                    ));
-                                                } else {
-                                                    output_content =
-                                                        Some(format!("data: {}\n\n", final_json));
-                                                }
-                                            }
+                                        let finish_reason = if !ctx_guard.tool_calls.is_empty() {
+                                            "tool_calls"
                                         } else {
-                                            // Completion event with no content
-                                            let (prompt_tokens, completion_tokens, total_tokens) =
-                                                generator.calculate_tokens(&mut ctx_guard);
-                                            let finish_reason = if !ctx_guard.tool_calls.is_empty()
-                                            {
-                                                "tool_calls"
-                                            } else {
-                                                "stop"
-                                            };
-                                            let final_chunk = generator.create_stream_chunk(
-                                                "",
-                                                Some(finish_reason.to_string()),
+                                            "stop"
+                                        };
+                                        let mut output = String::new();
+                                        let mut finished = false;
+                                        // Content returned with Done carries image references that were not sent yet
+                                        if let Some(chunk_content) =
+                                            chunk_content_opt.filter(|content| content != "done")
+                                        {
+                                            debug!(
+                                                "✅ Done event contains unprocessed image references, sending final content"
                                             );
-                                            let final_json = if generator.include_usage {
-                                                debug!(
-                                                    "📊 Token usage statistics | prompt_tokens: {} | completion_tokens: {} | total_tokens: {}",
-                                                    prompt_tokens, completion_tokens, total_tokens
-                                                );
-                                                let mut json_value =
-                                                    serde_json::to_value(&final_chunk).unwrap();
-                                                json_value["usage"] = serde_json::json!({
-                                                    "prompt_tokens": prompt_tokens,
-                                                    "completion_tokens": completion_tokens,
-                                                    "total_tokens": total_tokens,
-                                                    "prompt_tokens_details": {"cached_tokens": 0}
-                                                });
-                                                serde_json::to_string(&json_value).unwrap()
-                                            } else {
-                                                serde_json::to_string(&final_chunk).unwrap()
-                                            };
-
-                                            if !ctx_guard.role_chunk_sent {
-                                                let role_chunk = generator.create_role_chunk();
-                                                let role_json =
-                                                    serde_json::to_string(&role_chunk).unwrap();
-                                                ctx_guard.role_chunk_sent = true;
-                                                output_content = Some(format!(
-                                                    "data: {}\n\ndata: {}\n\n",
-                                                    role_json, final_json
-                                                ));
-                                            } else {
-                                                output_content =
-                                                    Some(format!("data: {}\n\n", final_json));
-                                            }
+                                            let (content_output, limited) = generator
+                                                .render_content(&mut ctx_guard, &chunk_content);
+                                            output.push_str(&content_output);
+                                            finished = limited;
                                         }
+                                        if !finished {
+                                            output.push_str(
+                                                &generator
+                                                    .render_done(&mut ctx_guard, finish_reason),
+                                            );
+                                        }
+                                        output_content = Some(output);
                                     }
                                     _ => {
                                         // Other event types, if content is returned, process it
                                         if let Some(chunk_content) = chunk_content_opt {
-                                            if !ctx_guard.role_chunk_sent {
-                                                let role_chunk = generator.create_role_chunk();
-                                                let role_json =
-                                                    serde_json::to_string(&role_chunk).unwrap();
-                                                ctx_guard.role_chunk_sent = true;
-
-                                                let content_chunk = generator
-                                                    .create_stream_chunk(&chunk_content, None);
-                                                let content_json =
-                                                    serde_json::to_string(&content_chunk).unwrap();
-
-                                                output_content = Some(format!(
-                                                    "data: {}\n\ndata: {}\n\n",
-                                                    role_json, content_json
-                                                ));
-                                            } else {
-                                                let chunk = generator
-                                                    .create_stream_chunk(&chunk_content, None);
-                                                let json = serde_json::to_string(&chunk).unwrap();
-                                                output_content =
-                                                    Some(format!("data: {}\n\n", json));
-                                            }
+                                            let (output, limited) = generator
+                                                .render_content(&mut ctx_guard, &chunk_content);
+                                            is_done |= limited;
+                                            output_content = Some(output);
                                         }
                                     }
                                 }
@@ -1106,19 +1817,324 @@ impl OutputGenerator {
             },
         );
 
-        // Add done message
-        let done_message = "data: [DONE]\n\n".to_string();
+        // Filter out empty messages
+        Box::pin(stream_processor.filter(|result| {
+            future::ready(match result {
+                Ok(s) => !s.is_empty(),
+                Err(_) => true,
+            })
+        }))
+    }
+}
 
-        // Filter out empty messages and add done message
-        Box::pin(
-            stream_processor
-                .filter(|result| {
-                    future::ready(match result {
-                        Ok(s) => !s.is_empty(),
-                        Err(_) => true,
-                    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use poe_api_process::types::ChatToolCall;
+    use salvo::test::ResponseExt;
+
+    async fn started_choice(text: &str, semaphore: &Arc<Semaphore>) -> ChoiceStart {
+        let permit = Arc::clone(semaphore).acquire_owned().await.unwrap();
+        let started = StartedChat {
+            display_model: "gpt-4o".to_string(),
+            prompt_tokens: 7,
+            event_stream: replay_text_events(text.to_string()),
+            refusal: None,
+            max_tokens: None,
+        };
+        Box::pin(future::ready(Ok((started, permit))))
+    }
+
+    fn sse_chunks(body: &str) -> Vec<serde_json::Value> {
+        body.split("\n\n")
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter(|data| *data != "[DONE]")
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect()
+    }
+
+    fn tool_call(id: &str, name: &str, arguments: &str) -> ChatToolCall {
+        serde_json::from_value(json!({
+            "id": id,
+            "type": "function",
+            "function": {"name": name, "arguments": arguments}
+        }))
+        .unwrap()
+    }
+
+    // Poe reply with the given batches of tool calls followed by Done
+    fn tool_call_events(batches: Vec<Vec<ChatToolCall>>) -> EventStream {
+        let mut events: Vec<Result<ChatResponse, PoeError>> = batches
+            .into_iter()
+            .map(|calls| {
+                Ok(ChatResponse {
+                    event: ChatEventType::Json,
+                    data: Some(ChatResponseData::ToolCalls(calls)),
                 })
-                .chain(stream::once(future::ready(Ok(done_message)))),
+            })
+            .collect();
+        events.push(Ok(ChatResponse {
+            event: ChatEventType::Done,
+            data: None,
+        }));
+        Box::pin(stream::iter(events))
+    }
+
+    async fn render_stream(generator: OutputGenerator, event_stream: EventStream) -> String {
+        generator
+            .process_stream(event_stream)
+            .await
+            .map(|chunk| chunk.unwrap())
+            .collect::<Vec<_>>()
+            .await
+            .concat()
+    }
+
+    #[tokio::test]
+    async fn fan_out_merges_choices_and_usage() {
+        assert!(check_choice_count(None).is_ok_and(|n| n == 1));
+        assert!(check_choice_count(Some(MAX_CHOICES)).is_ok());
+        assert!(check_choice_count(Some(0)).is_err());
+        assert_eq!(
+            check_choice_count(Some(MAX_CHOICES + 1))
+                .unwrap_err()
+                .error
+                .param
+                .as_deref(),
+            Some("n")
+        );
+
+        let semaphore = Arc::new(Semaphore::new(4));
+        let expected_tokens =
+            count_completion_tokens("Red apples") + count_completion_tokens("Green pears");
+
+        // Non-streaming: one response, choices in index order, usage summed once
+        let starts = vec![
+            started_choice("Red apples", &semaphore).await,
+            started_choice("Green pears", &semaphore).await,
+        ];
+        let mut res = Response::new();
+        handle_multi_non_stream_response(
+            &mut res,
+            starts,
+            OutputGenerator::new("gpt-4o".to_string(), 7, true),
+            Arc::new(AtomicU32::new(0)),
         )
+        .await;
+        let body: serde_json::Value = res.take_json().await.unwrap();
+        assert_eq!(body["choices"][0]["index"], 0);
+        assert_eq!(body["choices"][0]["message"]["content"], "Red apples");
+        assert_eq!(body["choices"][1]["index"], 1);
+        assert_eq!(body["choices"][1]["message"]["content"], "Green pears");
+        assert_eq!(body["usage"]["prompt_tokens"], 7);
+        assert_eq!(body["usage"]["completion_tokens"], expected_tokens);
+
+        // Streaming: chunks of both choices, then a single usage chunk and [DONE]
+        let starts = vec![
+            started_choice("Red apples", &semaphore).await,
+            started_choice("Green pears", &semaphore).await,
+        ];
+        let mut res = Response::new();
+        handle_multi_stream_response(
+            &mut res,
+            starts,
+            OutputGenerator::new("gpt-4o".to_string(), 7, true),
+            Arc::new(AtomicU32::new(0)),
+        );
+        let body = res.take_string().await.unwrap();
+        assert!(body.ends_with("data: [DONE]\n\n"));
+        let chunks = sse_chunks(&body);
+        for index in 0..2 {
+            let finishes = chunks
+                .iter()
+                .filter(|c| c["choices"][0]["index"] == index)
+                .filter(|c| c["choices"][0]["finish_reason"] == "stop")
+                .count();
+            assert_eq!(finishes, 1);
+        }
+        let usage: Vec<_> = chunks.iter().filter(|c| !c["usage"].is_null()).collect();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0]["choices"], json!([]));
+        assert_eq!(usage[0]["usage"]["completion_tokens"], expected_tokens);
+
+        // A choice that fails to start ends the stream with its error and no usage
+        let failed: ChoiceStart = Box::pin(future::ready(Err::<
+            (StartedChat, OwnedSemaphorePermit),
+            _,
+        >((
+            StatusCode::TOO_MANY_REQUESTS,
+            check_choice_count(Some(0)).unwrap_err(),
+        ))));
+        let mut res = Response::new();
+        handle_multi_stream_response(
+            &mut res,
+            vec![failed],
+            OutputGenerator::new("gpt-4o".to_string(), 7, true),
+            Arc::new(AtomicU32::new(0)),
+        );
+        let body = res.take_string().await.unwrap();
+        let chunks = sse_chunks(&body);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0]["error"]["param"], "n");
+        assert!(body.ends_with("data: [DONE]\n\n"));
+    }
+
+    #[tokio::test]
+    async fn parallel_tool_calls_false_keeps_only_the_first_call() {
+        let batches = || {
+            vec![
+                vec![
+                    tool_call("call_weather", "get_weather", r#"{"city":"Paris"}"#),
+                    tool_call("call_time", "get_time", r#"{"tz":"CET"}"#),
+                ],
+                vec![tool_call("call_news", "get_news", "{}")],
+            ]
+        };
+
+        // Streaming: every tool call delta is index 0 of call_weather
+        let generator = OutputGenerator::new("gpt-4o".to_string(), 0, false);
+        let body =
+            render_stream(generator, first_tool_call_only(tool_call_events(batches()))).await;
+        let chunks = sse_chunks(&body);
+        let deltas: Vec<&serde_json::Value> = chunks
+            .iter()
+            .filter_map(|c| c["choices"][0]["delta"]["tool_calls"].as_array())
+            .flatten()
+            .collect();
+        assert!(!deltas.is_empty());
+        assert!(deltas.iter().all(|d| d["index"] == 0));
+        let ids: Vec<&serde_json::Value> = deltas
+            .iter()
+            .map(|d| &d["id"])
+            .filter(|id| !id.is_null())
+            .collect();
+        assert_eq!(ids, vec!["call_weather"]);
+        assert_eq!(
+            chunks.last().unwrap()["choices"][0]["finish_reason"],
+            "tool_calls"
+        );
+
+        // Non-streaming: a single tool call in the message
+        let mut ctx = collect_events(first_tool_call_only(tool_call_events(batches())))
+            .await
+            .unwrap();
+        let generator = OutputGenerator::new("gpt-4o".to_string(), 0, false);
+        let response = generator.create_final_response(&mut ctx);
+        let choice = &response.choices[0];
+        let tool_calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].id, "call_weather");
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+    }
+
+    #[tokio::test]
+    async fn max_tokens_boundary_matches_in_both_modes() {
+        let text = "one two three";
+        let tokens = count_completion_tokens(text);
+
+        for (limit, finish_reason, content) in [
+            (tokens, "stop", "one two three"),
+            (tokens - 1, "length", "one two"),
+        ] {
+            let mut generator = OutputGenerator::new("gpt-4o".to_string(), 0, false);
+            generator.max_tokens = Some(limit);
+
+            let body = render_stream(generator.clone(), replay_text_events(text.to_string())).await;
+            let chunks = sse_chunks(&body);
+            let streamed: String = chunks
+                .iter()
+                .filter_map(|c| c["choices"][0]["delta"]["content"].as_str())
+                .collect();
+            assert_eq!(streamed, content);
+            assert_eq!(
+                chunks.last().unwrap()["choices"][0]["finish_reason"],
+                finish_reason
+            );
+
+            let mut ctx = collect_events(replay_text_events(text.to_string()))
+                .await
+                .unwrap();
+            let response = generator.create_final_response(&mut ctx);
+            assert_eq!(response.choices[0].message.content, content);
+            assert_eq!(
+                response.choices[0].finish_reason.as_deref(),
+                Some(finish_reason)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn stop_sequence_split_across_stream_chunks() {
+        let events: Vec<Result<ChatResponse, PoeError>> = ["Count: 1, 2, ST", "OP", " 3, 4"]
+            .into_iter()
+            .map(|text| {
+                Ok(ChatResponse {
+                    event: ChatEventType::Text,
+                    data: Some(ChatResponseData::Text {
+                        text: text.to_string(),
+                    }),
+                })
+            })
+            .chain(std::iter::once(Ok(ChatResponse {
+                event: ChatEventType::Done,
+                data: None,
+            })))
+            .collect();
+        let mut generator = OutputGenerator::new("gpt-4o".to_string(), 0, false);
+        generator.stop = vec!["STOP".to_string()];
+
+        let body = render_stream(generator, Box::pin(stream::iter(events))).await;
+        let chunks = sse_chunks(&body);
+        let streamed: String = chunks
+            .iter()
+            .filter_map(|c| c["choices"][0]["delta"]["content"].as_str())
+            .collect();
+        assert_eq!(streamed, "Count: 1, 2, ");
+        let finishes: Vec<_> = chunks
+            .iter()
+            .filter_map(|c| c["choices"][0]["finish_reason"].as_str())
+            .collect();
+        assert_eq!(finishes, vec!["stop"]);
+    }
+
+    #[test]
+    fn tool_call_arguments_stream_in_whole_character_fragments() {
+        let arguments = json!({
+            "city": "Zürich",
+            "note": "☀️ sunny, 25°C — ideal for a walk along the lake 🚶‍♀️ ".repeat(3)
+        })
+        .to_string();
+        assert!(arguments.chars().count() > TOOL_ARGUMENT_FRAGMENT_CHARS);
+        let generator = OutputGenerator::new("gpt-4o".to_string(), 0, false);
+        let body = generator.render_tool_calls(&[ChunkToolCall {
+            index: 0,
+            call: tool_call("call_weather", "get_weather", &arguments),
+        }]);
+        let deltas: Vec<serde_json::Value> = sse_chunks(&body)
+            .iter()
+            .map(|c| c["choices"][0]["delta"]["tool_calls"][0].clone())
+            .collect();
+
+        // The header delta carries id, type and name with empty arguments
+        assert_eq!(deltas[0]["id"], "call_weather");
+        assert_eq!(deltas[0]["type"], "function");
+        assert_eq!(deltas[0]["function"]["name"], "get_weather");
+        assert_eq!(deltas[0]["function"]["arguments"], "");
+
+        let fragments: Vec<&str> = deltas[1..]
+            .iter()
+            .map(|d| d["function"]["arguments"].as_str().unwrap())
+            .collect();
+        assert!(fragments.len() > 1);
+        assert!(fragments.iter().all(|f| {
+            let chars = f.chars().count();
+            chars > 0 && chars <= TOOL_ARGUMENT_FRAGMENT_CHARS
+        }));
+        assert_eq!(fragments.concat(), arguments);
+        assert!(
+            deltas[1..]
+                .iter()
+                .all(|d| d["id"].is_null() && d["function"]["name"].is_null())
+        );
     }
 }
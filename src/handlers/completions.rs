@@ -0,0 +1,510 @@
+use super::chat::{StartedChat, extract_access_key, start_chat};
+use super::limit::UsageMeter;
+use crate::cache::get_cached_config;
+use crate::evert::{EventDelta, StopMatcher, collect_events, delta_stream, finalize_content};
+use crate::policy::CredentialPolicy;
+use crate::types::*;
+use crate::utils::{
+    count_completion_tokens, format_duration, pretty_json_truncated, redact_headers,
+    redact_json_fields, truncate_to_tokens,
+};
+use chrono::Utc;
+use futures_util::future;
+use futures_util::stream::{self, StreamExt};
+use nanoid::nanoid;
+use salvo::http::header;
+use salvo::prelude::*;
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{debug, error, info};
+
+#[handler]
+pub async fn completions(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let start_time = Instant::now();
+
+    // Structure request/response logging with separator
+    debug!("------ Incoming Request [POST] {} ------", req.uri());
+
+    // Log inbound request metadata with redacted headers
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let redacted_headers = redact_headers(req.headers());
+
+    debug!(
+        "📝 Request metadata | Method: {} | Path: {} | Headers: {:?}",
+        method, path, redacted_headers
+    );
+
+    let max_size: usize = std::env::var("MAX_REQUEST_SIZE")
+        .unwrap_or_else(|_| "1073741824".to_string())
+        .parse()
+        .unwrap_or(1024 * 1024 * 1024);
+
+    let config = get_cached_config().await;
+
+    // Validate authorization
+    let access_key = match extract_access_key(req) {
+        Ok(key) => key,
+        Err(message) => {
+            error!("❌ {}", message);
+            res.status_code(StatusCode::UNAUTHORIZED);
+            res.render(Json(json!({ "error": message })));
+            return;
+        }
+    };
+
+    // Parse request body
+    let completion_request = match req.payload_with_max_size(max_size).await {
+        Ok(bytes) => match serde_json::from_slice::<CompletionRequest>(bytes) {
+            Ok(parsed) => {
+                debug!(
+                    "📊 Completion request parsed | Model: {} | Stream: {:?}",
+                    parsed.model, parsed.stream
+                );
+                let request_value = serde_json::to_value(&parsed).unwrap_or_else(|_| json!(null));
+                let redacted_request = redact_json_fields(&request_value);
+                debug!(
+                    "📋 Request body (sanitized, truncated):\n{}",
+                    pretty_json_truncated(&redacted_request, 64 * 1024)
+                );
+                parsed
+            }
+            Err(e) => {
+                error!("❌ JSON parsing failed: {}", e);
+                res.status_code(StatusCode::BAD_REQUEST);
+                res.render(Json(
+                    json!({ "error": format!("JSON parsing failed: {}", e) }),
+                ));
+                return;
+            }
+        },
+        Err(e) => {
+            error!("❌ Request size exceeded limit or read failed: {}", e);
+            res.status_code(StatusCode::PAYLOAD_TOO_LARGE);
+            res.render(Json(json!({
+                "error": format!("Request size exceeded limit ({} bytes) or read failed: {}", max_size, e)
+            })));
+            return;
+        }
+    };
+
+    let prompts = match completion_request.prompt.clone() {
+        Some(CompletionPrompt::Text(prompt)) => vec![prompt],
+        Some(CompletionPrompt::Batch(prompts)) if !prompts.is_empty() => prompts,
+        Some(CompletionPrompt::Tokens(_)) => {
+            render_invalid_prompt(
+                res,
+                "Token array prompts are not supported, send text prompts",
+            );
+            return;
+        }
+        _ => {
+            render_invalid_prompt(res, "prompt must be a non-empty string or array of strings");
+            return;
+        }
+    };
+
+    let stops = completion_request
+        .stop
+        .clone()
+        .map(StopSequences::into_vec)
+        .unwrap_or_default();
+    let stream = completion_request.stream.unwrap_or(false);
+
+    // Start one Poe request per prompt, each prompt becomes one choice
+    let policy = depot.obtain::<CredentialPolicy>().ok();
+    let mut started_chats = Vec::with_capacity(prompts.len());
+    for prompt in &prompts {
+        let chat_request = prompt_to_chat_request(&completion_request, prompt, &stops);
+        match start_chat(&config, &access_key, &chat_request, policy).await {
+            Ok(started) => started_chats.push(started),
+            Err((status, error_response)) => {
+                res.status_code(status);
+                res.render(Json(error_response));
+                return;
+            }
+        }
+    }
+
+    let generator = CompletionGenerator {
+        id: format!("cmpl-{}", nanoid!(10)),
+        created: Utc::now().timestamp(),
+        model: started_chats[0].display_model.clone(),
+        prompt_tokens: started_chats.iter().map(|s| s.prompt_tokens).sum(),
+        stops,
+        max_tokens: started_chats[0].max_tokens,
+        usage_meter: depot.obtain::<UsageMeter>().cloned().unwrap_or_default(),
+    };
+    let echo_prompts: Vec<String> = if completion_request.echo.unwrap_or(false) {
+        prompts
+    } else {
+        vec![String::new(); prompts.len()]
+    };
+
+    if stream {
+        let include_usage = completion_request
+            .stream_options
+            .as_ref()
+            .and_then(|opts| opts.include_usage)
+            .unwrap_or(false);
+        handle_stream_response(res, started_chats, generator, echo_prompts, include_usage);
+    } else {
+        handle_non_stream_response(res, started_chats, generator, echo_prompts).await;
+    }
+
+    let duration = start_time.elapsed();
+    info!(
+        "✅ Completion request processing completed | Duration: {}",
+        format_duration(duration)
+    );
+}
+
+fn render_invalid_prompt(res: &mut Response, message: &str) {
+    error!("❌ Invalid prompt: {}", message);
+    res.status_code(StatusCode::BAD_REQUEST);
+    res.render(Json(OpenAIErrorResponse {
+        error: OpenAIError {
+            message: message.to_string(),
+            r#type: "invalid_request_error".to_string(),
+            code: "invalid_prompt".to_string(),
+            param: Some("prompt".to_string()),
+        },
+    }));
+}
+
+/// Wrap a single prompt into a chat request with one user message
+pub(crate) fn prompt_to_chat_request(
+    request: &CompletionRequest,
+    prompt: &str,
+    stops: &[String],
+) -> ChatCompletionRequest {
+    ChatCompletionRequest {
+        model: request.model.clone(),
+        messages: vec![Message {
+            role: "user".to_string(),
+            content: Some(OpenAiContent::Text(prompt.to_string())),
+            ..Default::default()
+        }],
+        temperature: request.temperature,
+        top_p: request.top_p,
+        max_tokens: request.max_tokens,
+        stop: if stops.is_empty() {
+            None
+        } else {
+            Some(stops.to_vec())
+        },
+        stream: request.stream,
+        user: request.user.clone(),
+        ..Default::default()
+    }
+}
+
+async fn handle_non_stream_response(
+    res: &mut Response,
+    started_chats: Vec<StartedChat>,
+    generator: CompletionGenerator,
+    echo_prompts: Vec<String>,
+) {
+    let mut choices = Vec::with_capacity(started_chats.len());
+    let mut completion_tokens = 0;
+
+    for (index, (started, echo)) in started_chats.into_iter().zip(echo_prompts).enumerate() {
+        let mut ctx = match collect_events(started.event_stream).await {
+            Ok(ctx) => ctx,
+            Err((status, error_response)) => {
+                res.status_code(status);
+                res.render(Json(error_response));
+                return;
+            }
+        };
+        let (text, _) = StopMatcher::truncate(&finalize_content(&mut ctx), &generator.stops);
+        let (text, limited) = generator.limit_text(&text, 0);
+        completion_tokens += count_completion_tokens(&text);
+        choices.push(TextCompletionChoice {
+            text: format!("{}{}", echo, text),
+            index: index as u32,
+            logprobs: None,
+            finish_reason: Some(if limited { "length" } else { "stop" }.to_string()),
+        });
+    }
+
+    generator
+        .usage_meter
+        .record(&generator.model, generator.prompt_tokens, completion_tokens);
+    let response = TextCompletionResponse {
+        id: generator.id.clone(),
+        object: "text_completion".to_string(),
+        created: generator.created,
+        model: generator.model.clone(),
+        choices,
+        usage: Some(generator.usage(completion_tokens)),
+    };
+
+    let response_value = serde_json::to_value(&response).unwrap_or_else(|_| json!(null));
+    debug!(
+        "📤 Response body (sanitized, truncated):\n{}",
+        pretty_json_truncated(&redact_json_fields(&response_value), 64 * 1024)
+    );
+    debug!("------ Outgoing Response [200] /v1/completions ------");
+
+    res.render(Json(response));
+}
+
+fn handle_stream_response(
+    res: &mut Response,
+    started_chats: Vec<StartedChat>,
+    generator: CompletionGenerator,
+    echo_prompts: Vec<String>,
+    include_usage: bool,
+) {
+    info!(
+        "🌊 Starting completion streaming response | ID: {} | Model: {} | Prompts: {}",
+        generator.id,
+        generator.model,
+        started_chats.len()
+    );
+
+    res.headers_mut()
+        .insert(header::CONTENT_TYPE, "text/event-stream".parse().unwrap());
+    res.headers_mut()
+        .insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
+    res.headers_mut()
+        .insert(header::CONNECTION, "keep-alive".parse().unwrap());
+
+    let generator = Arc::new(generator);
+    let completion_tokens = Arc::new(Mutex::new(0u32));
+
+    // Choices are interleaved as they arrive, the index tells them apart
+    let choice_streams = started_chats
+        .into_iter()
+        .zip(echo_prompts)
+        .enumerate()
+        .map(|(index, (started, echo))| {
+            let echo_chunk = if echo.is_empty() {
+                String::new()
+            } else {
+                generator.chunk(index as u32, &echo, None)
+            };
+            let state = ChoiceStreamState {
+                index: index as u32,
+                generator: generator.clone(),
+                matcher: StopMatcher::new(&generator.stops),
+                text: String::new(),
+                sent_tokens: 0,
+                finished: false,
+                completion_tokens: completion_tokens.clone(),
+            };
+            stream::once(future::ready(echo_chunk)).chain(delta_stream(started.event_stream).scan(
+                state,
+                |state, delta| {
+                    future::ready(if state.finished {
+                        None
+                    } else {
+                        Some(state.render(delta))
+                    })
+                },
+            ))
+        })
+        .map(Box::pin)
+        .collect::<Vec<_>>();
+
+    let usage_generator = generator.clone();
+    let trailer = stream::once(async move {
+        let mut output = String::new();
+        let completion_tokens = *completion_tokens.lock().unwrap();
+        usage_generator.usage_meter.record(
+            &usage_generator.model,
+            usage_generator.prompt_tokens,
+            completion_tokens,
+        );
+        if include_usage {
+            let mut usage_chunk = json!({
+                "id": usage_generator.id,
+                "object": "text_completion",
+                "created": usage_generator.created,
+                "model": usage_generator.model,
+                "choices": []
+            });
+            usage_chunk["usage"] = usage_generator.usage(completion_tokens);
+            output.push_str(&format!("data: {}\n\n", usage_chunk));
+        }
+        output.push_str("data: [DONE]\n\n");
+        output
+    });
+
+    let body = stream::select_all(choice_streams)
+        .chain(trailer)
+        .filter(|chunk| future::ready(!chunk.is_empty()))
+        .map(Ok::<String, Infallible>);
+    res.stream(body);
+}
+
+// Shared response fields for all choices of one completion
+struct CompletionGenerator {
+    id: String,
+    created: i64,
+    model: String,
+    prompt_tokens: u32,
+    stops: Vec<String>,
+    max_tokens: Option<u32>,
+    // Records the completion's tokens once every choice is done
+    usage_meter: UsageMeter,
+}
+
+impl CompletionGenerator {
+    fn chunk(&self, index: u32, text: &str, finish_reason: Option<&str>) -> String {
+        let chunk = TextCompletionResponse {
+            id: self.id.clone(),
+            object: "text_completion".to_string(),
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![TextCompletionChoice {
+                text: text.to_string(),
+                index,
+                logprobs: None,
+                finish_reason: finish_reason.map(|r| r.to_string()),
+            }],
+            usage: None,
+        };
+        format!("data: {}\n\n", serde_json::to_string(&chunk).unwrap())
+    }
+
+    // Cut text at max_tokens given the tokens already sent, returns true once the limit is exceeded
+    fn limit_text(&self, text: &str, sent_tokens: u32) -> (String, bool) {
+        match self.max_tokens {
+            Some(limit) if sent_tokens + count_completion_tokens(text) > limit => (
+                truncate_to_tokens(text, limit.saturating_sub(sent_tokens)),
+                true,
+            ),
+            _ => (text.to_string(), false),
+        }
+    }
+
+    fn usage(&self, completion_tokens: u32) -> serde_json::Value {
+        json!({
+            "prompt_tokens": self.prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": self.prompt_tokens + completion_tokens
+        })
+    }
+}
+
+// Per-choice streaming state
+struct ChoiceStreamState {
+    index: u32,
+    generator: Arc<CompletionGenerator>,
+    matcher: StopMatcher,
+    text: String,
+    sent_tokens: u32,
+    finished: bool,
+    completion_tokens: Arc<Mutex<u32>>,
+}
+
+impl ChoiceStreamState {
+    fn render(&mut self, delta: EventDelta) -> String {
+        match delta {
+            EventDelta::Content(text) => {
+                let emitted = self.matcher.push(&text);
+                let (mut output, limited) = self.emit(&emitted);
+                if limited {
+                    output.push_str(&self.finish("length"));
+                } else if self.matcher.stopped() {
+                    output.push_str(&self.finish("stop"));
+                }
+                output
+            }
+            EventDelta::Done => {
+                let remaining = self.matcher.finish();
+                let (mut output, limited) = self.emit(&remaining);
+                output.push_str(&self.finish(if limited { "length" } else { "stop" }));
+                output
+            }
+            EventDelta::Error(_, error_response) => {
+                debug!("❌ Detected error, interrupting completion stream");
+                self.finished = true;
+                format!(
+                    "data: {}\n\n",
+                    serde_json::to_string(&error_response).unwrap()
+                )
+            }
+            // Text completions carry neither reasoning nor tool calls
+            EventDelta::Reasoning(_) | EventDelta::ToolCalls(_) => String::new(),
+        }
+    }
+
+    // Send text that passed the stop matcher, cut at max_tokens. Returns true when the limit was hit.
+    fn emit(&mut self, text: &str) -> (String, bool) {
+        if text.is_empty() {
+            return (String::new(), false);
+        }
+        let (text, limited) = self.generator.limit_text(text, self.sent_tokens);
+        self.sent_tokens += count_completion_tokens(&text);
+        self.text.push_str(&text);
+        let output = if text.is_empty() {
+            String::new()
+        } else {
+            self.generator.chunk(self.index, &text, None)
+        };
+        (output, limited)
+    }
+
+    fn finish(&mut self, finish_reason: &str) -> String {
+        self.finished = true;
+        *self.completion_tokens.lock().unwrap() += count_completion_tokens(&self.text);
+        self.generator.chunk(self.index, "", Some(finish_reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_prompt_wraps_each_prompt_as_user_message() {
+        let request: CompletionRequest = serde_json::from_value(json!({
+            "model": "gpt-4o-mini",
+            "prompt": ["one", "two"],
+            "stop": "\n"
+        }))
+        .expect("valid request");
+        let stops = request.stop.clone().map(StopSequences::into_vec).unwrap();
+        let chat_request = prompt_to_chat_request(&request, "two", &stops);
+        assert_eq!(chat_request.messages.len(), 1);
+        assert_eq!(chat_request.messages[0].role, "user");
+        assert_eq!(chat_request.stop, Some(vec!["\n".to_string()]));
+        assert!(matches!(request.prompt, Some(CompletionPrompt::Batch(ref p)) if p.len() == 2));
+    }
+
+    #[test]
+    fn max_tokens_cuts_streamed_choice_with_length() {
+        let generator = Arc::new(CompletionGenerator {
+            id: "cmpl-test".to_string(),
+            created: 0,
+            model: "gpt-4o-mini".to_string(),
+            prompt_tokens: 1,
+            stops: Vec::new(),
+            max_tokens: Some(3),
+            usage_meter: UsageMeter::default(),
+        });
+        let mut state = ChoiceStreamState {
+            index: 0,
+            generator: generator.clone(),
+            matcher: StopMatcher::new(&generator.stops),
+            text: String::new(),
+            sent_tokens: 0,
+            finished: false,
+            completion_tokens: Arc::new(Mutex::new(0)),
+        };
+        let output = state.render(EventDelta::Content("one two three four five".to_string()));
+        assert!(output.contains(r#""finish_reason":"length""#));
+        assert!(state.finished);
+        assert_eq!(state.sent_tokens, 3);
+        assert_eq!(*state.completion_tokens.lock().unwrap(), 3);
+
+        let (text, limited) = generator.limit_text("one two three", 0);
+        assert_eq!(text, "one two three");
+        assert!(!limited);
+    }
+}
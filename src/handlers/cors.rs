@@ -1,9 +1,63 @@
+use crate::cache::get_cached_config;
+use crate::policy::glob_match;
+use crate::types::CorsConfig;
 use salvo::http::{HeaderValue, Method, StatusCode, header};
 use salvo::prelude::*;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+
+const DEFAULT_METHODS: &[&str] = &["GET", "POST", "OPTIONS", "PUT", "DELETE", "PATCH", "HEAD"];
+
+// Used when `cors.allowed_headers` is not set; X-head headers (like X-Stainless-*) are matched by `x-*`
+const DEFAULT_HEADERS: &[&str] = &[
+    "Authorization",
+    "Content-Type",
+    "User-Agent",
+    "Accept",
+    "Origin",
+    "X-Requested-With",
+    "Accept-Encoding",
+    "Accept-Language",
+    "Cache-Control",
+    "Connection",
+    "Referer",
+    "Sec-Fetch-Dest",
+    "Sec-Fetch-Mode",
+    "Sec-Fetch-Site",
+    "Pragma",
+    "X-Api-Key",
+    "x-*",
+];
+
+const DEFAULT_MAX_AGE: u64 = 3600;
+
+fn allowed_headers(cors: &CorsConfig) -> Vec<String> {
+    cors.allowed_headers.clone().unwrap_or_else(|| {
+        DEFAULT_HEADERS
+            .iter()
+            .map(|header| header.to_string())
+            .collect()
+    })
+}
+
+fn allowed_methods(cors: &CorsConfig) -> Vec<String> {
+    cors.allowed_methods
+        .as_ref()
+        .map(|methods| methods.iter().map(|m| m.to_uppercase()).collect())
+        .unwrap_or_else(|| DEFAULT_METHODS.iter().map(|m| m.to_string()).collect())
+}
+
+/// Whether an Origin may read responses: permissive mode, an exact entry or a `*` pattern
+fn is_allowed_origin(cors: &CorsConfig, origin: &str) -> bool {
+    cors.permissive
+        || cors.allowed_origins.iter().any(|allowed| {
+            let allowed = allowed.trim().trim_end_matches('/');
+            allowed.eq_ignore_ascii_case(origin)
+                || (allowed.contains('*') && glob_match(allowed, origin))
+        })
+}
 
 /// Check if header is safe
-fn is_safe_header(header: &str) -> bool {
+fn is_safe_header(header: &str, allowed: &[String]) -> bool {
     let header_lower = header.trim().to_lowercase();
 
     // Exclude empty strings
@@ -11,36 +65,19 @@ fn is_safe_header(header: &str) -> bool {
         return false;
     }
 
-    // Blacklist: explicit malicious headers
+    // Blacklist: explicit malicious headers, whatever the config says
     if matches!(header_lower.as_str(), "cookie" | "set-cookie") {
         return false;
     }
 
-    // Whitelist: allowed header patterns
-    // 1. X-head headers (like X-Stainless-*)
-    // 2. Standard HTTP headers
-    header_lower.starts_with("x-")
-        || matches!(
-            header_lower.as_str(),
-            "accept"
-                | "accept-encoding"
-                | "accept-language"
-                | "authorization"
-                | "cache-control"
-                | "connection"
-                | "content-type"
-                | "user-agent"
-                | "referer"
-                | "origin"
-                | "pragma"
-                | "sec-fetch-dest"
-                | "sec-fetch-mode"
-                | "sec-fetch-site"
-        )
+    // Whitelist: configured header names and patterns
+    allowed
+        .iter()
+        .any(|pattern| glob_match(pattern.trim(), &header_lower))
 }
 
 /// Parse client requested headers and perform security filtering
-fn parse_requested_headers(req: &Request) -> Vec<String> {
+fn parse_requested_headers(req: &Request, allowed: &[String]) -> Vec<String> {
     req.headers()
         .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
         .and_then(|h| h.to_str().ok())
@@ -48,7 +85,7 @@ fn parse_requested_headers(req: &Request) -> Vec<String> {
             headers_str
                 .split(',')
                 .map(|h| h.trim().to_string())
-                .filter(|h| !h.is_empty() && is_safe_header(h))
+                .filter(|h| !h.is_empty() && is_safe_header(h, allowed))
                 .collect()
         })
         .unwrap_or_default()
@@ -61,43 +98,48 @@ pub async fn cors_middleware(
     res: &mut Response,
     ctrl: &mut FlowCtrl,
 ) {
-    // Get Origin header from request
+    let config = get_cached_config().await;
+    let cors = config.cors.clone().unwrap_or_default();
+
+    // Add Vary header to all responses, indicating response varies based on Origin header
+    res.headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("Origin"));
+
+    // Get Origin header from request, non-browser clients send none
     let origin = req
         .headers()
         .get(header::ORIGIN)
         .and_then(|h| h.to_str().ok())
-        .unwrap_or("null");
+        .map(|origin| origin.to_string());
 
     // Log request origin for debugging
-    debug!("📡 Received request from Origin: {}", origin);
+    debug!("📡 Received request from Origin: {:?}", origin);
 
-    // Set CORS headers
-    match HeaderValue::from_str(origin) {
-        Ok(origin_value) => {
+    let allowed_origin = origin
+        .as_deref()
+        .filter(|origin| is_allowed_origin(&cors, origin))
+        .and_then(|origin| HeaderValue::from_str(origin).ok());
+    match &allowed_origin {
+        Some(origin_value) => {
             res.headers_mut()
-                .insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin_value);
+                .insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin_value.clone());
+            if cors.allow_credentials {
+                res.headers_mut().insert(
+                    header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                    HeaderValue::from_static("true"),
+                );
+            }
         }
-        Err(e) => {
-            debug!("⚠️ Invalid Origin header: {}, Error: {}", origin, e);
-            res.headers_mut().insert(
-                header::ACCESS_CONTROL_ALLOW_ORIGIN,
-                HeaderValue::from_static("null"),
-            );
+        // Without CORS headers the browser will not let the page read the response
+        None if origin.is_some() => {
+            debug!("🚫 Origin not in CORS allowlist: {:?}", origin);
         }
+        None => {}
     }
 
-    res.headers_mut().insert(
-        header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
-        HeaderValue::from_static("true"),
-    );
-
-    // Add Vary header to all responses, indicating response varies based on Origin header
-    res.headers_mut()
-        .insert(header::VARY, HeaderValue::from_static("Origin"));
-
     // If OPTIONS request, handle directly and stop rest of flow
     if req.method() == Method::OPTIONS {
-        handle_preflight_request(req, res);
+        handle_preflight_request(req, res, &cors, allowed_origin.is_some());
         ctrl.skip_rest();
     } else {
         // Non-OPTIONS request, continue normal flow
@@ -106,48 +148,54 @@ pub async fn cors_middleware(
 }
 
 /// Handle CORS preflight requests specifically
-fn handle_preflight_request(req: &Request, res: &mut Response) {
+fn handle_preflight_request(
+    req: &Request,
+    res: &mut Response,
+    cors: &CorsConfig,
+    origin_allowed: bool,
+) {
     info!("🔍 Handling OPTIONS preflight request: {}", req.uri());
 
+    let methods = allowed_methods(cors);
+    let requested_method = req
+        .headers()
+        .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+        .and_then(|h| h.to_str().ok())
+        .map(|m| m.trim().to_uppercase());
+    let method_allowed = requested_method
+        .as_ref()
+        .is_none_or(|method| methods.contains(method));
+    if !origin_allowed || !method_allowed {
+        warn!(
+            "🚫 CORS preflight rejected | Origin: {:?} | Method: {:?}",
+            req.headers().get(header::ORIGIN),
+            requested_method
+        );
+        res.status_code(StatusCode::FORBIDDEN);
+        return;
+    }
+
     // Set standard headers for CORS preflight response
-    res.headers_mut().insert(
-        header::ACCESS_CONTROL_ALLOW_METHODS,
-        HeaderValue::from_static("GET, POST, OPTIONS, PUT, DELETE, PATCH, HEAD"),
-    );
+    if let Ok(methods_value) = HeaderValue::from_str(&methods.join(", ")) {
+        res.headers_mut()
+            .insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods_value);
+    }
 
-    // Basic hardcoded headers (maintain backward compatibility)
-    let base_headers = vec![
-        "Authorization",
-        "Content-Type",
-        "User-Agent",
-        "Accept",
-        "Origin",
-        "X-Requested-With",
-        "Access-Control-Request-Method",
-        "Access-Control-Request-Headers",
-        "Accept-Encoding",
-        "Accept-Language",
-        "Cache-Control",
-        "Connection",
-        "Referer",
-        "Sec-Fetch-Dest",
-        "Sec-Fetch-Mode",
-        "Sec-Fetch-Site",
-        "Pragma",
-        "X-Api-Key",
-    ];
+    // Configured header names, patterns are answered with the requested headers they match
+    let allowed = allowed_headers(cors);
+    let mut all_headers: Vec<String> = allowed
+        .iter()
+        .filter(|h| !h.contains('*') && is_safe_header(h, &allowed))
+        .cloned()
+        .collect();
 
     // Parse dynamically requested headers from client
-    let dynamic_headers = parse_requested_headers(req);
+    let dynamic_headers = parse_requested_headers(req, &allowed);
 
-    // Merge base headers and dynamic headers
-    let mut all_headers = base_headers.clone();
+    // Merge configured headers and dynamic headers
     for header in &dynamic_headers {
-        if !all_headers
-            .iter()
-            .any(|h| h.to_lowercase() == header.to_lowercase())
-        {
-            all_headers.push(header);
+        if !all_headers.iter().any(|h| h.eq_ignore_ascii_case(header)) {
+            all_headers.push(header.clone());
         }
     }
 
@@ -167,35 +215,59 @@ fn handle_preflight_request(req: &Request, res: &mut Response) {
                 .insert(header::ACCESS_CONTROL_ALLOW_HEADERS, headers_value);
         }
         Err(e) => {
-            // Fallback handling: if dynamic headers have issues, use base headers
+            // Fallback handling: if dynamic headers have issues, only allow the minimum
             debug!(
                 "⚠️ Dynamic headers setting failed: {}, using base headers",
                 e
             );
             res.headers_mut().insert(
                 header::ACCESS_CONTROL_ALLOW_HEADERS,
-                HeaderValue::from_static(
-                    "Authorization, Content-Type, User-Agent, Accept, Origin, \
-                    X-Requested-With, Access-Control-Request-Method, \
-                    Access-Control-Request-Headers, Accept-Encoding, Accept-Language, \
-                    Cache-Control, Connection, Referer, Sec-Fetch-Dest, Sec-Fetch-Mode, \
-                    Sec-Fetch-Site, Pragma, X-Api-Key",
-                ),
+                HeaderValue::from_static("Authorization, Content-Type"),
             );
         }
     }
 
     res.headers_mut().insert(
         header::ACCESS_CONTROL_MAX_AGE,
-        HeaderValue::from_static("3600"),
+        HeaderValue::from(cors.max_age.unwrap_or(DEFAULT_MAX_AGE)),
     );
 
     // Add Vary header, indicating response will vary based on these request headers
     res.headers_mut().insert(
         header::VARY,
-        HeaderValue::from_static("Access-Control-Request-Method, Access-Control-Request-Headers"),
+        HeaderValue::from_static(
+            "Origin, Access-Control-Request-Method, Access-Control-Request-Headers",
+        ),
     );
 
     // Set correct status code: 204 No Content
     res.status_code(StatusCode::NO_CONTENT);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_allowlist_matches_exact_and_wildcard_entries() {
+        let cors = CorsConfig {
+            allowed_origins: vec![
+                "https://chat.example.com".to_string(),
+                "https://*.example.org".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert!(is_allowed_origin(&cors, "https://chat.example.com"));
+        assert!(is_allowed_origin(&cors, "https://app.example.org"));
+        assert!(!is_allowed_origin(&cors, "https://evil.com"));
+        assert!(!is_allowed_origin(
+            &CorsConfig::default(),
+            "https://chat.example.com"
+        ));
+
+        let headers = allowed_headers(&cors);
+        assert!(is_safe_header("X-Stainless-Lang", &headers));
+        assert!(!is_safe_header("Cookie", &headers));
+        assert!(!is_safe_header("X-Custom", &["Authorization".to_string()]));
+    }
+}
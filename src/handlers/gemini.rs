@@ -0,0 +1,790 @@
+use super::chat::{extract_access_key, start_chat};
+use super::limit::UsageMeter;
+use crate::cache::get_cached_config;
+use crate::evert::{
+    EventDelta, EventStream, OutputCut, OutputLimit, collect_events, delta_stream, finalize_content,
+};
+use crate::policy::CredentialPolicy;
+use crate::types::*;
+use crate::utils::{
+    count_completion_tokens, format_duration, pretty_json_truncated, redact_headers,
+    redact_json_fields,
+};
+use futures_util::future;
+use futures_util::stream::{self, StreamExt};
+use nanoid::nanoid;
+use poe_api_process::types::{ChatTool, ChatToolCall};
+use salvo::http::header;
+use salvo::prelude::*;
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::time::Instant;
+use tracing::{debug, error, info, warn};
+
+#[handler]
+pub async fn gemini_generate_content(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let start_time = Instant::now();
+
+    // Structure request/response logging with separator
+    debug!("------ Incoming Request [POST] {} ------", req.uri());
+
+    // Log inbound request metadata with redacted headers
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let redacted_headers = redact_headers(req.headers());
+
+    debug!(
+        "📝 Request metadata | Method: {} | Path: {} | Headers: {:?}",
+        method, path, redacted_headers
+    );
+
+    // The path segment is "{model}:{action}"
+    let target = req.param::<String>("target").unwrap_or_default();
+    let Some((model, action)) = target.rsplit_once(':') else {
+        render_gemini_error(
+            res,
+            StatusCode::NOT_FOUND,
+            &format!("Unknown Gemini method: {}", target),
+        );
+        return;
+    };
+    let stream = match action {
+        "generateContent" => false,
+        "streamGenerateContent" => true,
+        _ => {
+            render_gemini_error(
+                res,
+                StatusCode::NOT_FOUND,
+                &format!("Unsupported Gemini method: {}", action),
+            );
+            return;
+        }
+    };
+    let model = model.to_string();
+    let sse = req.query::<String>("alt").as_deref() == Some("sse");
+
+    let max_size: usize = std::env::var("MAX_REQUEST_SIZE")
+        .unwrap_or_else(|_| "1073741824".to_string())
+        .parse()
+        .unwrap_or(1024 * 1024 * 1024);
+
+    let config = get_cached_config().await;
+
+    // Gemini clients send x-goog-api-key or ?key=, fall back to Bearer authorization
+    let access_key = match extract_access_key(req) {
+        Ok(key) => key,
+        Err(message) => {
+            error!("❌ {}", message);
+            render_gemini_error(res, StatusCode::UNAUTHORIZED, message);
+            return;
+        }
+    };
+
+    // Parse request body
+    let gemini_request = match req.payload_with_max_size(max_size).await {
+        Ok(bytes) => match serde_json::from_slice::<GeminiRequest>(bytes) {
+            Ok(parsed) => {
+                debug!(
+                    "📊 Gemini request parsed | Model: {} | Contents: {} | Stream: {}",
+                    model,
+                    parsed.contents.len(),
+                    stream
+                );
+                let request_value = serde_json::to_value(&parsed).unwrap_or_else(|_| json!(null));
+                let redacted_request = redact_json_fields(&request_value);
+                debug!(
+                    "📋 Request body (sanitized, truncated):\n{}",
+                    pretty_json_truncated(&redacted_request, 64 * 1024)
+                );
+                parsed
+            }
+            Err(e) => {
+                error!("❌ JSON parsing failed: {}", e);
+                render_gemini_error(
+                    res,
+                    StatusCode::BAD_REQUEST,
+                    &format!("JSON parsing failed: {}", e),
+                );
+                return;
+            }
+        },
+        Err(e) => {
+            error!("❌ Request size exceeded limit or read failed: {}", e);
+            render_gemini_error(
+                res,
+                StatusCode::PAYLOAD_TOO_LARGE,
+                &format!(
+                    "Request size exceeded limit ({} bytes) or read failed: {}",
+                    max_size, e
+                ),
+            );
+            return;
+        }
+    };
+
+    let include_thoughts = gemini_request
+        .generation_config
+        .as_ref()
+        .and_then(|c| c.thinking_config.as_ref())
+        .and_then(|t| t.include_thoughts)
+        .unwrap_or(false);
+    let chat_request = gemini_to_chat_request(&model, &gemini_request, stream);
+
+    let policy = depot.obtain::<CredentialPolicy>().ok();
+    match start_chat(&config, &access_key, &chat_request, policy).await {
+        Ok(started) => {
+            let limit = started.output_limit(&chat_request);
+            let mut state = GeminiStreamState::new(
+                started.display_model,
+                started.prompt_tokens,
+                include_thoughts,
+            );
+            state.usage_meter = depot.obtain::<UsageMeter>().cloned().unwrap_or_default();
+            state.limit = limit;
+            if stream {
+                handle_stream_response(res, started.event_stream, state, sse);
+            } else {
+                handle_non_stream_response(res, started.event_stream, state).await;
+            }
+        }
+        Err((status, error_response)) => {
+            render_gemini_error(res, status, &error_response.error.message);
+        }
+    }
+
+    let duration = start_time.elapsed();
+    info!(
+        "✅ Gemini request processing completed | Duration: {}",
+        format_duration(duration)
+    );
+}
+
+// With ?alt=sse chunks are sent as SSE events, otherwise as a streamed JSON array
+fn handle_stream_response(
+    res: &mut Response,
+    event_stream: EventStream,
+    state: GeminiStreamState,
+    sse: bool,
+) {
+    info!(
+        "🌊 Starting Gemini streaming response | Model: {} | SSE: {}",
+        state.model, sse
+    );
+
+    let content_type = if sse {
+        "text/event-stream"
+    } else {
+        "application/json"
+    };
+    res.headers_mut()
+        .insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+    res.headers_mut()
+        .insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
+    res.headers_mut()
+        .insert(header::CONNECTION, "keep-alive".parse().unwrap());
+
+    let mut first = true;
+    let events = delta_stream(event_stream)
+        .map(Some)
+        .chain(stream::once(future::ready(None)))
+        .scan(state, move |state, delta| {
+            if state.finished {
+                return future::ready(None);
+            }
+            let Some(delta) = delta else {
+                // Upstream ended without Done or an error, the JSON array still has to be closed
+                state.finished = true;
+                let closing = match (sse, first) {
+                    (true, _) => "",
+                    (false, true) => "[]",
+                    (false, false) => "]",
+                };
+                return future::ready(Some(Ok(closing.to_string())));
+            };
+            let Some(chunk) = state.render(delta) else {
+                return future::ready(Some(Ok::<String, Infallible>(String::new())));
+            };
+            if sse {
+                return future::ready(Some(Ok(format!("data: {}\n\n", chunk))));
+            }
+            let separator = if first { "[" } else { ",\n" };
+            first = false;
+            let closing = if state.finished { "]" } else { "" };
+            future::ready(Some(Ok(format!("{}{}{}", separator, chunk, closing))))
+        });
+
+    let body = events.filter(|result| {
+        future::ready(match result {
+            Ok(s) => !s.is_empty(),
+            Err(_) => true,
+        })
+    });
+    res.stream(body);
+}
+
+async fn handle_non_stream_response(
+    res: &mut Response,
+    event_stream: EventStream,
+    mut state: GeminiStreamState,
+) {
+    let mut ctx = match collect_events(event_stream).await {
+        Ok(ctx) => ctx,
+        Err((status, error_response)) => {
+            render_gemini_error(res, status, &error_response.error.message);
+            return;
+        }
+    };
+
+    let (text, cut) = std::mem::take(&mut state.limit).apply(&finalize_content(&mut ctx));
+    let output_tokens = count_completion_tokens(&text);
+    state
+        .usage_meter
+        .record(&state.model, state.prompt_tokens, output_tokens);
+
+    let mut parts = Vec::new();
+    if state.include_thoughts && !ctx.reasoning_content.trim().is_empty() {
+        parts.push(GeminiPart {
+            text: Some(ctx.reasoning_content.clone()),
+            thought: Some(true),
+            ..Default::default()
+        });
+    }
+    if !text.is_empty() {
+        parts.push(GeminiPart {
+            text: Some(text),
+            ..Default::default()
+        });
+    }
+    for tool_call in &ctx.tool_calls {
+        parts.push(function_call_part(tool_call));
+    }
+
+    let response = state.response(
+        parts,
+        Some(finish_reason(cut.as_ref())),
+        Some(output_tokens),
+    );
+    let response_value = serde_json::to_value(&response).unwrap_or_else(|_| json!(null));
+    debug!(
+        "📤 Response body (sanitized, truncated):\n{}",
+        pretty_json_truncated(&redact_json_fields(&response_value), 64 * 1024)
+    );
+    debug!("------ Outgoing Response [200] /v1beta/models ------");
+
+    res.render(Json(response));
+}
+
+// Translates deltas into GenerateContentResponse chunks
+struct GeminiStreamState {
+    model: String,
+    prompt_tokens: u32,
+    include_thoughts: bool,
+    output_text: String,
+    usage_meter: UsageMeter,
+    limit: OutputLimit,
+    finished: bool,
+}
+
+impl GeminiStreamState {
+    fn new(model: String, prompt_tokens: u32, include_thoughts: bool) -> Self {
+        Self {
+            model,
+            prompt_tokens,
+            include_thoughts,
+            output_text: String::new(),
+            usage_meter: UsageMeter::default(),
+            limit: OutputLimit::default(),
+            finished: false,
+        }
+    }
+
+    fn response(
+        &self,
+        parts: Vec<GeminiPart>,
+        finish_reason: Option<&str>,
+        output_tokens: Option<u32>,
+    ) -> GeminiResponse {
+        GeminiResponse {
+            candidates: vec![GeminiCandidate {
+                content: GeminiContent {
+                    role: Some("model".to_string()),
+                    parts,
+                },
+                finish_reason: finish_reason.map(|r| r.to_string()),
+                index: 0,
+            }],
+            usage_metadata: output_tokens.map(|output_tokens| GeminiUsageMetadata {
+                prompt_token_count: self.prompt_tokens,
+                candidates_token_count: output_tokens,
+                total_token_count: self.prompt_tokens + output_tokens,
+            }),
+            model_version: self.model.clone(),
+        }
+    }
+
+    fn render(&mut self, delta: EventDelta) -> Option<String> {
+        let response = match delta {
+            EventDelta::Reasoning(thinking) => {
+                if !self.include_thoughts {
+                    return None;
+                }
+                let part = GeminiPart {
+                    text: Some(thinking),
+                    thought: Some(true),
+                    ..Default::default()
+                };
+                self.response(vec![part], None, None)
+            }
+            EventDelta::Content(text) => {
+                let text = self.limit.push(&text);
+                if self.limit.cut().is_some() {
+                    return Some(self.finish(text));
+                }
+                if text.is_empty() {
+                    return None;
+                }
+                self.output_text.push_str(&text);
+                let part = GeminiPart {
+                    text: Some(text),
+                    ..Default::default()
+                };
+                self.response(vec![part], None, None)
+            }
+            EventDelta::ToolCalls(tool_calls) => {
+                let parts = tool_calls
+                    .iter()
+                    .map(|tool_call| function_call_part(&tool_call.call))
+                    .collect();
+                self.response(parts, None, None)
+            }
+            EventDelta::Error(status, error_response) => {
+                debug!("❌ Detected error, interrupting Gemini stream");
+                self.finished = true;
+                return Some(gemini_error(status, &error_response.error.message).to_string());
+            }
+            EventDelta::Done => {
+                let text = self.limit.finish();
+                return Some(self.finish(text));
+            }
+        };
+        Some(serde_json::to_string(&response).unwrap())
+    }
+
+    // Last chunk with the remaining text, finish reason and usage, nothing is sent after this
+    fn finish(&mut self, text: String) -> String {
+        self.finished = true;
+        self.output_text.push_str(&text);
+        let output_tokens = count_completion_tokens(&self.output_text);
+        debug!(
+            "📊 Token usage statistics | prompt_tokens: {} | candidates_tokens: {}",
+            self.prompt_tokens, output_tokens
+        );
+        self.usage_meter
+            .record(&self.model, self.prompt_tokens, output_tokens);
+        let part = GeminiPart {
+            text: Some(text),
+            ..Default::default()
+        };
+        let response = self.response(
+            vec![part],
+            Some(finish_reason(self.limit.cut())),
+            Some(output_tokens),
+        );
+        serde_json::to_string(&response).unwrap()
+    }
+}
+
+fn finish_reason(cut: Option<&OutputCut>) -> &'static str {
+    match cut {
+        Some(OutputCut::MaxTokens) => "MAX_TOKENS",
+        _ => "STOP",
+    }
+}
+
+fn function_call_part(tool_call: &ChatToolCall) -> GeminiPart {
+    let arguments = &tool_call.function.arguments;
+    let args = if arguments.trim().is_empty() {
+        json!({})
+    } else {
+        serde_json::from_str(arguments).unwrap_or_else(|e| {
+            warn!(
+                "⚠️ Tool arguments are not valid JSON, wrapping as string: {}",
+                e
+            );
+            json!({ "arguments": arguments })
+        })
+    };
+    GeminiPart {
+        function_call: Some(GeminiFunctionCall {
+            id: Some(tool_call.id.clone()),
+            name: tool_call.function.name.clone(),
+            args,
+        }),
+        ..Default::default()
+    }
+}
+
+fn gemini_error(status: StatusCode, message: &str) -> Value {
+    let error_status = match status.as_u16() {
+        400 | 413 => "INVALID_ARGUMENT",
+        401 => "UNAUTHENTICATED",
+        403 => "PERMISSION_DENIED",
+        404 => "NOT_FOUND",
+        429 => "RESOURCE_EXHAUSTED",
+        503 => "UNAVAILABLE",
+        _ => "INTERNAL",
+    };
+    json!({
+        "error": {
+            "code": status.as_u16(),
+            "message": message,
+            "status": error_status
+        }
+    })
+}
+
+fn render_gemini_error(res: &mut Response, status: StatusCode, message: &str) {
+    debug!(
+        "------ Outgoing Response [{}] /v1beta/models ------",
+        status.as_u16()
+    );
+    res.status_code(status);
+    res.render(Json(gemini_error(status, message)));
+}
+
+/// Convert a Gemini generateContent request into the internal chat completion request
+pub(crate) fn gemini_to_chat_request(
+    model: &str,
+    request: &GeminiRequest,
+    stream: bool,
+) -> ChatCompletionRequest {
+    let mut messages = Vec::new();
+
+    if let Some(system) = &request.system_instruction {
+        let system_text = system
+            .parts
+            .iter()
+            .filter_map(|part| part.text.clone())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if !system_text.is_empty() {
+            messages.push(Message {
+                role: "system".to_string(),
+                content: Some(OpenAiContent::Text(system_text)),
+                ..Default::default()
+            });
+        }
+    }
+
+    // Gemini matches function responses to calls by name, OpenAI by id
+    let mut pending_calls: HashMap<String, VecDeque<String>> = HashMap::new();
+    for content in &request.contents {
+        messages.extend(convert_parts(content, &mut pending_calls));
+    }
+
+    let tools: Option<Vec<ChatTool>> = request.tools.as_ref().map(|tools| {
+        tools
+            .iter()
+            .filter_map(|tool| tool.function_declarations.as_ref())
+            .flatten()
+            .filter_map(|declaration| {
+                let parameters = declaration
+                    .parameters
+                    .clone()
+                    .or_else(|| declaration.parameters_json_schema.clone())
+                    .map(lowercase_schema_types)
+                    .unwrap_or_else(|| json!({"type": "object", "properties": {}}));
+                let value = json!({
+                    "type": "function",
+                    "function": {
+                        "name": declaration.name,
+                        "description": declaration.description,
+                        "parameters": parameters
+                    }
+                });
+                match serde_json::from_value::<ChatTool>(value) {
+                    Ok(chat_tool) => Some(chat_tool),
+                    Err(e) => {
+                        warn!("⚠️ Skipping Gemini function '{}': {}", declaration.name, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    });
+    let tools = tools.filter(|tools| !tools.is_empty());
+
+    let tool_choice = request
+        .tool_config
+        .as_ref()
+        .and_then(|config| config.get("functionCallingConfig"))
+        .and_then(gemini_tool_choice);
+
+    let generation_config = request.generation_config.as_ref();
+    let thinking_budget = generation_config
+        .and_then(|c| c.thinking_config.as_ref())
+        .and_then(|t| t.thinking_budget);
+    let extra_body = thinking_budget.map(|budget| ExtraBody {
+        google: Some(GoogleConfig {
+            thinking_config: Some(GoogleThinkingConfig {
+                thinking_budget: Some(budget),
+            }),
+        }),
+    });
+    let response_format = generation_config.and_then(|c| {
+        if let Some(schema) = &c.response_schema {
+            Some(json!({
+                "type": "json_schema",
+                "json_schema": {"name": "response", "schema": lowercase_schema_types(schema.clone())}
+            }))
+        } else {
+            (c.response_mime_type.as_deref() == Some("application/json"))
+                .then(|| json!({"type": "json_object"}))
+        }
+    });
+
+    ChatCompletionRequest {
+        model: model.to_string(),
+        messages,
+        temperature: generation_config.and_then(|c| c.temperature),
+        top_p: generation_config.and_then(|c| c.top_p),
+        max_tokens: generation_config.and_then(|c| c.max_output_tokens),
+        stop: generation_config.and_then(|c| c.stop_sequences.clone()),
+        stream: Some(stream),
+        tools,
+        tool_choice,
+        response_format,
+        extra_body,
+        ..Default::default()
+    }
+}
+
+// Map functionCallingConfig ({"mode": "AUTO" | "ANY" | "NONE", "allowedFunctionNames"}) to OpenAI format
+fn gemini_tool_choice(config: &Value) -> Option<Value> {
+    let allowed: Vec<&str> = config
+        .get("allowedFunctionNames")
+        .and_then(|v| v.as_array())
+        .map(|names| names.iter().filter_map(|n| n.as_str()).collect())
+        .unwrap_or_default();
+    match config.get("mode").and_then(|m| m.as_str()) {
+        Some("AUTO") => Some(json!("auto")),
+        Some("NONE") => Some(json!("none")),
+        Some("ANY") if allowed.len() == 1 => {
+            Some(json!({"type": "function", "function": {"name": allowed[0]}}))
+        }
+        Some("ANY") => Some(json!("required")),
+        _ => None,
+    }
+}
+
+// Gemini schemas use upper-case OpenAPI type names ("OBJECT", "STRING")
+fn lowercase_schema_types(mut schema: Value) -> Value {
+    match &mut schema {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if key == "type" {
+                    if let Value::String(t) = value {
+                        *t = t.to_lowercase();
+                    }
+                } else {
+                    *value = lowercase_schema_types(value.take());
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                *item = lowercase_schema_types(item.take());
+            }
+        }
+        _ => {}
+    }
+    schema
+}
+
+// Convert one Gemini content into OpenAI-style messages.
+// functionResponse parts become separate tool messages placed before the remaining content.
+fn convert_parts(
+    content: &GeminiContent,
+    pending_calls: &mut HashMap<String, VecDeque<String>>,
+) -> Vec<Message> {
+    let role = match content.role.as_deref() {
+        Some("model") => "assistant",
+        _ => "user",
+    };
+    let mut items: Vec<OpenAiContentItem> = Vec::new();
+    let mut tool_calls: Vec<ChatToolCall> = Vec::new();
+    let mut converted = Vec::new();
+
+    for part in &content.parts {
+        if part.thought == Some(true) {
+            debug!("🧠 Skipping thought part from conversation history");
+            continue;
+        }
+        if let Some(text) = &part.text {
+            items.push(OpenAiContentItem::Text {
+                r#type: Some("text".to_string()),
+                text: text.clone(),
+                extra: HashMap::new(),
+            });
+        } else if let Some(blob) = &part.inline_data {
+            items.push(image_url_item(format!(
+                "data:{};base64,{}",
+                blob.mime_type, blob.data
+            )));
+        } else if let Some(file) = &part.file_data {
+            items.push(image_url_item(file.file_uri.clone()));
+        } else if let Some(call) = &part.function_call {
+            let id = call
+                .id
+                .clone()
+                .unwrap_or_else(|| format!("call_{}", nanoid!(24)));
+            pending_calls
+                .entry(call.name.clone())
+                .or_default()
+                .push_back(id.clone());
+            let arguments = if call.args.is_null() {
+                "{}".to_string()
+            } else {
+                call.args.to_string()
+            };
+            match serde_json::from_value::<ChatToolCall>(json!({
+                "id": id,
+                "type": "function",
+                "function": {"name": call.name, "arguments": arguments}
+            })) {
+                Ok(tool_call) => tool_calls.push(tool_call),
+                Err(e) => warn!("⚠️ Skipping functionCall part {}: {}", call.name, e),
+            }
+        } else if let Some(response) = &part.function_response {
+            let queued = pending_calls
+                .get_mut(&response.name)
+                .and_then(|ids| ids.pop_front());
+            let id = response
+                .id
+                .clone()
+                .or(queued)
+                .unwrap_or_else(|| format!("call_{}", nanoid!(24)));
+            converted.push(Message {
+                role: "tool".to_string(),
+                content: Some(OpenAiContent::Text(response.response.to_string())),
+                tool_call_id: Some(id),
+                ..Default::default()
+            });
+        } else {
+            debug!("🔍 Skipping unsupported Gemini part");
+        }
+    }
+
+    if !items.is_empty() || !tool_calls.is_empty() {
+        converted.push(Message {
+            role: role.to_string(),
+            content: if items.is_empty() {
+                None
+            } else {
+                Some(OpenAiContent::Multi(items))
+            },
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+            ..Default::default()
+        });
+    }
+
+    converted
+}
+
+fn image_url_item(url: String) -> OpenAiContentItem {
+    OpenAiContentItem::ImageUrl {
+        r#type: Some("image_url".to_string()),
+        image_url: ImageUrlContent {
+            url,
+            extra: HashMap::new(),
+        },
+        extra: HashMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_function_calls_and_thinking_budget() {
+        let payload = json!({
+            "systemInstruction": {"parts": [{"text": "Be brief"}]},
+            "contents": [
+                {"role": "user", "parts": [
+                    {"text": "Weather in Paris?"},
+                    {"inlineData": {"mimeType": "image/png", "data": "AAAA"}}
+                ]},
+                {"role": "model", "parts": [
+                    {"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}}
+                ]},
+                {"role": "user", "parts": [
+                    {"functionResponse": {"name": "get_weather", "response": {"sky": "sunny"}}}
+                ]}
+            ],
+            "tools": [{"functionDeclarations": [{
+                "name": "get_weather",
+                "parameters": {"type": "OBJECT", "properties": {"city": {"type": "STRING"}}}
+            }]}],
+            "toolConfig": {"functionCallingConfig": {"mode": "ANY"}},
+            "generationConfig": {"maxOutputTokens": 256, "thinkingConfig": {"thinkingBudget": 1024}}
+        });
+
+        let request: GeminiRequest = serde_json::from_value(payload).expect("valid request");
+        let chat_request = gemini_to_chat_request("gemini-2.5-pro", &request, false);
+
+        let roles: Vec<&str> = chat_request
+            .messages
+            .iter()
+            .map(|m| m.role.as_str())
+            .collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "tool"]);
+
+        let call_id = &chat_request.messages[2].tool_calls.as_ref().unwrap()[0].id;
+        assert_eq!(
+            chat_request.messages[3].tool_call_id.as_ref(),
+            Some(call_id)
+        );
+        assert_eq!(chat_request.tool_choice, Some(json!("required")));
+        assert_eq!(chat_request.max_tokens, Some(256));
+        let budget = chat_request
+            .extra_body
+            .and_then(|b| b.google)
+            .and_then(|g| g.thinking_config)
+            .and_then(|t| t.thinking_budget);
+        assert_eq!(budget, Some(1024));
+    }
+
+    #[test]
+    fn stream_state_hides_thoughts_unless_requested() {
+        let mut state = GeminiStreamState::new("gemini".to_string(), 3, false);
+        assert!(
+            state
+                .render(EventDelta::Reasoning("hmm".to_string()))
+                .is_none()
+        );
+        let text = state.render(EventDelta::Content("Hi".to_string())).unwrap();
+        assert!(text.contains("\"role\":\"model\""));
+        assert!(!text.contains("usageMetadata"));
+        let done = state.render(EventDelta::Done).unwrap();
+        assert!(done.contains("\"finishReason\":\"STOP\""));
+        assert!(done.contains("\"promptTokenCount\":3"));
+    }
+
+    #[test]
+    fn stream_state_reports_max_tokens() {
+        let mut state = GeminiStreamState::new("gemini".to_string(), 3, false);
+        state.limit = OutputLimit::new(&[], Some(1));
+        let chunk = state
+            .render(EventDelta::Content("one two three".to_string()))
+            .unwrap();
+        assert!(chunk.contains("\"finishReason\":\"MAX_TOKENS\""));
+        assert!(chunk.contains("\"candidatesTokenCount\":1"));
+        assert!(state.finished);
+    }
+}
@@ -0,0 +1,352 @@
+use super::chat::{extract_access_key, start_chat};
+use super::limit::UsageMeter;
+use crate::cache::get_cached_config;
+use crate::evert::{collect_events, finalize_content};
+use crate::policy::CredentialPolicy;
+use crate::types::*;
+use crate::utils::{
+    download_file, format_duration, image_generation_suffixes, is_poe_cdn_url,
+    pretty_json_truncated, redact_headers, redact_json_fields,
+};
+use base64::prelude::*;
+use chrono::Utc;
+use futures_util::future::join_all;
+use regex::Regex;
+use salvo::prelude::*;
+use serde_json::json;
+use std::time::Instant;
+use tracing::{debug, error, info, warn};
+
+// Largest generated image downloaded for b64_json
+const MAX_IMAGE_SIZE: usize = 50 * 1024 * 1024;
+
+#[handler]
+pub async fn image_generations(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let start_time = Instant::now();
+
+    // Structure request/response logging with separator
+    debug!("------ Incoming Request [POST] {} ------", req.uri());
+
+    // Log inbound request metadata with redacted headers
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let redacted_headers = redact_headers(req.headers());
+
+    debug!(
+        "📝 Request metadata | Method: {} | Path: {} | Headers: {:?}",
+        method, path, redacted_headers
+    );
+
+    let max_size: usize = std::env::var("MAX_REQUEST_SIZE")
+        .unwrap_or_else(|_| "1073741824".to_string())
+        .parse()
+        .unwrap_or(1024 * 1024 * 1024);
+
+    let config = get_cached_config().await;
+
+    // Validate authorization
+    let access_key = match extract_access_key(req) {
+        Ok(key) => key,
+        Err(message) => {
+            error!("❌ {}", message);
+            res.status_code(StatusCode::UNAUTHORIZED);
+            res.render(Json(json!({ "error": message })));
+            return;
+        }
+    };
+
+    // Parse request body
+    let image_request = match req.payload_with_max_size(max_size).await {
+        Ok(bytes) => match serde_json::from_slice::<ImageGenerationRequest>(bytes) {
+            Ok(parsed) => {
+                let request_value = serde_json::to_value(&parsed).unwrap_or_else(|_| json!(null));
+                let redacted_request = redact_json_fields(&request_value);
+                debug!(
+                    "📋 Request body (sanitized, truncated):\n{}",
+                    pretty_json_truncated(&redacted_request, 64 * 1024)
+                );
+                parsed
+            }
+            Err(e) => {
+                error!("❌ JSON parsing failed: {}", e);
+                res.status_code(StatusCode::BAD_REQUEST);
+                res.render(Json(
+                    json!({ "error": format!("JSON parsing failed: {}", e) }),
+                ));
+                return;
+            }
+        },
+        Err(e) => {
+            error!("❌ Request size exceeded limit or read failed: {}", e);
+            res.status_code(StatusCode::PAYLOAD_TOO_LARGE);
+            res.render(Json(json!({
+                "error": format!("Request size exceeded limit ({} bytes) or read failed: {}", max_size, e)
+            })));
+            return;
+        }
+    };
+
+    let (n, response_format) = match image_options(&image_request) {
+        Ok(options) => options,
+        Err((status, error_response)) => {
+            error!(
+                "❌ Image generation failed: {}",
+                error_response.error.message
+            );
+            res.status_code(status);
+            res.render(Json(error_response));
+            return;
+        }
+    };
+
+    let model = image_request.model.clone().unwrap_or_else(|| {
+        std::env::var("DEFAULT_IMAGE_MODEL").unwrap_or_else(|_| "gpt-image-1".to_string())
+    });
+    let suffixes = image_generation_suffixes(
+        image_request.size.as_deref(),
+        image_request.quality.as_deref(),
+    );
+    let chat_request = ChatCompletionRequest {
+        model: model.clone(),
+        messages: vec![Message {
+            role: "user".to_string(),
+            content: Some(OpenAiContent::Text(format!(
+                "{}{}",
+                image_request.prompt, suffixes
+            ))),
+            ..Default::default()
+        }],
+        user: image_request.user.clone(),
+        ..Default::default()
+    };
+
+    info!(
+        "🎨 Generating images | Model: {} | n: {} | Format: {}",
+        model, n, response_format
+    );
+
+    // Each image is a separate bot call, run them concurrently
+    let usage_meter = depot.obtain::<UsageMeter>().cloned().unwrap_or_default();
+    let policy = depot.obtain::<CredentialPolicy>().ok();
+    let results =
+        join_all((0..n).map(|_| {
+            generate_image_urls(&config, &access_key, &chat_request, policy, &usage_meter)
+        }))
+        .await;
+
+    let mut urls = Vec::new();
+    for result in results {
+        match result {
+            Ok(image_urls) => urls.extend(image_urls),
+            Err((status, error_response)) => {
+                res.status_code(status);
+                res.render(Json(error_response));
+                return;
+            }
+        }
+    }
+
+    // A bot may return several images per call, the client asked for n
+    urls.truncate(n as usize);
+    if urls.is_empty() {
+        render_image_error(
+            res,
+            StatusCode::BAD_GATEWAY,
+            "api_error",
+            "no_image_generated",
+            "The image bot did not return any images",
+            None,
+        );
+        return;
+    }
+
+    let mut data = Vec::with_capacity(urls.len());
+    for url in urls {
+        if response_format == "b64_json" {
+            match download_file(&url, MAX_IMAGE_SIZE).await {
+                Ok((bytes, _)) => data.push(ImageData {
+                    url: None,
+                    b64_json: Some(BASE64_STANDARD.encode(bytes)),
+                    revised_prompt: None,
+                }),
+                Err(e) => {
+                    error!("❌ Failed to download generated image: {}", e);
+                    render_image_error(
+                        res,
+                        StatusCode::BAD_GATEWAY,
+                        "api_error",
+                        "image_download_failed",
+                        &format!("Failed to download generated image: {}", e),
+                        None,
+                    );
+                    return;
+                }
+            }
+        } else {
+            data.push(ImageData {
+                url: Some(url),
+                b64_json: None,
+                revised_prompt: None,
+            });
+        }
+    }
+
+    let response = ImageGenerationResponse {
+        created: Utc::now().timestamp(),
+        data,
+    };
+    debug!("------ Outgoing Response [200] /v1/images/generations ------");
+    res.render(Json(response));
+
+    let duration = start_time.elapsed();
+    info!(
+        "✅ Image generation completed | Duration: {}",
+        format_duration(duration)
+    );
+}
+
+// Run one bot call and collect the image URLs it returned
+async fn generate_image_urls(
+    config: &Config,
+    access_key: &str,
+    chat_request: &ChatCompletionRequest,
+    policy: Option<&CredentialPolicy>,
+    usage_meter: &UsageMeter,
+) -> Result<Vec<String>, (StatusCode, OpenAIErrorResponse)> {
+    let started = start_chat(config, access_key, chat_request, policy).await?;
+    let mut ctx = collect_events(started.event_stream).await?;
+    // Images are not counted as completion tokens
+    usage_meter.record(&started.display_model, started.prompt_tokens, 0);
+
+    let mut urls: Vec<String> = ctx
+        .file_refs
+        .values()
+        .map(|file| file.url.clone())
+        .collect();
+    if urls.is_empty() {
+        // Some bots reply with markdown images instead of File events
+        let content = finalize_content(&mut ctx);
+        urls = extract_markdown_image_urls(&content);
+        if urls.is_empty() {
+            warn!("⚠️ No image found in bot reply: {}", content);
+        }
+    }
+    debug!("🖼️ Collected {} image URL(s)", urls.len());
+    Ok(urls)
+}
+
+// Images in the reply text are only taken from the Poe CDN, other hosts are never fetched server-side
+fn extract_markdown_image_urls(content: &str) -> Vec<String> {
+    let re = Regex::new(r"!\[[^\]]*\]\((https?://[^)\s]+)\)").unwrap();
+    re.captures_iter(content)
+        .map(|caps| caps[1].to_string())
+        .filter(|url| is_poe_cdn_url(url))
+        .collect()
+}
+
+// Validated n and response_format of an image request
+fn image_options(
+    request: &ImageGenerationRequest,
+) -> Result<(u32, &str), (StatusCode, OpenAIErrorResponse)> {
+    let n = request.n.unwrap_or(1);
+    if !(1..=10).contains(&n) {
+        return Err(image_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            "invalid_value",
+            "n must be between 1 and 10",
+            Some("n"),
+        ));
+    }
+    let response_format = request.response_format.as_deref().unwrap_or("url");
+    if response_format != "url" && response_format != "b64_json" {
+        return Err(image_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            "invalid_value",
+            "response_format must be 'url' or 'b64_json'",
+            Some("response_format"),
+        ));
+    }
+    Ok((n, response_format))
+}
+
+fn image_error(
+    status: StatusCode,
+    error_type: &str,
+    code: &str,
+    message: &str,
+    param: Option<&str>,
+) -> (StatusCode, OpenAIErrorResponse) {
+    (
+        status,
+        OpenAIErrorResponse {
+            error: OpenAIError {
+                message: message.to_string(),
+                r#type: error_type.to_string(),
+                code: code.to_string(),
+                param: param.map(|p| p.to_string()),
+            },
+        },
+    )
+}
+
+fn render_image_error(
+    res: &mut Response,
+    status: StatusCode,
+    error_type: &str,
+    code: &str,
+    message: &str,
+    param: Option<&str>,
+) {
+    error!("❌ Image generation failed: {}", message);
+    let (status, error_response) = image_error(status, error_type, code, message, param);
+    res.status_code(status);
+    res.render(Json(error_response));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_image_urls_are_limited_to_the_poe_cdn() {
+        let content = "Here you go:\n![cat](https://pfst.cf2.poecdn.net/base/image/cat.png)\n\
+            ![meta](http://169.254.169.254/latest/meta-data)\n\
+            ![dog](https://pfst.cf2.poecdn.net/base/image/dog.webp \"dog\")\n\
+            [link](https://pfst.cf2.poecdn.net/base/image/link.png)";
+        assert_eq!(
+            extract_markdown_image_urls(content),
+            vec!["https://pfst.cf2.poecdn.net/base/image/cat.png".to_string()]
+        );
+    }
+
+    #[test]
+    fn image_options_validate_n_and_response_format() {
+        let request = |value: serde_json::Value| -> ImageGenerationRequest {
+            serde_json::from_value(value).expect("valid request")
+        };
+
+        let defaults = request(json!({"prompt": "a cat"}));
+        assert_eq!(image_options(&defaults).ok(), Some((1, "url")));
+
+        let b64 = request(json!({"prompt": "a cat", "n": 10, "response_format": "b64_json"}));
+        assert_eq!(image_options(&b64).ok(), Some((10, "b64_json")));
+
+        for n in [0, 11] {
+            let (status, error_response) =
+                image_options(&request(json!({"prompt": "a cat", "n": n}))).unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(error_response.error.param.as_deref(), Some("n"));
+        }
+
+        let (_, error_response) = image_options(&request(
+            json!({"prompt": "a cat", "response_format": "png"}),
+        ))
+        .unwrap_err();
+        assert_eq!(
+            error_response.error.param.as_deref(),
+            Some("response_format")
+        );
+    }
+}
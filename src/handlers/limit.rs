@@ -1,27 +1,374 @@
+use super::access::client_ip;
+use super::chat::{presented_credential, resolve_model};
+use crate::cache::get_cached_config;
+use crate::keys::{API_KEY_PREFIX, ApiKeyRecord, find_api_key};
+use crate::types::{Config, KeyLimits, OpenAIError, OpenAIErrorResponse};
+use crate::utils::{count_tokens, hash_token};
+use crate::{policy, usage};
+use salvo::http::header::HeaderName;
+use salvo::http::{HeaderValue, StatusCode, header};
 use salvo::prelude::*;
-use std::sync::Arc;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
-use tokio::time::sleep;
-use tracing::debug;
-
-// Global variable, will be initialized in main.rs
-pub static GLOBAL_RATE_LIMITER: tokio::sync::OnceCell<Arc<Mutex<Instant>>> =
-    tokio::sync::OnceCell::const_new();
-
-/// Get rate limit interval (milliseconds)
-/// Returns None to indicate rate limiting is disabled
-fn get_rate_limit_ms() -> Option<Duration> {
-    let ms = std::env::var("RATE_LIMIT_MS")
+use tracing::{debug, warn};
+
+// Idle buckets are dropped once the table grows past this size
+const MAX_BUCKETS: usize = 10_000;
+const IDLE_BUCKET_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LimitKind {
+    Requests,
+    Tokens,
+}
+
+impl LimitKind {
+    fn name(self) -> &'static str {
+        match self {
+            LimitKind::Requests => "requests",
+            LimitKind::Tokens => "tokens",
+        }
+    }
+
+    fn unit(self) -> &'static str {
+        match self {
+            LimitKind::Requests => "requests per min (RPM)",
+            LimitKind::Tokens => "tokens per min (TPM)",
+        }
+    }
+}
+
+// Token bucket that refills continuously at per_minute / 60 per second
+struct Bucket {
+    available: f64,
+    updated: Instant,
+}
+
+// One per-minute limit that applies to the current request
+struct Limit {
+    key: String,
+    kind: LimitKind,
+    per_minute: u32,
+}
+
+// Bucket state reported in the x-ratelimit-* headers
+struct LimitState {
+    kind: LimitKind,
+    per_minute: u32,
+    remaining: f64,
+    reset: Duration,
+}
+
+struct Rejection {
+    kind: LimitKind,
+    per_minute: u32,
+    retry_after: Duration,
+}
+
+struct Admission {
+    states: Vec<LimitState>,
+    rejection: Option<Rejection>,
+}
+
+static BUCKETS: OnceLock<Mutex<HashMap<String, Bucket>>> = OnceLock::new();
+
+fn buckets() -> &'static Mutex<HashMap<String, Bucket>> {
+    BUCKETS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn refill(bucket: &mut Bucket, per_minute: u32, now: Instant) {
+    let capacity = f64::from(per_minute);
+    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+    bucket.available = (bucket.available + elapsed * capacity / 60.0).min(capacity);
+    bucket.updated = now;
+}
+
+// Time until the bucket holds `amount` again
+fn time_until(available: f64, amount: f64, per_minute: u32) -> Duration {
+    let missing = (amount - available).max(0.0);
+    Duration::from_secs_f64(missing * 60.0 / f64::from(per_minute))
+}
+
+// Check every bucket first, then take one request and the prompt tokens from all of them
+fn admit(limits: &[Limit], prompt_tokens: u32, now: Instant) -> Admission {
+    let mut table = buckets().lock().unwrap();
+    if table.len() > MAX_BUCKETS {
+        table.retain(|_, bucket| now.saturating_duration_since(bucket.updated) < IDLE_BUCKET_TTL);
+    }
+
+    let mut rejection: Option<Rejection> = None;
+    for limit in limits {
+        let bucket = table.entry(limit.key.clone()).or_insert_with(|| Bucket {
+            available: f64::from(limit.per_minute),
+            updated: now,
+        });
+        refill(bucket, limit.per_minute, now);
+        // Token buckets only need a positive balance, a long prompt may overdraw them
+        if bucket.available < 1.0 {
+            let retry_after = time_until(bucket.available, 1.0, limit.per_minute);
+            if rejection
+                .as_ref()
+                .is_none_or(|current| retry_after > current.retry_after)
+            {
+                rejection = Some(Rejection {
+                    kind: limit.kind,
+                    per_minute: limit.per_minute,
+                    retry_after,
+                });
+            }
+        }
+    }
+
+    if rejection.is_none() {
+        for limit in limits {
+            if let Some(bucket) = table.get_mut(&limit.key) {
+                bucket.available -= match limit.kind {
+                    LimitKind::Requests => 1.0,
+                    LimitKind::Tokens => f64::from(prompt_tokens),
+                };
+            }
+        }
+    }
+
+    let states = limits
+        .iter()
+        .filter_map(|limit| {
+            let bucket = table.get(&limit.key)?;
+            Some(LimitState {
+                kind: limit.kind,
+                per_minute: limit.per_minute,
+                remaining: bucket.available.max(0.0),
+                reset: time_until(
+                    bucket.available,
+                    f64::from(limit.per_minute),
+                    limit.per_minute,
+                ),
+            })
+        })
+        .collect();
+    Admission { states, rejection }
+}
+
+/// Client of one request, used to record its usage once a reply is complete
+#[derive(Clone, Debug, Default)]
+pub struct UsageMeter {
+    client: String,
+    // Tokens-per-minute buckets that still need the completion tokens
+    token_keys: Vec<String>,
+    // The request is counted with its first reply, so requests that fail
+    // authentication or upstream never reach the usage store
+    request_counted: Arc<AtomicBool>,
+}
+
+impl UsageMeter {
+    pub fn record(&self, model: &str, prompt_tokens: u32, completion_tokens: u32) {
+        if self.client.is_empty() {
+            return;
+        }
+        let count_request = !self.request_counted.swap(true, Ordering::Relaxed);
+        usage::record_reply(
+            &self.client,
+            model,
+            count_request,
+            prompt_tokens,
+            completion_tokens,
+        );
+        if self.token_keys.is_empty() || completion_tokens == 0 {
+            return;
+        }
+        let mut table = buckets().lock().unwrap();
+        for key in &self.token_keys {
+            if let Some(bucket) = table.get_mut(key) {
+                bucket.available -= f64::from(completion_tokens);
+            }
+        }
+        debug!(
+            "🪙 Completion tokens charged to rate limit | Tokens: {} | Buckets: {}",
+            completion_tokens,
+            self.token_keys.len()
+        );
+    }
+}
+
+/// Default per-client limit from the environment, unset or 0 disables it
+fn default_limit(var: &str) -> Option<u32> {
+    std::env::var(var)
         .ok()
-        .and_then(|s| s.parse::<u64>().ok())
-        .unwrap_or(100);
+        .and_then(|s| s.parse::<u32>().ok())
+        .filter(|v| *v > 0)
+}
+
+/// Hash of the presented bearer token, identifies the credential's policy and stored responses
+pub(crate) fn credential_hash(req: &Request) -> Option<String> {
+    presented_credential(req).map(|key| hash_token(&key))
+}
+
+/// Identity of a client for rate limits and usage: proxy API key id, Poe token hash, or client IP
+fn client_identity(req: &Request) -> (String, Option<ApiKeyRecord>) {
+    let Some(key) = presented_credential(req) else {
+        return (format!("ip:{}", client_ip(req)), None);
+    };
+    if let Some(record) = key
+        .starts_with(API_KEY_PREFIX)
+        .then(|| find_api_key(&key))
+        .flatten()
+    {
+        return (format!("key:{}", record.id), Some(record));
+    }
+    (format!("token:{}", &hash_token(&key)[..16]), None)
+}
+
+// Requested model and JSON body; Gemini carries the model in the path.
+// Any Content-Type is parsed as JSON, since handlers do not check it, except forms,
+// whose body must stay unread for the handler's form parser.
+async fn inspect_request(req: &mut Request) -> (Option<String>, Value) {
+    let path_model = req
+        .param::<String>("target")
+        .and_then(|target| target.split(':').next().map(|m| m.to_string()));
+    let is_form = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("multipart/") || v.contains("x-www-form-urlencoded"));
+    if is_form {
+        return (path_model, Value::Null);
+    }
+
+    let max_size: usize = std::env::var("MAX_REQUEST_SIZE")
+        .unwrap_or_else(|_| "1073741824".to_string())
+        .parse()
+        .unwrap_or(1024 * 1024 * 1024);
+    // The payload is cached on the request, so the handler can still parse it
+    let body: Value = match req.payload_with_max_size(max_size).await {
+        Ok(bytes) => serde_json::from_slice(bytes).unwrap_or_default(),
+        Err(e) => {
+            debug!("⚠️ Rate limiter could not read request body: {}", e);
+            Value::Null
+        }
+    };
+    let model = path_model.or_else(|| {
+        body.get("model")
+            .and_then(Value::as_str)
+            .map(|m| m.to_string())
+    });
+    (model, body)
+}
+
+// Gather the text of a request body for a prompt token estimate, skipping inline data
+fn collect_text(value: &Value, text: &mut String) {
+    match value {
+        Value::String(s) if !s.starts_with("data:") => {
+            text.push_str(s);
+            text.push('\n');
+        }
+        Value::Array(items) => items.iter().for_each(|item| collect_text(item, text)),
+        Value::Object(map) => map
+            .iter()
+            .filter(|(key, _)| !matches!(key.as_str(), "model" | "data"))
+            .for_each(|(_, item)| collect_text(item, text)),
+        _ => {}
+    }
+}
+
+// Per-client limits plus the limits of the requested model from models.yaml
+fn collect_limits(
+    config: &Config,
+    identity: &str,
+    key_limits: KeyLimits,
+    model: Option<&str>,
+) -> Vec<Limit> {
+    let mut limits = Vec::new();
+    let mut push = |key: String, kind: LimitKind, per_minute: Option<u32>| {
+        if let Some(per_minute) = per_minute.filter(|v| *v > 0) {
+            limits.push(Limit {
+                key,
+                kind,
+                per_minute,
+            });
+        }
+    };
+
+    // A key's own limit overrides the default, 0 lifts it
+    push(
+        format!("{}|rpm", identity),
+        LimitKind::Requests,
+        key_limits.rpm.or_else(|| default_limit("RATE_LIMIT_RPM")),
+    );
+    push(
+        format!("{}|tpm", identity),
+        LimitKind::Tokens,
+        key_limits.tpm.or_else(|| default_limit("RATE_LIMIT_TPM")),
+    );
 
-    // If value is 0, it means disable rate limiting
-    if ms == 0 {
-        None
+    if let Some(model) = model {
+        let (_, original_model) = resolve_model(config, model);
+        if let Some(model_config) = config.models.get(&original_model) {
+            push(
+                format!("{}|{}|rpm", identity, original_model),
+                LimitKind::Requests,
+                model_config.rpm,
+            );
+            push(
+                format!("{}|{}|tpm", identity, original_model),
+                LimitKind::Tokens,
+                model_config.tpm,
+            );
+        }
+    }
+    limits
+}
+
+// Reset durations in OpenAI's format, e.g. "250ms", "20s", "6m0s"
+fn format_reset(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        return format!("{}ms", duration.as_millis());
+    }
+    let secs = duration.as_secs_f64().ceil() as u64;
+    if secs < 60 {
+        format!("{}s", secs)
     } else {
-        Some(Duration::from_millis(ms))
+        format!("{}m{}s", secs / 60, secs % 60)
+    }
+}
+
+fn set_header(res: &mut Response, name: &str, value: String) {
+    if let (Ok(name), Ok(value)) = (
+        HeaderName::from_bytes(name.as_bytes()),
+        HeaderValue::from_str(&value),
+    ) {
+        res.headers_mut().insert(name, value);
+    }
+}
+
+// Report the tightest bucket of each kind
+fn set_rate_limit_headers(res: &mut Response, states: &[LimitState]) {
+    for kind in [LimitKind::Requests, LimitKind::Tokens] {
+        let Some(state) = states
+            .iter()
+            .filter(|s| s.kind == kind)
+            .min_by(|a, b| a.remaining.total_cmp(&b.remaining))
+        else {
+            continue;
+        };
+        let name = kind.name();
+        set_header(
+            res,
+            &format!("x-ratelimit-limit-{}", name),
+            state.per_minute.to_string(),
+        );
+        set_header(
+            res,
+            &format!("x-ratelimit-remaining-{}", name),
+            (state.remaining.floor() as u64).to_string(),
+        );
+        set_header(
+            res,
+            &format!("x-ratelimit-reset-{}", name),
+            format_reset(state.reset),
+        );
     }
 }
 
@@ -32,27 +379,175 @@ pub async fn rate_limit_middleware(
     res: &mut Response,
     ctrl: &mut FlowCtrl,
 ) {
-    // Get rate limit interval, None means disabled
-    if let Some(interval) = get_rate_limit_ms() {
-        if let Some(cell) = GLOBAL_RATE_LIMITER.get() {
-            let mut lock = cell.lock().await;
-            let now = Instant::now();
-            let elapsed = now.duration_since(*lock);
-
-            if elapsed < interval {
-                let wait = interval - elapsed;
-                debug!(
-                    "⏳ Request triggered global rate limit, delay {:?}, interval setting: {:?}",
-                    wait, interval
+    let (identity, record) = client_identity(req);
+    let key_limits = record
+        .as_ref()
+        .map(|record| record.limits)
+        .unwrap_or_default();
+    let config = get_cached_config().await;
+    // Limits and policies are per model, so the body is always inspected
+    let (model, body) = inspect_request(req).await;
+
+    // Reject denied models before they count against limits, start_chat enforces the whole policy
+    if let Some(policy) =
+        credential_hash(req).and_then(|credential| policy::load_policy(&credential))
+    {
+        if let Some(model) = &model {
+            let (_, original_model) = resolve_model(&config, model);
+            if let Err(violation) = policy.check_model(model, &original_model) {
+                warn!(
+                    "⛔ Model denied by policy | Client: {} | Model: {}",
+                    identity, original_model
                 );
-                sleep(wait).await;
+                let (status, error_response) = violation.into_response();
+                res.status_code(status);
+                res.render(Json(error_response));
+                ctrl.skip_rest();
+                return;
             }
-
-            *lock = Instant::now();
         }
+        depot.inject(policy);
+    }
+
+    if let Some(Err(reason)) = record
+        .as_ref()
+        .map(|record| usage::check_budget(&identity, &record.budget))
+    {
+        warn!("💸 Budget exhausted | Client: {} | {}", identity, reason);
+        res.status_code(StatusCode::TOO_MANY_REQUESTS);
+        res.render(Json(OpenAIErrorResponse {
+            error: OpenAIError {
+                message: format!("You exceeded your current quota: {}.", reason),
+                r#type: "insufficient_quota".to_string(),
+                code: "insufficient_quota".to_string(),
+                param: None,
+            },
+        }));
+        ctrl.skip_rest();
+        return;
+    }
+
+    let limits = collect_limits(&config, &identity, key_limits, model.as_deref());
+    let token_keys: Vec<String> = limits
+        .iter()
+        .filter(|limit| limit.kind == LimitKind::Tokens)
+        .map(|limit| limit.key.clone())
+        .collect();
+
+    if limits.is_empty() {
+        debug!("🚦 No rate limits apply | Client: {}", identity);
     } else {
-        debug!("🚫 Global rate limit disabled (RATE_LIMIT_MS=0)");
+        let prompt_tokens = if token_keys.is_empty() {
+            0
+        } else {
+            let mut text = String::new();
+            collect_text(&body, &mut text);
+            count_tokens(&text)
+        };
+
+        let admission = admit(&limits, prompt_tokens, Instant::now());
+        set_rate_limit_headers(res, &admission.states);
+
+        if let Some(rejection) = admission.rejection {
+            let retry_after = rejection.retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            warn!(
+                "🚦 Rate limit exceeded | Client: {} | Limit: {} {} | Retry after: {}s",
+                identity,
+                rejection.per_minute,
+                rejection.kind.name(),
+                retry_after
+            );
+            set_header(res, "retry-after", retry_after.to_string());
+            res.status_code(StatusCode::TOO_MANY_REQUESTS);
+            res.render(Json(OpenAIErrorResponse {
+                error: OpenAIError {
+                    message: format!(
+                        "Rate limit reached on {}: Limit {}. Please try again in {}.",
+                        rejection.kind.unit(),
+                        rejection.per_minute,
+                        format_reset(rejection.retry_after)
+                    ),
+                    r#type: rejection.kind.name().to_string(),
+                    code: "rate_limit_exceeded".to_string(),
+                    param: None,
+                },
+            }));
+            ctrl.skip_rest();
+            return;
+        }
+
+        debug!(
+            "🚦 Rate limit passed | Client: {} | Buckets: {} | Prompt tokens: {}",
+            identity,
+            limits.len(),
+            prompt_tokens
+        );
     }
 
+    depot.inject(UsageMeter {
+        client: identity,
+        token_keys,
+        request_counted: Arc::default(),
+    });
     ctrl.call_next(req, depot, res).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use salvo::test::TestClient;
+
+    #[test]
+    fn token_bucket_rejects_then_refills() {
+        let limits = vec![
+            Limit {
+                key: "test-client|rpm".to_string(),
+                kind: LimitKind::Requests,
+                per_minute: 2,
+            },
+            Limit {
+                key: "test-client|tpm".to_string(),
+                kind: LimitKind::Tokens,
+                per_minute: 100,
+            },
+        ];
+        let start = Instant::now();
+        assert!(admit(&limits, 10, start).rejection.is_none());
+        assert!(admit(&limits, 10, start).rejection.is_none());
+
+        let rejected = admit(&limits, 10, start);
+        let rejection = rejected
+            .rejection
+            .expect("third request is over the RPM limit");
+        assert_eq!(rejection.kind, LimitKind::Requests);
+        assert_eq!(rejection.retry_after, Duration::from_secs(30));
+        // Rejected requests do not consume tokens
+        let tokens = rejected
+            .states
+            .iter()
+            .find(|s| s.kind == LimitKind::Tokens)
+            .unwrap();
+        assert_eq!(tokens.remaining, 80.0);
+
+        assert!(
+            admit(&limits, 10, start + Duration::from_secs(30))
+                .rejection
+                .is_none()
+        );
+        assert_eq!(format_reset(Duration::from_secs(360)), "6m0s");
+    }
+
+    #[test]
+    fn credential_hash_follows_the_endpoint_credential() {
+        let with_both_headers = |url: &str| {
+            TestClient::post(url)
+                .add_header("authorization", "Bearer key-a", true)
+                .add_header("x-api-key", "key-b", true)
+                .build()
+        };
+        let chat = with_both_headers("http://127.0.0.1:8080/v1/chat/completions");
+        assert_eq!(credential_hash(&chat), Some(hash_token("key-a")));
+        let messages = with_both_headers("http://127.0.0.1:8080/v1/messages");
+        assert_eq!(credential_hash(&messages), Some(hash_token("key-b")));
+    }
+}
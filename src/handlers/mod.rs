@@ -1,11 +1,27 @@
+mod access;
 mod admin;
+mod anthropic;
+mod audio;
 mod chat;
+mod completions;
 mod cors;
-pub(crate) mod limit;
+mod gemini;
+mod images;
+mod limit;
 mod models;
+mod ollama;
+mod responses;
 
+pub use access::api_access_middleware;
 pub use admin::admin_routes;
+pub use anthropic::anthropic_messages;
+pub use audio::{audio_speech, audio_transcriptions, audio_translations};
 pub use chat::chat_completions;
+pub use completions::completions;
 pub use cors::cors_middleware;
+pub use gemini::gemini_generate_content;
+pub use images::image_generations;
 pub use limit::rate_limit_middleware;
 pub use models::get_models;
+pub use ollama::{ollama_chat, ollama_generate, ollama_show, ollama_tags};
+pub use responses::{create_response, delete_response, get_response};
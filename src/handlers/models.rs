@@ -1,3 +1,6 @@
+use super::chat::resolve_model;
+use super::limit::credential_hash;
+use crate::policy::{CredentialPolicy, load_policy};
 use crate::utils::{pretty_json_truncated, redact_headers, redact_json_fields};
 use crate::{cache::get_cached_config, poe_client::PoeClientWrapper, types::*};
 use chrono::Utc;
@@ -68,6 +71,154 @@ async fn get_models_from_api(config: &Config) -> Result<Vec<ModelInfo>, String>
     }
 }
 
+/// Get the Poe model list from API_MODELS_CACHE, populating it on a miss
+pub(crate) async fn cached_api_models(config: &Config) -> Result<Arc<Vec<ModelInfo>>, String> {
+    let read_guard = API_MODELS_CACHE.read().await;
+    if let Some(cached_data) = &*read_guard {
+        // Cache hit
+        debug!("✅ Model cache hit.");
+        return Ok(cached_data.clone());
+    }
+    // Cache miss
+    debug!("❌ Model cache miss. Attempting to populate...");
+    drop(read_guard);
+
+    let mut write_guard = API_MODELS_CACHE.write().await;
+    // Check again to prevent another thread from filling cache during write lock acquisition
+    if let Some(cached_data) = &*write_guard {
+        debug!("✅ API model cache populated by another thread while waiting for write lock.");
+        return Ok(cached_data.clone());
+    }
+    // Cache is indeed empty, get data from API
+    info!("⏳ Getting models from API to populate cache...");
+    let new_data = Arc::new(get_models_from_api(config).await?);
+    *write_guard = Some(new_data.clone());
+    info!("✅ API models cache populated successfully.");
+    Ok(new_data)
+}
+
+/// Apply models.yaml enable/mapping rules to the API list and append custom models
+pub(crate) fn merge_models_with_yaml(config: &Config, api_models: &[ModelInfo]) -> Vec<ModelInfo> {
+    let yaml_config_map: std::collections::HashMap<String, ModelConfig> = config
+        .models
+        .clone() // Clone HashMap from Config
+        .into_iter()
+        .map(|(k, v)| (k.to_lowercase(), v))
+        .collect();
+
+    let mut api_model_ids: HashSet<String> = HashSet::new();
+    for model_ref in api_models.iter() {
+        api_model_ids.insert(model_ref.id.to_lowercase());
+    }
+
+    let mut processed_models_enabled: Vec<ModelInfo> = Vec::new();
+
+    for api_model_ref in api_models.iter() {
+        let api_model_id_lower = api_model_ref.id.to_lowercase();
+        match yaml_config_map.get(&api_model_id_lower) {
+            Some(yaml_config) => {
+                // Found in YAML: check if enabled, if enabled apply mapping
+                if yaml_config.enable.unwrap_or(true) {
+                    let final_id = if let Some(mapping) = &yaml_config.mapping {
+                        let new_id = mapping.to_lowercase();
+                        debug!(
+                            "🔄 API model renamed (YAML enabled): {} -> {}",
+                            api_model_id_lower, new_id
+                        );
+                        new_id
+                    } else {
+                        debug!(
+                            "✅ Keep API model (YAML enabled, no mapping): {}",
+                            api_model_id_lower
+                        );
+                        api_model_id_lower.clone()
+                    };
+                    processed_models_enabled.push(ModelInfo {
+                        id: final_id,
+                        object: api_model_ref.object.clone(),
+                        created: api_model_ref.created,
+                        owned_by: api_model_ref.owned_by.clone(),
+                    });
+                } else {
+                    debug!(
+                        "❌ Exclude API model (YAML disabled): {}",
+                        api_model_id_lower
+                    );
+                }
+            }
+            None => {
+                debug!("✅ Keep API model (not in YAML): {}", api_model_id_lower);
+                processed_models_enabled.push(ModelInfo {
+                    id: api_model_id_lower.clone(),
+                    object: api_model_ref.object.clone(),
+                    created: api_model_ref.created,
+                    owned_by: api_model_ref.owned_by.clone(),
+                });
+            }
+        }
+    }
+
+    // Process custom models, adding them to the processed model list
+    if let Some(custom_models) = &config.custom_models {
+        if !custom_models.is_empty() {
+            info!(
+                "📋 Processing custom models | Count: {}",
+                custom_models.len()
+            );
+            for custom_model in custom_models {
+                let model_id = custom_model.id.to_lowercase();
+                // Check if this ID already exists in processed models
+                if !processed_models_enabled.iter().any(|m| m.id == model_id) {
+                    // Check if configured with enable: false in yaml_config_map
+                    if let Some(yaml_config) = yaml_config_map.get(&model_id) {
+                        if yaml_config.enable == Some(false) {
+                            debug!("❌ Exclude custom model (YAML disabled): {}", model_id);
+                            continue;
+                        }
+                    }
+
+                    debug!("➕ Add custom model: {}", model_id);
+                    processed_models_enabled.push(ModelInfo {
+                        id: model_id,
+                        object: "model".to_string(),
+                        created: custom_model
+                            .created
+                            .unwrap_or_else(|| Utc::now().timestamp()),
+                        owned_by: custom_model
+                            .owned_by
+                            .clone()
+                            .unwrap_or_else(|| "poe".to_string()),
+                    });
+                }
+            }
+        }
+    }
+
+    processed_models_enabled
+}
+
+// Only list the models the credential's policy allows, matched on the original model name
+pub(super) fn filter_by_policy(
+    config: &Config,
+    policy: Option<&CredentialPolicy>,
+    models: Vec<ModelInfo>,
+) -> Vec<ModelInfo> {
+    let Some(policy) = policy else {
+        return models;
+    };
+    let before = models.len();
+    let allowed: Vec<ModelInfo> = models
+        .into_iter()
+        .filter(|model| policy.allows_model(&resolve_model(config, &model.id).1))
+        .collect();
+    debug!(
+        "📜 Model list filtered by policy | Before: {} | After: {}",
+        before,
+        allowed.len()
+    );
+    allowed
+}
+
 #[handler]
 pub async fn get_models(req: &mut Request, res: &mut Response) {
     let path = req.uri().path();
@@ -142,6 +293,7 @@ pub async fn get_models(req: &mut Request, res: &mut Response) {
     }
 
     let config = get_cached_config().await;
+    let policy = credential_hash(req).and_then(|credential| load_policy(&credential));
 
     let is_enabled = config.enable.unwrap_or(false);
     debug!(
@@ -149,153 +301,31 @@ pub async fn get_models(req: &mut Request, res: &mut Response) {
         is_enabled
     );
 
-    let yaml_config_map: std::collections::HashMap<String, ModelConfig> = config
-        .models
-        .clone() // Clone HashMap from Arc<Config>
-        .into_iter()
-        .map(|(k, v)| (k.to_lowercase(), v))
-        .collect();
-
     if is_enabled {
         info!("⚙️ Merging cached Poe API list with models.yaml (enabled)");
 
-        let api_models_data_arc: Arc<Vec<ModelInfo>>;
-
-        let read_guard = API_MODELS_CACHE.read().await;
-        if let Some(cached_data) = &*read_guard {
-            // Cache hit
-            debug!("✅ Model cache hit.");
-            api_models_data_arc = cached_data.clone();
-            drop(read_guard);
-        } else {
-            // Cache miss
-            debug!("❌ Model cache miss. Attempting to populate...");
-            drop(read_guard);
-
-            let mut write_guard = API_MODELS_CACHE.write().await;
-            // Check again to prevent another thread from filling cache during write lock acquisition
-            if let Some(cached_data) = &*write_guard {
-                debug!(
-                    "✅ API model cache populated by another thread while waiting for write lock."
-                );
-                api_models_data_arc = cached_data.clone();
-            } else {
-                // Cache is indeed empty, get data from API
-                info!("⏳ Getting models from API to populate cache...");
-                match get_models_from_api(&config).await {
-                    Ok(models) => {
-                        let new_data = Arc::new(models);
-                        *write_guard = Some(new_data.clone());
-                        api_models_data_arc = new_data;
-                        info!("✅ API models cache populated successfully.");
-                    }
-                    Err(e) => {
-                        // If cache population fails, return error
-                        let duration = start_time.elapsed(); // Calculate duration
-                        error!(
-                            "❌ Failed to populate API models cache: {} | Duration: {}.",
-                            e,
-                            crate::utils::format_duration(duration) // Use duration in log
-                        );
-                        res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-                        res.render(Json(
-                            json!({ "error": format!("Failed to retrieve model list to populate cache: {}", e) }),
-                        ));
-                        drop(write_guard);
-                        return;
-                    }
-                }
-            }
-            drop(write_guard);
-        }
-
-        let mut api_model_ids: HashSet<String> = HashSet::new();
-        for model_ref in api_models_data_arc.iter() {
-            api_model_ids.insert(model_ref.id.to_lowercase());
-        }
-
-        let mut processed_models_enabled: Vec<ModelInfo> = Vec::new();
-
-        for api_model_ref in api_models_data_arc.iter() {
-            let api_model_id_lower = api_model_ref.id.to_lowercase();
-            match yaml_config_map.get(&api_model_id_lower) {
-                Some(yaml_config) => {
-                    // Found in YAML: check if enabled, if enabled apply mapping
-                    if yaml_config.enable.unwrap_or(true) {
-                        let final_id = if let Some(mapping) = &yaml_config.mapping {
-                            let new_id = mapping.to_lowercase();
-                            debug!(
-                                "🔄 API model renamed (YAML enabled): {} -> {}",
-                                api_model_id_lower, new_id
-                            );
-                            new_id
-                        } else {
-                            debug!(
-                                "✅ Keep API model (YAML enabled, no mapping): {}",
-                                api_model_id_lower
-                            );
-                            api_model_id_lower.clone()
-                        };
-                        processed_models_enabled.push(ModelInfo {
-                            id: final_id,
-                            object: api_model_ref.object.clone(),
-                            created: api_model_ref.created,
-                            owned_by: api_model_ref.owned_by.clone(),
-                        });
-                    } else {
-                        debug!(
-                            "❌ Exclude API model (YAML disabled): {}",
-                            api_model_id_lower
-                        );
-                    }
-                }
-                None => {
-                    debug!("✅ Keep API model (not in YAML): {}", api_model_id_lower);
-                    processed_models_enabled.push(ModelInfo {
-                        id: api_model_id_lower.clone(),
-                        object: api_model_ref.object.clone(),
-                        created: api_model_ref.created,
-                        owned_by: api_model_ref.owned_by.clone(),
-                    });
-                }
-            }
-        }
-
-        // Process custom models, adding them to the processed model list
-        if let Some(custom_models) = &config.custom_models {
-            if !custom_models.is_empty() {
-                info!(
-                    "📋 Processing custom models | Count: {}",
-                    custom_models.len()
+        let api_models_data_arc = match cached_api_models(&config).await {
+            Ok(models) => models,
+            Err(e) => {
+                // If cache population fails, return error
+                let duration = start_time.elapsed(); // Calculate duration
+                error!(
+                    "❌ Failed to populate API models cache: {} | Duration: {}.",
+                    e,
+                    crate::utils::format_duration(duration) // Use duration in log
                 );
-                for custom_model in custom_models {
-                    let model_id = custom_model.id.to_lowercase();
-                    // Check if this ID already exists in processed models
-                    if !processed_models_enabled.iter().any(|m| m.id == model_id) {
-                        // Check if configured with enable: false in yaml_config_map
-                        if let Some(yaml_config) = yaml_config_map.get(&model_id) {
-                            if yaml_config.enable == Some(false) {
-                                debug!("❌ Exclude custom model (YAML disabled): {}", model_id);
-                                continue;
-                            }
-                        }
-
-                        debug!("➕ Add custom model: {}", model_id);
-                        processed_models_enabled.push(ModelInfo {
-                            id: model_id,
-                            object: "model".to_string(),
-                            created: custom_model
-                                .created
-                                .unwrap_or_else(|| Utc::now().timestamp()),
-                            owned_by: custom_model
-                                .owned_by
-                                .clone()
-                                .unwrap_or_else(|| "poe".to_string()),
-                        });
-                    }
-                }
+                res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+                res.render(Json(
+                    json!({ "error": format!("Failed to retrieve model list to populate cache: {}", e) }),
+                ));
+                return;
             }
-        }
+        };
+        let processed_models_enabled = filter_by_policy(
+            &config,
+            policy.as_ref(),
+            merge_models_with_yaml(&config, &api_models_data_arc),
+        );
 
         let response = json!({
             "object": "list",
@@ -329,6 +359,7 @@ pub async fn get_models(req: &mut Request, res: &mut Response) {
 
         match get_models_from_api(&config).await {
             Ok(models) => {
+                let models = filter_by_policy(&config, policy.as_ref(), models);
                 let response = json!({
                     "object": "list",
                     "data": models
@@ -0,0 +1,828 @@
+use super::chat::{extract_access_key, start_chat};
+use super::limit::UsageMeter;
+use super::limit::credential_hash;
+use super::models::{cached_api_models, filter_by_policy, merge_models_with_yaml};
+use crate::cache::get_cached_config;
+use crate::evert::{
+    EventDelta, EventStream, OutputCut, OutputLimit, collect_events, delta_stream, finalize_content,
+};
+use crate::policy::{CredentialPolicy, load_policy};
+use crate::types::*;
+use crate::utils::{
+    count_completion_tokens, format_duration, pretty_json_truncated, redact_headers,
+    redact_json_fields,
+};
+use chrono::{TimeZone, Utc};
+use futures_util::{StreamExt, future};
+use nanoid::nanoid;
+use poe_api_process::ModelInfo;
+use poe_api_process::types::{ChatTool, ChatToolCall};
+use salvo::http::header;
+use salvo::prelude::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::time::Instant;
+use tracing::{debug, error, info, warn};
+
+#[derive(Clone, Copy, PartialEq)]
+enum OllamaMode {
+    Chat,
+    Generate,
+}
+
+#[handler]
+pub async fn ollama_chat(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let start_time = Instant::now();
+    let Some((access_key, chat_request)) = read_ollama_request::<OllamaChatRequest>(req, res)
+        .await
+        .map(|(key, request)| (key, ollama_chat_to_chat_request(&request)))
+    else {
+        return;
+    };
+    let usage_meter = depot.obtain::<UsageMeter>().cloned().unwrap_or_default();
+    run_ollama_request(
+        res,
+        &access_key,
+        chat_request,
+        OllamaMode::Chat,
+        start_time,
+        usage_meter,
+        depot.obtain::<CredentialPolicy>().ok(),
+    )
+    .await;
+
+    let duration = start_time.elapsed();
+    info!(
+        "✅ Ollama chat request processing completed | Duration: {}",
+        format_duration(duration)
+    );
+}
+
+#[handler]
+pub async fn ollama_generate(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let start_time = Instant::now();
+    let Some((access_key, chat_request)) = read_ollama_request::<OllamaGenerateRequest>(req, res)
+        .await
+        .map(|(key, request)| (key, ollama_generate_to_chat_request(&request)))
+    else {
+        return;
+    };
+    let usage_meter = depot.obtain::<UsageMeter>().cloned().unwrap_or_default();
+    run_ollama_request(
+        res,
+        &access_key,
+        chat_request,
+        OllamaMode::Generate,
+        start_time,
+        usage_meter,
+        depot.obtain::<CredentialPolicy>().ok(),
+    )
+    .await;
+
+    let duration = start_time.elapsed();
+    info!(
+        "✅ Ollama generate request processing completed | Duration: {}",
+        format_duration(duration)
+    );
+}
+
+#[handler]
+pub async fn ollama_tags(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [GET] {} ------", req.uri());
+    let start_time = Instant::now();
+
+    let models = match ollama_models(req).await {
+        Ok(models) => models,
+        Err(e) => {
+            error!("❌ [/api/tags] Failed to get model list | Error: {}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(json!({ "error": e })));
+            return;
+        }
+    };
+
+    let tags: Vec<Value> = models.iter().map(ollama_model_entry).collect();
+    debug!("------ Outgoing Response [200] /api/tags ------");
+    info!(
+        "✅ [/api/tags] Listed models | Model count: {} | Processing time: {}",
+        tags.len(),
+        format_duration(start_time.elapsed())
+    );
+    res.render(Json(json!({ "models": tags })));
+}
+
+#[handler]
+pub async fn ollama_show(req: &mut Request, res: &mut Response) {
+    debug!("------ Incoming Request [POST] {} ------", req.uri());
+
+    let show_request = match req.parse_json::<OllamaShowRequest>().await {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            error!("❌ JSON parsing failed: {}", e);
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(
+                json!({ "error": format!("JSON parsing failed: {}", e) }),
+            ));
+            return;
+        }
+    };
+    let name = show_request
+        .model
+        .or(show_request.name)
+        .map(|name| strip_ollama_tag(&name))
+        .unwrap_or_default();
+
+    let models = match ollama_models(req).await {
+        Ok(models) => models,
+        Err(e) => {
+            error!("❌ [/api/show] Failed to get model list | Error: {}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(json!({ "error": e })));
+            return;
+        }
+    };
+    let Some(model) = models.iter().find(|model| model.id == name) else {
+        debug!("------ Outgoing Response [404] /api/show ------");
+        res.status_code(StatusCode::NOT_FOUND);
+        res.render(Json(
+            json!({ "error": format!("model '{}' not found", name) }),
+        ));
+        return;
+    };
+
+    debug!("------ Outgoing Response [200] /api/show ------");
+    res.render(Json(json!({
+        "modelfile": format!("FROM {}", model.id),
+        "parameters": "",
+        "template": "{{ .Prompt }}",
+        "details": ollama_model_details(model),
+        "model_info": {"general.architecture": "poe", "general.basename": model.id},
+        "capabilities": ["completion", "tools", "vision", "thinking"],
+        "modified_at": model_modified_at(model.created)
+    })));
+}
+
+// Models as listed by /v1/models: the cached API list with models.yaml rules applied when enabled,
+// limited to the models the credential's policy allows
+async fn ollama_models(req: &Request) -> Result<Vec<ModelInfo>, String> {
+    let config = get_cached_config().await;
+    let policy = credential_hash(req).and_then(|credential| load_policy(&credential));
+    let api_models = cached_api_models(&config).await?;
+    let models = if config.enable.unwrap_or(false) {
+        merge_models_with_yaml(&config, &api_models)
+    } else {
+        api_models
+            .iter()
+            .map(|model| ModelInfo {
+                id: model.id.clone(),
+                object: model.object.clone(),
+                created: model.created,
+                owned_by: model.owned_by.clone(),
+            })
+            .collect()
+    };
+    Ok(filter_by_policy(&config, policy.as_ref(), models))
+}
+
+fn ollama_model_entry(model: &ModelInfo) -> Value {
+    json!({
+        "name": model.id,
+        "model": model.id,
+        "modified_at": model_modified_at(model.created),
+        "size": 0,
+        "digest": "",
+        "details": ollama_model_details(model)
+    })
+}
+
+fn ollama_model_details(model: &ModelInfo) -> Value {
+    json!({
+        "parent_model": "",
+        "format": "poe",
+        "family": model.owned_by,
+        "families": [model.owned_by],
+        "parameter_size": "",
+        "quantization_level": ""
+    })
+}
+
+fn model_modified_at(created: i64) -> String {
+    Utc.timestamp_opt(created, 0)
+        .single()
+        .unwrap_or_else(Utc::now)
+        .to_rfc3339()
+}
+
+// Ollama clients default to the ":latest" tag, which Poe model names never carry
+fn strip_ollama_tag(name: &str) -> String {
+    name.strip_suffix(":latest").unwrap_or(name).to_lowercase()
+}
+
+// Shared request prelude: logging, authorization and body parsing
+async fn read_ollama_request<T: DeserializeOwned + Serialize>(
+    req: &mut Request,
+    res: &mut Response,
+) -> Option<(String, T)> {
+    // Structure request/response logging with separator
+    debug!("------ Incoming Request [POST] {} ------", req.uri());
+
+    // Log inbound request metadata with redacted headers
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let redacted_headers = redact_headers(req.headers());
+
+    debug!(
+        "📝 Request metadata | Method: {} | Path: {} | Headers: {:?}",
+        method, path, redacted_headers
+    );
+
+    let max_size: usize = std::env::var("MAX_REQUEST_SIZE")
+        .unwrap_or_else(|_| "1073741824".to_string())
+        .parse()
+        .unwrap_or(1024 * 1024 * 1024);
+
+    // Validate authorization
+    let access_key = match extract_access_key(req) {
+        Ok(key) => key,
+        Err(message) => {
+            error!("❌ {}", message);
+            res.status_code(StatusCode::UNAUTHORIZED);
+            res.render(Json(json!({ "error": message })));
+            return None;
+        }
+    };
+
+    // Parse request body
+    match req.payload_with_max_size(max_size).await {
+        Ok(bytes) => match serde_json::from_slice::<T>(bytes) {
+            Ok(parsed) => {
+                let request_value = serde_json::to_value(&parsed).unwrap_or_else(|_| json!(null));
+                let redacted_request = redact_json_fields(&request_value);
+                debug!(
+                    "📋 Request body (sanitized, truncated):\n{}",
+                    pretty_json_truncated(&redacted_request, 64 * 1024)
+                );
+                Some((access_key, parsed))
+            }
+            Err(e) => {
+                error!("❌ JSON parsing failed: {}", e);
+                res.status_code(StatusCode::BAD_REQUEST);
+                res.render(Json(
+                    json!({ "error": format!("JSON parsing failed: {}", e) }),
+                ));
+                None
+            }
+        },
+        Err(e) => {
+            error!("❌ Request size exceeded limit or read failed: {}", e);
+            res.status_code(StatusCode::PAYLOAD_TOO_LARGE);
+            res.render(Json(json!({
+                "error": format!("Request size exceeded limit ({} bytes) or read failed: {}", max_size, e)
+            })));
+            None
+        }
+    }
+}
+
+async fn run_ollama_request(
+    res: &mut Response,
+    access_key: &str,
+    chat_request: ChatCompletionRequest,
+    mode: OllamaMode,
+    start_time: Instant,
+    usage_meter: UsageMeter,
+    policy: Option<&CredentialPolicy>,
+) {
+    let config = get_cached_config().await;
+    let stream = chat_request.stream.unwrap_or(true);
+    // Ollama only reports thinking when the client asked for it
+    let show_thinking = chat_request.reasoning_effort.is_some();
+
+    match start_chat(&config, access_key, &chat_request, policy).await {
+        Ok(started) => {
+            let limit = started.output_limit(&chat_request);
+            let state = OllamaStreamState {
+                model: started.display_model,
+                mode,
+                show_thinking,
+                prompt_tokens: started.prompt_tokens,
+                output_text: String::new(),
+                has_tool_calls: false,
+                start_time,
+                usage_meter,
+                limit,
+                finished: false,
+            };
+            if stream {
+                handle_stream_response(res, started.event_stream, state);
+            } else {
+                handle_non_stream_response(res, started.event_stream, state).await;
+            }
+        }
+        Err((status, error_response)) => {
+            debug!("------ Outgoing Response [{}] /api ------", status.as_u16());
+            res.status_code(status);
+            res.render(Json(json!({ "error": error_response.error.message })));
+        }
+    }
+}
+
+fn handle_stream_response(res: &mut Response, event_stream: EventStream, state: OllamaStreamState) {
+    info!(
+        "🌊 Starting Ollama NDJSON streaming response | Model: {}",
+        state.model
+    );
+
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        "application/x-ndjson".parse().unwrap(),
+    );
+    res.headers_mut()
+        .insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
+
+    let body = delta_stream(event_stream)
+        .scan(state, |state, delta| {
+            future::ready(if state.finished {
+                None
+            } else {
+                Some(Ok::<String, Infallible>(state.render(delta)))
+            })
+        })
+        .filter(|result| {
+            future::ready(match result {
+                Ok(s) => !s.is_empty(),
+                Err(_) => true,
+            })
+        });
+    res.stream(body);
+}
+
+async fn handle_non_stream_response(
+    res: &mut Response,
+    event_stream: EventStream,
+    mut state: OllamaStreamState,
+) {
+    let mut ctx = match collect_events(event_stream).await {
+        Ok(ctx) => ctx,
+        Err((status, error_response)) => {
+            res.status_code(status);
+            res.render(Json(json!({ "error": error_response.error.message })));
+            return;
+        }
+    };
+
+    let (text, cut) = std::mem::take(&mut state.limit).apply(&finalize_content(&mut ctx));
+    state.output_text = text.clone();
+    state.has_tool_calls = !ctx.tool_calls.is_empty();
+    let thinking = Some(ctx.reasoning_content.clone()).filter(|t| !t.trim().is_empty());
+    let tool_calls: Vec<&ChatToolCall> = ctx.tool_calls.iter().collect();
+
+    let mut response = state.chunk(&text, thinking.as_deref(), &tool_calls);
+    merge_json(&mut response, state.final_fields(cut.as_ref()));
+
+    let response_value = redact_json_fields(&response);
+    debug!(
+        "📤 Response body (sanitized, truncated):\n{}",
+        pretty_json_truncated(&response_value, 64 * 1024)
+    );
+    debug!("------ Outgoing Response [200] /api ------");
+
+    res.render(Json(response));
+}
+
+// Translates deltas into Ollama NDJSON lines
+struct OllamaStreamState {
+    model: String,
+    mode: OllamaMode,
+    show_thinking: bool,
+    prompt_tokens: u32,
+    output_text: String,
+    has_tool_calls: bool,
+    start_time: Instant,
+    usage_meter: UsageMeter,
+    limit: OutputLimit,
+    finished: bool,
+}
+
+impl OllamaStreamState {
+    fn chunk(&self, text: &str, thinking: Option<&str>, tool_calls: &[&ChatToolCall]) -> Value {
+        let thinking = thinking.filter(|_| self.show_thinking);
+        let mut chunk = json!({
+            "model": self.model,
+            "created_at": Utc::now().to_rfc3339(),
+            "done": false
+        });
+        match self.mode {
+            OllamaMode::Chat => {
+                let mut message = json!({"role": "assistant", "content": text});
+                if let Some(thinking) = thinking {
+                    message["thinking"] = json!(thinking);
+                }
+                if !tool_calls.is_empty() {
+                    message["tool_calls"] = tool_calls
+                        .iter()
+                        .map(|tool_call| ollama_tool_call(tool_call))
+                        .collect();
+                }
+                chunk["message"] = message;
+            }
+            OllamaMode::Generate => {
+                chunk["response"] = json!(text);
+                if let Some(thinking) = thinking {
+                    chunk["thinking"] = json!(thinking);
+                }
+            }
+        }
+        chunk
+    }
+
+    fn final_fields(&self, cut: Option<&OutputCut>) -> Value {
+        let eval_count = count_completion_tokens(&self.output_text);
+        debug!(
+            "📊 Token usage statistics | prompt_eval_count: {} | eval_count: {}",
+            self.prompt_tokens, eval_count
+        );
+        self.usage_meter
+            .record(&self.model, self.prompt_tokens, eval_count);
+        json!({
+            "done": true,
+            "done_reason": if self.has_tool_calls {
+                "tool_calls"
+            } else if cut == Some(&OutputCut::MaxTokens) {
+                "length"
+            } else {
+                "stop"
+            },
+            "total_duration": self.start_time.elapsed().as_nanos() as u64,
+            "prompt_eval_count": self.prompt_tokens,
+            "eval_count": eval_count
+        })
+    }
+
+    fn render(&mut self, delta: EventDelta) -> String {
+        let line = match delta {
+            EventDelta::Reasoning(thinking) => {
+                if !self.show_thinking {
+                    return String::new();
+                }
+                self.chunk("", Some(&thinking), &[])
+            }
+            EventDelta::Content(text) => {
+                let text = self.limit.push(&text);
+                let mut output = self.text_line(&text);
+                if self.limit.cut().is_some() {
+                    output.push_str(&self.done_line());
+                }
+                return output;
+            }
+            EventDelta::ToolCalls(tool_calls) => {
+                if self.mode == OllamaMode::Generate {
+                    return String::new();
+                }
+                self.has_tool_calls = true;
+                let calls: Vec<&ChatToolCall> = tool_calls.iter().map(|c| &c.call).collect();
+                self.chunk("", None, &calls)
+            }
+            EventDelta::Error(_, error_response) => {
+                debug!("❌ Detected error, interrupting Ollama stream");
+                self.finished = true;
+                json!({ "error": error_response.error.message })
+            }
+            EventDelta::Done => {
+                let text = self.limit.finish();
+                let mut output = self.text_line(&text);
+                output.push_str(&self.done_line());
+                return output;
+            }
+        };
+        format!("{}\n", line)
+    }
+
+    fn text_line(&mut self, text: &str) -> String {
+        if text.is_empty() {
+            return String::new();
+        }
+        self.output_text.push_str(text);
+        format!("{}\n", self.chunk(text, None, &[]))
+    }
+
+    // Final line with timings and counts, nothing is sent after this
+    fn done_line(&mut self) -> String {
+        self.finished = true;
+        let mut chunk = self.chunk("", None, &[]);
+        merge_json(&mut chunk, self.final_fields(self.limit.cut()));
+        format!("{}\n", chunk)
+    }
+}
+
+fn merge_json(target: &mut Value, fields: Value) {
+    if let (Some(target), Value::Object(fields)) = (target.as_object_mut(), fields) {
+        target.extend(fields);
+    }
+}
+
+// Tool arguments are a JSON string in OpenAI format but an object in Ollama format
+fn ollama_tool_call(tool_call: &ChatToolCall) -> Value {
+    let arguments = &tool_call.function.arguments;
+    let arguments = if arguments.trim().is_empty() {
+        json!({})
+    } else {
+        serde_json::from_str(arguments).unwrap_or_else(|e| {
+            warn!(
+                "⚠️ Tool arguments are not valid JSON, wrapping as string: {}",
+                e
+            );
+            json!({ "arguments": arguments })
+        })
+    };
+    json!({"function": {"name": tool_call.function.name, "arguments": arguments}})
+}
+
+/// Convert an Ollama /api/chat request into the internal chat completion request
+pub(crate) fn ollama_chat_to_chat_request(request: &OllamaChatRequest) -> ChatCompletionRequest {
+    let mut messages = Vec::new();
+    // Ollama matches tool results to calls by name (or order), OpenAI by id
+    let mut pending_calls: VecDeque<(String, String)> = VecDeque::new();
+
+    for message in &request.messages {
+        let mut tool_calls = Vec::new();
+        for call in message.tool_calls.iter().flatten() {
+            let id = format!("call_{}", nanoid!(24));
+            let arguments = match &call.function.arguments {
+                Value::String(arguments) => arguments.clone(),
+                Value::Null => "{}".to_string(),
+                arguments => arguments.to_string(),
+            };
+            match serde_json::from_value::<ChatToolCall>(json!({
+                "id": id,
+                "type": "function",
+                "function": {"name": call.function.name, "arguments": arguments}
+            })) {
+                Ok(tool_call) => {
+                    pending_calls.push_back((call.function.name.clone(), id));
+                    tool_calls.push(tool_call);
+                }
+                Err(e) => warn!("⚠️ Skipping Ollama tool call {}: {}", call.function.name, e),
+            }
+        }
+
+        if message.role == "tool" {
+            let position = message
+                .tool_name
+                .as_ref()
+                .and_then(|name| pending_calls.iter().position(|(n, _)| n == name))
+                .unwrap_or(0);
+            let id = pending_calls
+                .remove(position)
+                .map(|(_, id)| id)
+                .unwrap_or_else(|| format!("call_{}", nanoid!(24)));
+            messages.push(Message {
+                role: "tool".to_string(),
+                content: Some(OpenAiContent::Text(message.content.clone())),
+                tool_call_id: Some(id),
+                ..Default::default()
+            });
+            continue;
+        }
+
+        messages.push(Message {
+            role: message.role.clone(),
+            content: Some(ollama_content(&message.content, message.images.as_deref())),
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+            ..Default::default()
+        });
+    }
+
+    let tools: Option<Vec<ChatTool>> = request.tools.as_ref().map(|tools| {
+        tools
+            .iter()
+            .filter_map(
+                |tool| match serde_json::from_value::<ChatTool>(tool.clone()) {
+                    Ok(chat_tool) => Some(chat_tool),
+                    Err(e) => {
+                        warn!("⚠️ Skipping Ollama tool: {}", e);
+                        None
+                    }
+                },
+            )
+            .collect()
+    });
+
+    let mut chat_request = ChatCompletionRequest {
+        model: strip_ollama_tag(&request.model),
+        messages,
+        stream: Some(request.stream.unwrap_or(true)),
+        tools: tools.filter(|tools| !tools.is_empty()),
+        response_format: request.format.as_ref().and_then(ollama_response_format),
+        reasoning_effort: request
+            .think
+            .filter(|think| *think)
+            .map(|_| "medium".to_string()),
+        ..Default::default()
+    };
+    apply_ollama_options(&mut chat_request, request.options.as_ref());
+    chat_request
+}
+
+/// Convert an Ollama /api/generate request into the internal chat completion request
+pub(crate) fn ollama_generate_to_chat_request(
+    request: &OllamaGenerateRequest,
+) -> ChatCompletionRequest {
+    let mut messages = Vec::new();
+    if let Some(system) = request.system.as_ref().filter(|s| !s.is_empty()) {
+        messages.push(Message {
+            role: "system".to_string(),
+            content: Some(OpenAiContent::Text(system.clone())),
+            ..Default::default()
+        });
+    }
+    messages.push(Message {
+        role: "user".to_string(),
+        content: Some(ollama_content(&request.prompt, request.images.as_deref())),
+        ..Default::default()
+    });
+
+    let mut chat_request = ChatCompletionRequest {
+        model: strip_ollama_tag(&request.model),
+        messages,
+        stream: Some(request.stream.unwrap_or(true)),
+        response_format: request.format.as_ref().and_then(ollama_response_format),
+        reasoning_effort: request
+            .think
+            .filter(|think| *think)
+            .map(|_| "medium".to_string()),
+        ..Default::default()
+    };
+    apply_ollama_options(&mut chat_request, request.options.as_ref());
+    chat_request
+}
+
+fn apply_ollama_options(chat_request: &mut ChatCompletionRequest, options: Option<&OllamaOptions>) {
+    let Some(options) = options else {
+        return;
+    };
+    chat_request.temperature = options.temperature;
+    chat_request.top_p = options.top_p;
+    // num_predict <= 0 means unlimited in Ollama
+    chat_request.max_tokens = options.num_predict.filter(|n| *n > 0).map(|n| n as u32);
+    chat_request.stop = options.stop.clone();
+    chat_request.seed = options.seed;
+}
+
+// format is either "json" or a JSON schema object
+fn ollama_response_format(format: &Value) -> Option<Value> {
+    match format {
+        Value::String(s) if s == "json" => Some(json!({"type": "json_object"})),
+        Value::Object(_) => Some(json!({
+            "type": "json_schema",
+            "json_schema": {"name": "response", "schema": format}
+        })),
+        _ => None,
+    }
+}
+
+// Ollama images are bare base64 strings without a MIME type
+fn ollama_content(text: &str, images: Option<&[String]>) -> OpenAiContent {
+    let Some(images) = images.filter(|images| !images.is_empty()) else {
+        return OpenAiContent::Text(text.to_string());
+    };
+    let mut items = vec![OpenAiContentItem::Text {
+        r#type: Some("text".to_string()),
+        text: text.to_string(),
+        extra: HashMap::new(),
+    }];
+    for image in images {
+        let url = if image.starts_with("data:") || image.starts_with("http") {
+            image.clone()
+        } else {
+            format!("data:{};base64,{}", base64_image_mime(image), image)
+        };
+        items.push(OpenAiContentItem::ImageUrl {
+            r#type: Some("image_url".to_string()),
+            image_url: ImageUrlContent {
+                url,
+                extra: HashMap::new(),
+            },
+            extra: HashMap::new(),
+        });
+    }
+    OpenAiContent::Multi(items)
+}
+
+// Sniff the image type from the base64-encoded magic bytes
+fn base64_image_mime(data: &str) -> &'static str {
+    if data.starts_with("/9j/") {
+        "image/jpeg"
+    } else if data.starts_with("R0lGOD") {
+        "image/gif"
+    } else if data.starts_with("UklGR") {
+        "image/webp"
+    } else {
+        "image/png"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_chat_request_with_tools_and_images() {
+        let payload = json!({
+            "model": "gpt-4o:latest",
+            "messages": [
+                {"role": "user", "content": "What is this?", "images": ["/9j/4AAQ"]},
+                {"role": "assistant", "content": "", "tool_calls": [
+                    {"function": {"name": "lookup", "arguments": {"q": "cat"}}}
+                ]},
+                {"role": "tool", "content": "a cat", "tool_name": "lookup"}
+            ],
+            "format": "json",
+            "options": {"num_predict": 128, "stop": ["\n\n"]},
+            "stream": false
+        });
+
+        let request: OllamaChatRequest = serde_json::from_value(payload).expect("valid request");
+        let chat_request = ollama_chat_to_chat_request(&request);
+
+        assert_eq!(chat_request.model, "gpt-4o");
+        assert_eq!(chat_request.stream, Some(false));
+        assert_eq!(chat_request.max_tokens, Some(128));
+        assert_eq!(
+            chat_request.response_format,
+            Some(json!({"type": "json_object"}))
+        );
+        let image_url = match &chat_request.messages[0].content {
+            Some(OpenAiContent::Multi(items)) => items[1].as_image_url().unwrap().url.clone(),
+            _ => panic!("expected multi content"),
+        };
+        assert!(image_url.starts_with("data:image/jpeg;base64,"));
+        let call_id = &chat_request.messages[1].tool_calls.as_ref().unwrap()[0].id;
+        assert_eq!(
+            chat_request.messages[2].tool_call_id.as_ref(),
+            Some(call_id)
+        );
+    }
+
+    #[test]
+    fn stream_state_emits_ndjson_lines() {
+        let mut state = OllamaStreamState {
+            model: "m".to_string(),
+            mode: OllamaMode::Generate,
+            show_thinking: false,
+            prompt_tokens: 4,
+            output_text: String::new(),
+            has_tool_calls: false,
+            start_time: Instant::now(),
+            usage_meter: UsageMeter::default(),
+            limit: OutputLimit::default(),
+            finished: false,
+        };
+        assert!(
+            state
+                .render(EventDelta::Reasoning("hmm".to_string()))
+                .is_empty()
+        );
+        let line = state.render(EventDelta::Content("Hi".to_string()));
+        assert!(line.ends_with('\n'));
+        let chunk: Value = serde_json::from_str(line.trim()).unwrap();
+        assert_eq!(chunk["response"], "Hi");
+        assert_eq!(chunk["done"], false);
+        let done: Value = serde_json::from_str(state.render(EventDelta::Done).trim()).unwrap();
+        assert_eq!(done["done"], true);
+        assert_eq!(done["done_reason"], "stop");
+        assert_eq!(done["prompt_eval_count"], 4);
+    }
+
+    #[test]
+    fn stream_state_reports_length_at_num_predict() {
+        let mut state = OllamaStreamState {
+            model: "m".to_string(),
+            mode: OllamaMode::Chat,
+            show_thinking: false,
+            prompt_tokens: 4,
+            output_text: String::new(),
+            has_tool_calls: false,
+            start_time: Instant::now(),
+            usage_meter: UsageMeter::default(),
+            limit: OutputLimit::new(&[], Some(1)),
+            finished: false,
+        };
+        let output = state.render(EventDelta::Content("one two three".to_string()));
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["done_reason"], "length");
+        assert_eq!(lines[1]["eval_count"], 1);
+        assert!(state.finished);
+    }
+}
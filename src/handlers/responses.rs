@@ -0,0 +1,1124 @@
+use super::chat::{extract_access_key, start_chat};
+use super::limit::{UsageMeter, credential_hash};
+use crate::cache::{
+    delete_stored_response, get_cached_config, load_stored_response, save_stored_response,
+};
+use crate::evert::{
+    EventDelta, EventStream, OutputCut, OutputLimit, collect_events, delta_stream, finalize_content,
+};
+use crate::policy::CredentialPolicy;
+use crate::types::*;
+use crate::utils::{
+    count_completion_tokens, format_duration, pretty_json_truncated, redact_headers,
+    redact_json_fields,
+};
+use chrono::Utc;
+use futures_util::future;
+use futures_util::stream::{self, StreamExt};
+use nanoid::nanoid;
+use poe_api_process::types::{ChatTool, ChatToolCall};
+use salvo::http::header;
+use salvo::prelude::*;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::time::Instant;
+use tracing::{debug, error, info, warn};
+
+#[handler]
+pub async fn create_response(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let start_time = Instant::now();
+
+    // Structure request/response logging with separator
+    debug!("------ Incoming Request [POST] {} ------", req.uri());
+
+    // Log inbound request metadata with redacted headers
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let redacted_headers = redact_headers(req.headers());
+
+    debug!(
+        "📝 Request metadata | Method: {} | Path: {} | Headers: {:?}",
+        method, path, redacted_headers
+    );
+
+    let max_size: usize = std::env::var("MAX_REQUEST_SIZE")
+        .unwrap_or_else(|_| "1073741824".to_string())
+        .parse()
+        .unwrap_or(1024 * 1024 * 1024);
+
+    let config = get_cached_config().await;
+
+    // Validate authorization
+    let access_key = match extract_access_key(req) {
+        Ok(key) => key,
+        Err(message) => {
+            error!("❌ {}", message);
+            res.status_code(StatusCode::UNAUTHORIZED);
+            res.render(Json(json!({ "error": message })));
+            return;
+        }
+    };
+
+    // Parse request body
+    let responses_request = match req.payload_with_max_size(max_size).await {
+        Ok(bytes) => match serde_json::from_slice::<ResponsesRequest>(bytes) {
+            Ok(parsed) => {
+                debug!(
+                    "📊 Responses request parsed | Model: {} | Previous: {:?} | Stream: {:?}",
+                    parsed.model, parsed.previous_response_id, parsed.stream
+                );
+                let request_value = serde_json::to_value(&parsed).unwrap_or_else(|_| json!(null));
+                let redacted_request = redact_json_fields(&request_value);
+                debug!(
+                    "📋 Request body (sanitized, truncated):\n{}",
+                    pretty_json_truncated(&redacted_request, 64 * 1024)
+                );
+                parsed
+            }
+            Err(e) => {
+                error!("❌ JSON parsing failed: {}", e);
+                res.status_code(StatusCode::BAD_REQUEST);
+                res.render(Json(
+                    json!({ "error": format!("JSON parsing failed: {}", e) }),
+                ));
+                return;
+            }
+        },
+        Err(e) => {
+            error!("❌ Request size exceeded limit or read failed: {}", e);
+            res.status_code(StatusCode::PAYLOAD_TOO_LARGE);
+            res.render(Json(json!({
+                "error": format!("Request size exceeded limit ({} bytes) or read failed: {}", max_size, e)
+            })));
+            return;
+        }
+    };
+
+    // Stored responses belong to the presented credential, proxy keys sharing a Poe token stay separate
+    let owner = credential_hash(req).unwrap_or_default();
+
+    // Rebuild conversation history from the previous stored response
+    let mut history: Vec<Message> = Vec::new();
+    if let Some(previous_id) = &responses_request.previous_response_id {
+        match load_stored_response(previous_id) {
+            Some(stored) if stored.owner == owner => {
+                debug!(
+                    "📜 Restored history from {} | Messages: {}",
+                    previous_id,
+                    stored.messages.len()
+                );
+                history = stored.messages;
+            }
+            _ => {
+                warn!("⚠️ Previous response not found: {}", previous_id);
+                render_not_found(res, previous_id, Some("previous_response_id"));
+                return;
+            }
+        }
+    }
+    if let Some(input) = &responses_request.input {
+        history.extend(responses_input_to_messages(input));
+    }
+
+    let stream = responses_request.stream.unwrap_or(false);
+    let chat_request = responses_to_chat_request(&responses_request, &history);
+
+    let policy = depot.obtain::<CredentialPolicy>().ok();
+    match start_chat(&config, &access_key, &chat_request, policy).await {
+        Ok(started) => {
+            let limit = started.output_limit(&chat_request);
+            let builder = ResponseBuilder {
+                id: format!("resp_{}", nanoid!(24)),
+                created_at: Utc::now().timestamp(),
+                model: started.display_model,
+                echo: request_echo(&responses_request),
+                input_tokens: started.prompt_tokens,
+                store: responses_request.store.unwrap_or(true),
+                owner,
+                history,
+                usage_meter: depot.obtain::<UsageMeter>().cloned().unwrap_or_default(),
+            };
+            if stream {
+                handle_stream_response(res, started.event_stream, builder, limit, started.refusal);
+            } else {
+                handle_non_stream_response(
+                    res,
+                    started.event_stream,
+                    builder,
+                    limit,
+                    started.refusal,
+                )
+                .await;
+            }
+        }
+        Err((status, error_response)) => {
+            res.status_code(status);
+            res.render(Json(error_response));
+        }
+    }
+
+    let duration = start_time.elapsed();
+    info!(
+        "✅ Responses request processing completed | Duration: {}",
+        format_duration(duration)
+    );
+}
+
+#[handler]
+pub async fn get_response(req: &mut Request, res: &mut Response) {
+    if let Err(message) = extract_access_key(req) {
+        error!("❌ {}", message);
+        res.status_code(StatusCode::UNAUTHORIZED);
+        res.render(Json(json!({ "error": message })));
+        return;
+    }
+    let owner = credential_hash(req).unwrap_or_default();
+    let id = req.param::<String>("id").unwrap_or_default();
+
+    match load_stored_response(&id) {
+        Some(stored) if stored.owner == owner => {
+            debug!("📜 Returning stored response: {}", id);
+            res.render(Json(stored.response));
+        }
+        _ => render_not_found(res, &id, None),
+    }
+}
+
+#[handler]
+pub async fn delete_response(req: &mut Request, res: &mut Response) {
+    if let Err(message) = extract_access_key(req) {
+        error!("❌ {}", message);
+        res.status_code(StatusCode::UNAUTHORIZED);
+        res.render(Json(json!({ "error": message })));
+        return;
+    }
+    let owner = credential_hash(req).unwrap_or_default();
+    let id = req.param::<String>("id").unwrap_or_default();
+
+    match load_stored_response(&id) {
+        Some(stored) if stored.owner == owner => {
+            delete_stored_response(&id);
+            info!("🗑️ Deleted stored response: {}", id);
+            res.render(Json(json!({
+                "id": id,
+                "object": "response",
+                "deleted": true
+            })));
+        }
+        _ => render_not_found(res, &id, None),
+    }
+}
+
+fn render_not_found(res: &mut Response, id: &str, param: Option<&str>) {
+    res.status_code(StatusCode::NOT_FOUND);
+    res.render(Json(OpenAIErrorResponse {
+        error: OpenAIError {
+            message: format!("Response with id '{}' not found.", id),
+            r#type: "invalid_request_error".to_string(),
+            code: "response_not_found".to_string(),
+            param: param.map(|p| p.to_string()),
+        },
+    }));
+}
+
+fn handle_stream_response(
+    res: &mut Response,
+    event_stream: EventStream,
+    builder: ResponseBuilder,
+    limit: OutputLimit,
+    refusal: Option<String>,
+) {
+    info!(
+        "🌊 Starting Responses streaming response | ID: {} | Model: {}",
+        builder.id, builder.model
+    );
+
+    res.headers_mut()
+        .insert(header::CONTENT_TYPE, "text/event-stream".parse().unwrap());
+    res.headers_mut()
+        .insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
+    res.headers_mut()
+        .insert(header::CONNECTION, "keep-alive".parse().unwrap());
+
+    let mut state = ResponsesStreamState::new(builder);
+    state.limit = limit;
+    state.refusal = refusal;
+    let created = state.start();
+    let events = delta_stream(event_stream).scan(state, |state, delta| {
+        future::ready(if state.finished {
+            None
+        } else {
+            Some(Ok::<String, Infallible>(state.render(delta)))
+        })
+    });
+
+    let body = stream::once(future::ready(Ok::<String, Infallible>(created)))
+        .chain(events)
+        .filter(|result| {
+            future::ready(match result {
+                Ok(s) => !s.is_empty(),
+                Err(_) => true,
+            })
+        });
+    res.stream(body);
+}
+
+async fn handle_non_stream_response(
+    res: &mut Response,
+    event_stream: EventStream,
+    builder: ResponseBuilder,
+    limit: OutputLimit,
+    refusal: Option<String>,
+) {
+    let mut ctx = match collect_events(event_stream).await {
+        Ok(ctx) => ctx,
+        Err((status, error_response)) => {
+            res.status_code(status);
+            res.render(Json(error_response));
+            return;
+        }
+    };
+
+    let (text, cut) = limit.apply(&finalize_content(&mut ctx));
+
+    let mut output = Vec::new();
+    if !ctx.reasoning_content.trim().is_empty() {
+        output.push(reasoning_item(
+            &format!("rs_{}", nanoid!(24)),
+            &ctx.reasoning_content,
+        ));
+    }
+    if !text.is_empty() {
+        output.push(message_item(
+            &format!("msg_{}", nanoid!(24)),
+            &text,
+            "completed",
+        ));
+    }
+    for tool_call in &ctx.tool_calls {
+        output.push(function_call_item(tool_call, "completed"));
+    }
+    if let Some(refusal) = &refusal {
+        output.push(refusal_item(&format!("msg_{}", nanoid!(24)), refusal));
+    }
+
+    let output_tokens = count_completion_tokens(&text);
+    builder
+        .usage_meter
+        .record(&builder.model, builder.input_tokens, output_tokens);
+    let response =
+        builder.response_object(response_status(cut.as_ref()), &output, Some(output_tokens));
+    builder.store(&response, &text, &ctx.tool_calls);
+
+    debug!(
+        "📤 Response body (sanitized, truncated):\n{}",
+        pretty_json_truncated(&redact_json_fields(&response), 64 * 1024)
+    );
+    debug!("------ Outgoing Response [200] /v1/responses ------");
+
+    res.render(Json(response));
+}
+
+// Shared data for assembling and storing a response object
+struct ResponseBuilder {
+    id: String,
+    created_at: i64,
+    model: String,
+    echo: Value,
+    input_tokens: u32,
+    store: bool,
+    owner: String,
+    history: Vec<Message>,
+    usage_meter: UsageMeter,
+}
+
+impl ResponseBuilder {
+    fn response_object(&self, status: &str, output: &[Value], output_tokens: Option<u32>) -> Value {
+        let mut response = self.echo.clone();
+        response["id"] = json!(self.id);
+        response["object"] = json!("response");
+        response["created_at"] = json!(self.created_at);
+        response["status"] = json!(status);
+        response["model"] = json!(self.model);
+        response["output"] = json!(output);
+        response["error"] = Value::Null;
+        // max_output_tokens is the only reason a reply is cut short
+        response["incomplete_details"] = if status == "incomplete" {
+            json!({"reason": "max_output_tokens"})
+        } else {
+            Value::Null
+        };
+        response["usage"] = match output_tokens {
+            Some(output_tokens) => json!({
+                "input_tokens": self.input_tokens,
+                "input_tokens_details": {"cached_tokens": 0},
+                "output_tokens": output_tokens,
+                "output_tokens_details": {"reasoning_tokens": 0},
+                "total_tokens": self.input_tokens + output_tokens
+            }),
+            None => Value::Null,
+        };
+        response
+    }
+
+    // Persist the finished response together with the conversation it continues
+    fn store(&self, response: &Value, text: &str, tool_calls: &[ChatToolCall]) {
+        if !self.store {
+            debug!("🔍 store=false, skipping response persistence: {}", self.id);
+            return;
+        }
+        let mut messages = self.history.clone();
+        messages.push(Message {
+            role: "assistant".to_string(),
+            content: if text.is_empty() {
+                None
+            } else {
+                Some(OpenAiContent::Text(text.to_string()))
+            },
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls.to_vec())
+            },
+            ..Default::default()
+        });
+        save_stored_response(
+            &self.id,
+            &StoredResponse {
+                owner: self.owner.clone(),
+                response: response.clone(),
+                messages,
+            },
+        );
+    }
+}
+
+// Output item currently receiving deltas
+struct OpenItem {
+    kind: &'static str,
+    id: String,
+    text: String,
+}
+
+// Tracks output items while translating deltas into Responses SSE events
+struct ResponsesStreamState {
+    builder: ResponseBuilder,
+    sequence_number: u64,
+    output: Vec<Value>,
+    open_item: Option<OpenItem>,
+    output_text: String,
+    tool_calls: Vec<ChatToolCall>,
+    limit: OutputLimit,
+    refusal: Option<String>,
+    finished: bool,
+}
+
+impl ResponsesStreamState {
+    fn new(builder: ResponseBuilder) -> Self {
+        Self {
+            builder,
+            sequence_number: 0,
+            output: Vec::new(),
+            open_item: None,
+            output_text: String::new(),
+            tool_calls: Vec::new(),
+            limit: OutputLimit::default(),
+            refusal: None,
+            finished: false,
+        }
+    }
+
+    fn event(&mut self, name: &str, mut data: Value) -> String {
+        data["type"] = json!(name);
+        data["sequence_number"] = json!(self.sequence_number);
+        self.sequence_number += 1;
+        format!("event: {}\ndata: {}\n\n", name, data)
+    }
+
+    fn start(&mut self) -> String {
+        let response = self.builder.response_object("in_progress", &[], None);
+        let mut output = self.event("response.created", json!({ "response": response }));
+        output.push_str(&self.event("response.in_progress", json!({ "response": response })));
+        output
+    }
+
+    fn render(&mut self, delta: EventDelta) -> String {
+        match delta {
+            EventDelta::Reasoning(text) => {
+                let mut output = self.ensure_item("reasoning");
+                let output_index = self.output.len();
+                let item_id = match self.open_item.as_mut() {
+                    Some(item) => {
+                        item.text.push_str(&text);
+                        item.id.clone()
+                    }
+                    None => return output,
+                };
+                output.push_str(&self.event(
+                    "response.reasoning_summary_text.delta",
+                    json!({
+                        "item_id": item_id,
+                        "output_index": output_index,
+                        "summary_index": 0,
+                        "delta": text
+                    }),
+                ));
+                output
+            }
+            EventDelta::Content(text) => {
+                let text = self.limit.push(&text);
+                let mut output = self.text_delta(text);
+                if self.limit.cut().is_some() {
+                    output.push_str(&self.complete());
+                }
+                output
+            }
+            EventDelta::ToolCalls(tool_calls) => {
+                let mut output = self.close_item();
+                for tool_call in tool_calls {
+                    let output_index = self.output.len();
+                    let added = function_call_item(&tool_call.call, "in_progress");
+                    let item_id = added["id"].clone();
+                    let arguments = tool_call.call.function.arguments.clone();
+                    output.push_str(&self.event(
+                        "response.output_item.added",
+                        json!({ "output_index": output_index, "item": added }),
+                    ));
+                    output.push_str(&self.event(
+                        "response.function_call_arguments.delta",
+                        json!({
+                            "item_id": item_id,
+                            "output_index": output_index,
+                            "delta": arguments
+                        }),
+                    ));
+                    output.push_str(&self.event(
+                        "response.function_call_arguments.done",
+                        json!({
+                            "item_id": item_id,
+                            "output_index": output_index,
+                            "arguments": arguments
+                        }),
+                    ));
+                    let done = function_call_item(&tool_call.call, "completed");
+                    output.push_str(&self.event(
+                        "response.output_item.done",
+                        json!({ "output_index": output_index, "item": done }),
+                    ));
+                    self.output.push(done);
+                    self.tool_calls.push(tool_call.call);
+                }
+                output
+            }
+            EventDelta::Error(_, error_response) => {
+                debug!("❌ Detected error, interrupting Responses stream");
+                self.finished = true;
+                self.event(
+                    "error",
+                    json!({
+                        "code": error_response.error.code,
+                        "message": error_response.error.message,
+                        "param": error_response.error.param
+                    }),
+                )
+            }
+            EventDelta::Done => {
+                let text = self.limit.finish();
+                let mut output = self.text_delta(text);
+                output.push_str(&self.complete());
+                output
+            }
+        }
+    }
+
+    fn text_delta(&mut self, text: String) -> String {
+        if text.is_empty() {
+            return String::new();
+        }
+        let mut output = self.ensure_item("message");
+        let output_index = self.output.len();
+        self.output_text.push_str(&text);
+        let item_id = match self.open_item.as_mut() {
+            Some(item) => {
+                item.text.push_str(&text);
+                item.id.clone()
+            }
+            None => return output,
+        };
+        output.push_str(&self.event(
+            "response.output_text.delta",
+            json!({
+                "item_id": item_id,
+                "output_index": output_index,
+                "content_index": 0,
+                "delta": text
+            }),
+        ));
+        output
+    }
+
+    // Close the open item and send the final response, nothing is sent after this
+    fn complete(&mut self) -> String {
+        self.finished = true;
+        let mut output = self.close_item();
+        if let Some(refusal) = self.refusal.take() {
+            output.push_str(&self.refusal_events(&refusal));
+        }
+        let output_tokens = count_completion_tokens(&self.output_text);
+        debug!(
+            "📊 Token usage statistics | input_tokens: {} | output_tokens: {}",
+            self.builder.input_tokens, output_tokens
+        );
+        self.builder.usage_meter.record(
+            &self.builder.model,
+            self.builder.input_tokens,
+            output_tokens,
+        );
+        let status = response_status(self.limit.cut());
+        let response = self
+            .builder
+            .response_object(status, &self.output, Some(output_tokens));
+        self.builder
+            .store(&response, &self.output_text, &self.tool_calls);
+        output.push_str(&self.event(
+            &format!("response.{}", status),
+            json!({ "response": response }),
+        ));
+        output
+    }
+
+    // A failed structured output check is sent as a message with a refusal part
+    fn refusal_events(&mut self, refusal: &str) -> String {
+        let output_index = self.output.len();
+        let id = format!("msg_{}", nanoid!(24));
+        let mut added = refusal_item(&id, "");
+        added["status"] = json!("in_progress");
+        added["content"] = json!([]);
+        let mut output = self.event(
+            "response.output_item.added",
+            json!({ "output_index": output_index, "item": added }),
+        );
+        output.push_str(&self.event(
+            "response.content_part.added",
+            json!({
+                "item_id": id,
+                "output_index": output_index,
+                "content_index": 0,
+                "part": {"type": "refusal", "refusal": ""}
+            }),
+        ));
+        output.push_str(&self.event(
+            "response.refusal.delta",
+            json!({
+                "item_id": id,
+                "output_index": output_index,
+                "content_index": 0,
+                "delta": refusal
+            }),
+        ));
+        output.push_str(&self.event(
+            "response.refusal.done",
+            json!({
+                "item_id": id,
+                "output_index": output_index,
+                "content_index": 0,
+                "refusal": refusal
+            }),
+        ));
+        output.push_str(&self.event(
+            "response.content_part.done",
+            json!({
+                "item_id": id,
+                "output_index": output_index,
+                "content_index": 0,
+                "part": {"type": "refusal", "refusal": refusal}
+            }),
+        ));
+        let done = refusal_item(&id, refusal);
+        output.push_str(&self.event(
+            "response.output_item.done",
+            json!({ "output_index": output_index, "item": done }),
+        ));
+        self.output.push(done);
+        output
+    }
+
+    // Open an output item of the given kind, closing any different item first
+    fn ensure_item(&mut self, kind: &'static str) -> String {
+        if self.open_item.as_ref().map(|item| item.kind) == Some(kind) {
+            return String::new();
+        }
+        let mut output = self.close_item();
+        let output_index = self.output.len();
+        let id = if kind == "reasoning" {
+            format!("rs_{}", nanoid!(24))
+        } else {
+            format!("msg_{}", nanoid!(24))
+        };
+
+        if kind == "reasoning" {
+            output.push_str(&self.event(
+                "response.output_item.added",
+                json!({
+                    "output_index": output_index,
+                    "item": {"type": "reasoning", "id": id, "summary": []}
+                }),
+            ));
+            output.push_str(&self.event(
+                "response.reasoning_summary_part.added",
+                json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "summary_index": 0,
+                    "part": {"type": "summary_text", "text": ""}
+                }),
+            ));
+        } else {
+            output.push_str(&self.event(
+                "response.output_item.added",
+                json!({
+                    "output_index": output_index,
+                    "item": {
+                        "type": "message",
+                        "id": id,
+                        "status": "in_progress",
+                        "role": "assistant",
+                        "content": []
+                    }
+                }),
+            ));
+            output.push_str(&self.event(
+                "response.content_part.added",
+                json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "part": {"type": "output_text", "text": "", "annotations": []}
+                }),
+            ));
+        }
+
+        self.open_item = Some(OpenItem {
+            kind,
+            id,
+            text: String::new(),
+        });
+        output
+    }
+
+    fn close_item(&mut self) -> String {
+        let Some(item) = self.open_item.take() else {
+            return String::new();
+        };
+        let output_index = self.output.len();
+        let mut output = String::new();
+
+        let done = if item.kind == "reasoning" {
+            output.push_str(&self.event(
+                "response.reasoning_summary_text.done",
+                json!({
+                    "item_id": item.id,
+                    "output_index": output_index,
+                    "summary_index": 0,
+                    "text": item.text
+                }),
+            ));
+            output.push_str(&self.event(
+                "response.reasoning_summary_part.done",
+                json!({
+                    "item_id": item.id,
+                    "output_index": output_index,
+                    "summary_index": 0,
+                    "part": {"type": "summary_text", "text": item.text}
+                }),
+            ));
+            reasoning_item(&item.id, &item.text)
+        } else {
+            output.push_str(&self.event(
+                "response.output_text.done",
+                json!({
+                    "item_id": item.id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "text": item.text
+                }),
+            ));
+            output.push_str(&self.event(
+                "response.content_part.done",
+                json!({
+                    "item_id": item.id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "part": {"type": "output_text", "text": item.text, "annotations": []}
+                }),
+            ));
+            message_item(&item.id, &item.text, "completed")
+        };
+
+        output.push_str(&self.event(
+            "response.output_item.done",
+            json!({ "output_index": output_index, "item": done }),
+        ));
+        self.output.push(done);
+        output
+    }
+}
+
+fn reasoning_item(id: &str, text: &str) -> Value {
+    json!({
+        "type": "reasoning",
+        "id": id,
+        "summary": [{"type": "summary_text", "text": text}]
+    })
+}
+
+fn message_item(id: &str, text: &str, status: &str) -> Value {
+    json!({
+        "type": "message",
+        "id": id,
+        "status": status,
+        "role": "assistant",
+        "content": [{"type": "output_text", "text": text, "annotations": []}]
+    })
+}
+
+fn refusal_item(id: &str, refusal: &str) -> Value {
+    json!({
+        "type": "message",
+        "id": id,
+        "status": "completed",
+        "role": "assistant",
+        "content": [{"type": "refusal", "refusal": refusal}]
+    })
+}
+
+// Replies cut at max_output_tokens are incomplete
+fn response_status(cut: Option<&OutputCut>) -> &'static str {
+    match cut {
+        Some(OutputCut::MaxTokens) => "incomplete",
+        _ => "completed",
+    }
+}
+
+fn function_call_item(tool_call: &ChatToolCall, status: &str) -> Value {
+    json!({
+        "type": "function_call",
+        "id": format!("fc_{}", tool_call.id),
+        "call_id": tool_call.id,
+        "name": tool_call.function.name,
+        "arguments": tool_call.function.arguments,
+        "status": status
+    })
+}
+
+// Request parameters echoed back on every response object
+fn request_echo(request: &ResponsesRequest) -> Value {
+    json!({
+        "instructions": request.instructions,
+        "previous_response_id": request.previous_response_id,
+        "tools": request.tools.clone().unwrap_or_default(),
+        "tool_choice": request.tool_choice.clone().unwrap_or_else(|| json!("auto")),
+        "parallel_tool_calls": request.parallel_tool_calls.unwrap_or(true),
+        "temperature": request.temperature,
+        "top_p": request.top_p,
+        "max_output_tokens": request.max_output_tokens,
+        "store": request.store.unwrap_or(true),
+        "reasoning": request.reasoning.clone().unwrap_or_else(|| json!({"effort": null})),
+        "text": request.text.clone().unwrap_or_else(|| json!({"format": {"type": "text"}})),
+        "metadata": request.metadata.clone().unwrap_or_else(|| json!({})),
+        "user": request.user
+    })
+}
+
+/// Convert Responses API input into chat messages (without instructions)
+pub(crate) fn responses_input_to_messages(input: &ResponsesInput) -> Vec<Message> {
+    let items = match input {
+        ResponsesInput::Text(text) => {
+            return vec![Message {
+                role: "user".to_string(),
+                content: Some(OpenAiContent::Text(text.clone())),
+                ..Default::default()
+            }];
+        }
+        ResponsesInput::Items(items) => items,
+    };
+
+    let mut messages: Vec<Message> = Vec::new();
+    for item in items {
+        match item {
+            ResponsesInputItem::Message(message)
+            | ResponsesInputItem::Typed(ResponsesTypedItem::Message(message)) => {
+                // Responses API uses developer for system-level instructions
+                let role = if message.role == "developer" {
+                    "system".to_string()
+                } else {
+                    message.role.clone()
+                };
+                messages.push(Message {
+                    role,
+                    content: Some(convert_content(&message.content)),
+                    ..Default::default()
+                });
+            }
+            ResponsesInputItem::Typed(ResponsesTypedItem::FunctionCall {
+                call_id,
+                name,
+                arguments,
+            }) => {
+                let tool_call = match serde_json::from_value::<ChatToolCall>(json!({
+                    "id": call_id,
+                    "type": "function",
+                    "function": {"name": name, "arguments": arguments}
+                })) {
+                    Ok(tool_call) => tool_call,
+                    Err(e) => {
+                        warn!("⚠️ Skipping function_call item {}: {}", call_id, e);
+                        continue;
+                    }
+                };
+                // Consecutive function calls belong to the same assistant turn
+                match messages.last_mut() {
+                    Some(last) if last.role == "assistant" => {
+                        last.tool_calls.get_or_insert_with(Vec::new).push(tool_call);
+                    }
+                    _ => messages.push(Message {
+                        role: "assistant".to_string(),
+                        tool_calls: Some(vec![tool_call]),
+                        ..Default::default()
+                    }),
+                }
+            }
+            ResponsesInputItem::Typed(ResponsesTypedItem::FunctionCallOutput {
+                call_id,
+                output,
+            }) => {
+                let output_text = match output {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                };
+                messages.push(Message {
+                    role: "tool".to_string(),
+                    content: Some(OpenAiContent::Text(output_text)),
+                    tool_call_id: Some(call_id.clone()),
+                    ..Default::default()
+                });
+            }
+            ResponsesInputItem::Typed(ResponsesTypedItem::Unsupported) => {
+                debug!("🔍 Skipping unsupported Responses input item");
+            }
+        }
+    }
+    messages
+}
+
+fn convert_content(content: &ResponsesContent) -> OpenAiContent {
+    let parts = match content {
+        ResponsesContent::Text(text) => return OpenAiContent::Text(text.clone()),
+        ResponsesContent::Parts(parts) => parts,
+    };
+
+    let mut items = Vec::new();
+    for part in parts {
+        match part {
+            ResponsesContentPart::InputText { text }
+            | ResponsesContentPart::OutputText { text }
+            | ResponsesContentPart::Refusal { refusal: text } => {
+                items.push(OpenAiContentItem::Text {
+                    r#type: Some("text".to_string()),
+                    text: text.clone(),
+                    extra: HashMap::new(),
+                })
+            }
+            ResponsesContentPart::InputImage {
+                image_url: Some(url),
+                ..
+            }
+            | ResponsesContentPart::InputFile {
+                file_data: Some(url),
+                ..
+            } => items.push(OpenAiContentItem::ImageUrl {
+                r#type: Some("image_url".to_string()),
+                image_url: ImageUrlContent {
+                    url: url.clone(),
+                    extra: HashMap::new(),
+                },
+                extra: HashMap::new(),
+            }),
+            // file_id references are resolved (or rejected) by process_message_images
+            ResponsesContentPart::InputImage { file_id, .. }
+            | ResponsesContentPart::InputFile { file_id, .. } => {
+                items.push(OpenAiContentItem::Other(json!({
+                    "type": "file",
+                    "file": {"file_id": file_id}
+                })))
+            }
+            ResponsesContentPart::Unsupported => {
+                debug!("🔍 Skipping unsupported Responses content part");
+            }
+        }
+    }
+    OpenAiContent::Multi(items)
+}
+
+/// Convert a Responses API request plus rebuilt history into the internal chat completion request
+pub(crate) fn responses_to_chat_request(
+    request: &ResponsesRequest,
+    history: &[Message],
+) -> ChatCompletionRequest {
+    let mut messages = Vec::new();
+    // Instructions only apply to the current request and are not carried over
+    if let Some(instructions) = request.instructions.as_ref().filter(|i| !i.is_empty()) {
+        messages.push(Message {
+            role: "system".to_string(),
+            content: Some(OpenAiContent::Text(instructions.clone())),
+            ..Default::default()
+        });
+    }
+    messages.extend(history.iter().cloned());
+
+    let tools: Option<Vec<ChatTool>> = request.tools.as_ref().map(|tools| {
+        tools
+            .iter()
+            .filter_map(|tool| {
+                if tool.get("type").and_then(|t| t.as_str()) != Some("function") {
+                    warn!("⚠️ Skipping unsupported Responses tool: {}", tool);
+                    return None;
+                }
+                let value = json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.get("name"),
+                        "description": tool.get("description"),
+                        "parameters": tool.get("parameters").cloned().unwrap_or_else(|| json!({"type": "object"}))
+                    }
+                });
+                match serde_json::from_value::<ChatTool>(value) {
+                    Ok(chat_tool) => Some(chat_tool),
+                    Err(e) => {
+                        warn!("⚠️ Skipping Responses tool: {}", e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    });
+
+    // Named tool choice is flat in the Responses API: {"type": "function", "name": "..."}
+    let tool_choice = request.tool_choice.as_ref().map(|choice| match choice {
+        Value::Object(map) if map.get("type").and_then(|t| t.as_str()) == Some("function") => {
+            json!({"type": "function", "function": {"name": map.get("name")}})
+        }
+        other => other.clone(),
+    });
+
+    // text.format maps to response_format, json_schema fields are nested in chat completions
+    let response_format = request
+        .text
+        .as_ref()
+        .and_then(|text| text.get("format"))
+        .map(|format| match format.get("type").and_then(|t| t.as_str()) {
+            Some("json_schema") => json!({
+                "type": "json_schema",
+                "json_schema": {
+                    "name": format.get("name"),
+                    "schema": format.get("schema"),
+                    "strict": format.get("strict")
+                }
+            }),
+            _ => format.clone(),
+        });
+
+    let reasoning_effort = request
+        .reasoning
+        .as_ref()
+        .and_then(|reasoning| reasoning.get("effort"))
+        .and_then(|effort| effort.as_str())
+        .map(|effort| effort.to_string());
+
+    ChatCompletionRequest {
+        model: request.model.clone(),
+        messages,
+        temperature: request.temperature,
+        top_p: request.top_p,
+        max_tokens: request.max_output_tokens,
+        stream: request.stream,
+        tools,
+        tool_choice,
+        parallel_tool_calls: request.parallel_tool_calls,
+        response_format,
+        reasoning_effort,
+        metadata: request.metadata.clone(),
+        user: request.user.clone(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_function_call_items_into_history() {
+        let input: ResponsesInput = serde_json::from_value(json!([
+            {"role": "developer", "content": "Be brief"},
+            {"type": "message", "role": "user", "content": [
+                {"type": "input_text", "text": "Weather in Paris?"}
+            ]},
+            {"type": "function_call", "call_id": "call_1", "name": "get_weather", "arguments": "{\"city\":\"Paris\"}"},
+            {"type": "function_call", "call_id": "call_2", "name": "get_time", "arguments": "{}"},
+            {"type": "function_call_output", "call_id": "call_1", "output": "Sunny"},
+            {"type": "reasoning", "summary": []}
+        ]))
+        .expect("valid input");
+
+        let messages = responses_input_to_messages(&input);
+        let roles: Vec<&str> = messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "tool"]);
+        assert_eq!(messages[2].tool_calls.as_ref().map(|c| c.len()), Some(2));
+        assert_eq!(messages[3].tool_call_id.as_deref(), Some("call_1"));
+    }
+
+    fn test_builder() -> ResponseBuilder {
+        ResponseBuilder {
+            id: "resp_1".to_string(),
+            created_at: 0,
+            model: "m".to_string(),
+            echo: json!({}),
+            input_tokens: 3,
+            store: false,
+            owner: String::new(),
+            history: Vec::new(),
+            usage_meter: UsageMeter::default(),
+        }
+    }
+
+    #[test]
+    fn stream_state_emits_completed_response() {
+        let mut state = ResponsesStreamState::new(test_builder());
+        assert!(state.start().starts_with("event: response.created"));
+        let text = state.render(EventDelta::Content("Hi".to_string()));
+        assert!(text.contains("event: response.output_text.delta"));
+        let done = state.render(EventDelta::Done);
+        assert!(done.starts_with("event: response.output_text.done"));
+        assert!(done.contains("event: response.completed"));
+        assert!(done.contains("\"status\":\"completed\""));
+    }
+
+    #[test]
+    fn stream_state_reports_incomplete_and_refusal() {
+        let mut state = ResponsesStreamState::new(test_builder());
+        state.limit = OutputLimit::new(&[], Some(1));
+        let text = state.render(EventDelta::Content("one two three".to_string()));
+        assert!(text.contains("event: response.incomplete"));
+        assert!(text.contains("\"reason\":\"max_output_tokens\""));
+        assert!(state.finished);
+
+        let mut state = ResponsesStreamState::new(test_builder());
+        state.refusal = Some("invalid output".to_string());
+        let done = state.render(EventDelta::Done);
+        assert!(done.contains("event: response.refusal.done"));
+        assert!(done.contains("{\"refusal\":\"invalid output\",\"type\":\"refusal\"}"));
+        assert!(done.contains("event: response.completed"));
+    }
+}
@@ -0,0 +1,228 @@
+use crate::cache::get_persistent_db;
+use crate::pool::POOL_ACCESS_KEY;
+use crate::types::{KeyBudget, KeyLimits};
+use crate::utils::hash_token;
+use chrono::Utc;
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+
+/// Prefix of proxy-issued API keys, any other bearer value is treated as a raw Poe token
+pub const API_KEY_PREFIX: &str = "sk-p2o-";
+
+const API_KEYS_TREE: &str = "api_keys";
+
+/// Proxy-issued API key, stored under the SHA-256 hash of the key itself
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiKeyRecord {
+    pub id: String,
+    pub name: String,
+    pub key_hash: String,
+    // Leading characters of the key so admins can tell keys apart
+    pub key_prefix: String,
+    // Empty when the key is served from the token pool
+    pub poe_token: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub revoked: bool,
+    #[serde(default)]
+    pub limits: KeyLimits,
+    #[serde(default)]
+    pub budget: KeyBudget,
+}
+
+impl ApiKeyRecord {
+    /// Check whether the key may be used at the given unix time
+    pub fn check_usable(&self, now: i64) -> Result<(), &'static str> {
+        if self.revoked {
+            return Err("API key has been revoked");
+        }
+        if self.expires_at.is_some_and(|expires_at| now >= expires_at) {
+            return Err("API key has expired");
+        }
+        Ok(())
+    }
+}
+
+/// Whether raw Poe tokens are still accepted as bearer tokens (ALLOW_RAW_POE_TOKENS, default true)
+pub fn raw_poe_tokens_allowed() -> bool {
+    std::env::var("ALLOW_RAW_POE_TOKENS")
+        .map(|v| !matches!(v.to_lowercase().as_str(), "false" | "0" | "no"))
+        .unwrap_or(true)
+}
+
+fn open_keys_tree() -> Result<sled::Tree, String> {
+    get_persistent_db()
+        .open_tree(API_KEYS_TREE)
+        .map_err(|e| format!("Unable to open API keys tree: {}", e))
+}
+
+/// Create a new API key, returns the raw key (shown once) and the stored record
+pub fn create_api_key(
+    name: &str,
+    poe_token: &str,
+    expires_at: Option<i64>,
+    limits: KeyLimits,
+    budget: KeyBudget,
+) -> Result<(String, ApiKeyRecord), String> {
+    let raw_key = format!("{}{}", API_KEY_PREFIX, nanoid!(40));
+    let record = ApiKeyRecord {
+        id: format!("key_{}", nanoid!(12)),
+        name: name.to_string(),
+        key_hash: hash_token(&raw_key),
+        key_prefix: raw_key.chars().take(API_KEY_PREFIX.len() + 4).collect(),
+        poe_token: poe_token.to_string(),
+        created_at: Utc::now().timestamp(),
+        expires_at,
+        revoked: false,
+        limits,
+        budget,
+    };
+    save_api_key(&record)?;
+    info!(
+        "🔑 API key created | ID: {} | Name: {}",
+        record.id, record.name
+    );
+    Ok((raw_key, record))
+}
+
+fn save_api_key(record: &ApiKeyRecord) -> Result<(), String> {
+    let tree = open_keys_tree()?;
+    let bytes =
+        serde_json::to_vec(record).map_err(|e| format!("Failed to serialize API key: {}", e))?;
+    tree.insert(record.key_hash.as_bytes(), bytes)
+        .map_err(|e| format!("Failed to save API key: {}", e))?;
+    get_persistent_db().flush().ok();
+    Ok(())
+}
+
+/// List all API keys, newest first
+pub fn list_api_keys() -> Vec<ApiKeyRecord> {
+    let tree = match open_keys_tree() {
+        Ok(tree) => tree,
+        Err(e) => {
+            error!("❌ {}", e);
+            return Vec::new();
+        }
+    };
+    let mut records: Vec<ApiKeyRecord> = tree
+        .iter()
+        .values()
+        .filter_map(|value| value.ok())
+        .filter_map(|bytes| serde_json::from_slice(&bytes).ok())
+        .collect();
+    records.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    records
+}
+
+/// Revoke an API key by id, returns false when no key has that id
+pub fn revoke_api_key(id: &str) -> Result<bool, String> {
+    let Some(mut record) = list_api_keys().into_iter().find(|r| r.id == id) else {
+        return Ok(false);
+    };
+    record.revoked = true;
+    save_api_key(&record)?;
+    info!(
+        "🚫 API key revoked | ID: {} | Name: {}",
+        record.id, record.name
+    );
+    Ok(true)
+}
+
+/// Replace the rate limits and/or budget of a key, returns None when no key has that id
+pub fn update_api_key(
+    id: &str,
+    limits: Option<KeyLimits>,
+    budget: Option<KeyBudget>,
+) -> Result<Option<ApiKeyRecord>, String> {
+    let Some(mut record) = list_api_keys().into_iter().find(|r| r.id == id) else {
+        return Ok(None);
+    };
+    if let Some(limits) = limits {
+        record.limits = limits;
+    }
+    if let Some(budget) = budget {
+        record.budget = budget;
+    }
+    save_api_key(&record)?;
+    info!(
+        "✏️ API key updated | ID: {} | Name: {}",
+        record.id, record.name
+    );
+    Ok(Some(record))
+}
+
+/// Look up the record of a proxy-issued key
+pub fn find_api_key(raw_key: &str) -> Option<ApiKeyRecord> {
+    let tree = match open_keys_tree() {
+        Ok(tree) => tree,
+        Err(e) => {
+            error!("❌ {}", e);
+            return None;
+        }
+    };
+    match tree.get(hash_token(raw_key).as_bytes()) {
+        Ok(Some(bytes)) => serde_json::from_slice(&bytes).ok(),
+        Ok(None) => None,
+        Err(e) => {
+            error!("❌ Failed to read API key: {}", e);
+            None
+        }
+    }
+}
+
+/// Resolve a client bearer token into the upstream Poe token
+pub fn resolve_access_key(bearer: &str) -> Result<String, &'static str> {
+    if !bearer.starts_with(API_KEY_PREFIX) {
+        if raw_poe_tokens_allowed() {
+            return Ok(bearer.to_string());
+        }
+        warn!("🚫 Raw Poe token rejected (ALLOW_RAW_POE_TOKENS=false)");
+        return Err("Invalid API key");
+    }
+    let Some(record) = find_api_key(bearer) else {
+        warn!("🚫 Unknown API key presented");
+        return Err("Invalid API key");
+    };
+    record
+        .check_usable(Utc::now().timestamp())
+        .inspect_err(|reason| {
+            warn!("🚫 API key rejected | ID: {} | {}", record.id, reason);
+        })?;
+    debug!(
+        "🔑 API key resolved | ID: {} | Name: {}",
+        record.id, record.name
+    );
+    if record.poe_token.is_empty() {
+        return Ok(POOL_ACCESS_KEY.to_string());
+    }
+    Ok(record.poe_token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(expires_at: Option<i64>, revoked: bool) -> ApiKeyRecord {
+        ApiKeyRecord {
+            id: "key_test".to_string(),
+            name: "test".to_string(),
+            key_hash: hash_token("sk-p2o-test"),
+            key_prefix: "sk-p2o-test".to_string(),
+            poe_token: "poe".to_string(),
+            created_at: 1_000,
+            expires_at,
+            revoked,
+            limits: KeyLimits::default(),
+            budget: KeyBudget::default(),
+        }
+    }
+
+    #[test]
+    fn check_usable_honours_expiry_and_revocation() {
+        assert!(record(None, false).check_usable(2_000).is_ok());
+        assert!(record(Some(3_000), false).check_usable(2_000).is_ok());
+        assert!(record(Some(2_000), false).check_usable(2_000).is_err());
+        assert!(record(None, true).check_usable(2_000).is_err());
+    }
+}
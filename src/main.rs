@@ -1,15 +1,19 @@
 use salvo::prelude::*;
 use std::env;
 use std::path::Path;
-use std::sync::Arc;
-use std::time::Duration;
-use tracing::{debug, info};
+use tracing::{debug, error, info, warn};
 
+mod admin_tokens;
+mod auth;
 mod cache;
 mod evert;
 mod handlers;
+mod keys;
 mod poe_client;
+mod policy;
+mod pool;
 mod types;
+mod usage;
 mod utils;
 
 #[global_allocator]
@@ -79,31 +83,71 @@ fn log_cache_settings() {
     );
 }
 
+// `poe2openai hash-password [password]` prints a value for ADMIN_PASSWORD_HASH
+fn print_password_hash() {
+    let password = match env::args().nth(2) {
+        Some(password) => password,
+        None => {
+            let mut line = String::new();
+            if let Err(e) = std::io::stdin().read_line(&mut line) {
+                eprintln!("Failed to read password: {}", e);
+                std::process::exit(1);
+            }
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    match auth::hash_password(&password) {
+        Ok(hash) => println!("{}", hash),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn check_admin_credentials() {
+    if auth::uses_default_credentials() {
+        let allow_insecure = env::var("ADMIN_ALLOW_INSECURE_DEFAULTS").is_ok_and(|v| v == "true");
+        if !allow_insecure {
+            error!(
+                "❌ Refusing to start with the default admin password. Set ADMIN_PASSWORD_HASH (or ADMIN_PASSWORD), or ADMIN_ALLOW_INSECURE_DEFAULTS=true for local testing"
+            );
+            std::process::exit(1);
+        }
+        warn!("⚠️ Admin interface uses the default password (ADMIN_ALLOW_INSECURE_DEFAULTS=true)");
+    } else if auth::uses_plaintext_password() {
+        warn!(
+            "⚠️ ADMIN_PASSWORD is stored in plaintext, consider ADMIN_PASSWORD_HASH (generate one with `poe2openai hash-password`)"
+        );
+    } else {
+        info!("🔐 Admin password: hashed (ADMIN_PASSWORD_HASH)");
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    if env::args().nth(1).as_deref() == Some("hash-password") {
+        print_password_hash();
+        return;
+    }
+
     let log_level = get_env_or_default("LOG_LEVEL", "debug");
     setup_logging(&log_level);
 
     // Initialize cache settings
     log_cache_settings();
 
-    // Initialize global rate limit
-    let _ = handlers::limit::GLOBAL_RATE_LIMITER.set(Arc::new(tokio::sync::Mutex::new(
-        std::time::Instant::now() - Duration::from_secs(60),
-    )));
-
     // Show rate limit settings
-    let rate_limit_ms = std::env::var("RATE_LIMIT_MS")
-        .ok()
-        .and_then(|s| s.parse::<u64>().ok())
-        .unwrap_or(100);
-
-    if rate_limit_ms == 0 {
-        info!("⚙️  Global rate limit: Disabled (RATE_LIMIT_MS=0)");
+    let rate_limit_rpm = get_env_or_default("RATE_LIMIT_RPM", "0");
+    let rate_limit_tpm = get_env_or_default("RATE_LIMIT_TPM", "0");
+    if rate_limit_rpm == "0" && rate_limit_tpm == "0" {
+        info!(
+            "⚙️  Default per-client rate limit: Disabled (per-key and per-model limits still apply)"
+        );
     } else {
         info!(
-            "⚙️  Global rate limit: Enabled (every {}ms per request)",
-            rate_limit_ms
+            "⚙️  Default per-client rate limit: {} RPM / {} TPM (0 = unlimited)",
+            rate_limit_rpm, rate_limit_tpm
         );
     }
 
@@ -111,6 +155,17 @@ async fn main() {
     let port = get_env_or_default("PORT", "8080");
     get_env_or_default("ADMIN_USERNAME", "admin");
     get_env_or_default("ADMIN_PASSWORD", "123456");
+    check_admin_credentials();
+    let trusted_proxies = get_env_or_default("TRUSTED_PROXIES", "");
+    if trusted_proxies.is_empty() {
+        info!("🌐 Trusted proxies: none, X-Forwarded-For / Forwarded are ignored");
+    } else {
+        info!("🌐 Trusted proxies: {}", trusted_proxies);
+    }
+    get_env_or_default("API_ALLOW_CIDRS", "");
+    get_env_or_default("API_DENY_CIDRS", "");
+    get_env_or_default("ADMIN_ALLOW_CIDRS", "");
+    get_env_or_default("ADMIN_DENY_CIDRS", "");
     let config_dir = get_env_or_default("CONFIG_DIR", "./");
     let config_path = Path::new(&config_dir).join("models.yaml");
     info!("📁 Configuration file path: {}", config_path.display());
@@ -133,6 +188,7 @@ async fn main() {
     info!("💾 Memory database initialization complete");
 
     let api_router = Router::new()
+        .hoop(handlers::api_access_middleware)
         .hoop(handlers::cors_middleware)
         .push(
             Router::with_path("models")
@@ -145,11 +201,39 @@ async fn main() {
                 .post(handlers::chat_completions)
                 .options(handlers::cors_middleware),
         )
+        .push(
+            Router::with_path("completions")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::completions)
+                .options(handlers::cors_middleware),
+        )
         .push(
             Router::with_path("api/models")
                 .get(handlers::get_models)
                 .options(handlers::cors_middleware),
         )
+        .push(
+            Router::with_path("api/tags")
+                .get(handlers::ollama_tags)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("api/show")
+                .post(handlers::ollama_show)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("api/chat")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::ollama_chat)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("api/generate")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::ollama_generate)
+                .options(handlers::cors_middleware),
+        )
         .push(
             Router::with_path("v1/models")
                 .get(handlers::get_models)
@@ -160,6 +244,60 @@ async fn main() {
                 .hoop(handlers::rate_limit_middleware)
                 .post(handlers::chat_completions)
                 .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/completions")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::completions)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/images/generations")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::image_generations)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/audio/speech")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::audio_speech)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/audio/transcriptions")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::audio_transcriptions)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/audio/translations")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::audio_translations)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/messages")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::anthropic_messages)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1beta/models/{target}")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::gemini_generate_content)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/responses")
+                .hoop(handlers::rate_limit_middleware)
+                .post(handlers::create_response)
+                .options(handlers::cors_middleware),
+        )
+        .push(
+            Router::with_path("v1/responses/{id}")
+                .get(handlers::get_response)
+                .delete(handlers::delete_response)
+                .options(handlers::cors_middleware),
         );
 
     let router: Router = Router::new()
@@ -1,7 +1,10 @@
 use crate::{
     cache::get_cached_config,
     types::*,
-    utils::{extract_tool_call_id, filter_tools_for_poe, get_text_from_openai_content},
+    utils::{
+        apply_tool_choice, extract_tool_call_id, filter_tools_for_poe,
+        get_text_from_openai_content, parse_tool_choice,
+    },
 };
 use futures_util::Stream;
 use poe_api_process::types::Attachment;
@@ -130,11 +133,13 @@ fn openai_message_to_poe(
                         OpenAiContentItem::ToolResult { .. } => {
                             debug!("🧰 Skipping tool_result content in message conversion");
                         }
+                        // process_message_images turns these into image_url attachments,
+                        // anything left here was never uploaded
                         OpenAiContentItem::InputAudio { .. } => {
-                            debug!("🎧 Skipping input_audio content in message conversion");
+                            warn!("⚠️ input_audio content was not uploaded and is dropped");
                         }
                         OpenAiContentItem::Other(value) => {
-                            debug!("🔍 Unhandled content block: {}", value);
+                            warn!("⚠️ Unhandled content block is dropped: {}", value);
                         }
                     }
                 }
@@ -199,7 +204,13 @@ pub async fn create_chat_request(
 ) -> ChatRequest {
     let temperature = chat_completion_request.temperature;
     let original_tools = chat_completion_request.tools.clone();
-    let tools = filter_tools_for_poe(&original_tools);
+    // tool_choice was validated in start_chat, an unparsable value falls back to auto
+    let tool_choice = parse_tool_choice(
+        chat_completion_request.tool_choice.as_ref(),
+        original_tools.as_deref(),
+    )
+    .unwrap_or(ToolChoice::Auto);
+    let tools = apply_tool_choice(filter_tools_for_poe(&original_tools), &tool_choice);
     let logit_bias = chat_completion_request.logit_bias.clone();
     let stop = chat_completion_request.stop.clone();
 
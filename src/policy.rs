@@ -0,0 +1,329 @@
+use crate::cache::get_persistent_db;
+use crate::types::{ChatCompletionRequest, OpenAIError, OpenAIErrorResponse};
+use salvo::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+
+const POLICIES_TREE: &str = "policies";
+
+/// Allowed range of a sampling parameter, both ends inclusive
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct ParamRange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f32>,
+}
+
+impl ParamRange {
+    fn contains(&self, value: f32) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+}
+
+/// Values filled in when the client leaves the parameter out
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PolicyDefaults {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<String>,
+}
+
+/// Model and parameter policy of one client credential, stored under the SHA-256 hash of its bearer token
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CredentialPolicy {
+    // Model patterns (`*` and `?` wildcards) matched against the original Poe model name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_models: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied_models: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<ParamRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<ParamRange>,
+    // Upper bound for max_tokens / max_completion_tokens, also applied when the client sends none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub defaults: PolicyDefaults,
+}
+
+/// Request rejected by a credential policy
+#[derive(Debug, PartialEq)]
+pub struct PolicyViolation {
+    pub forbidden: bool,
+    pub param: &'static str,
+    pub message: String,
+}
+
+impl PolicyViolation {
+    fn invalid(param: &'static str, message: String) -> Self {
+        Self {
+            forbidden: false,
+            param,
+            message,
+        }
+    }
+
+    /// OpenAI-style 403 for denied models, 400 for parameters outside the policy
+    pub fn into_response(self) -> (StatusCode, OpenAIErrorResponse) {
+        let (status, error_type) = if self.forbidden {
+            (StatusCode::FORBIDDEN, "permission_error")
+        } else {
+            (StatusCode::BAD_REQUEST, "invalid_request_error")
+        };
+        (
+            status,
+            OpenAIErrorResponse {
+                error: OpenAIError {
+                    message: self.message,
+                    r#type: error_type.to_string(),
+                    code: "policy_violation".to_string(),
+                    param: Some(self.param.to_string()),
+                },
+            },
+        )
+    }
+}
+
+/// Case-insensitive glob match supporting `*` and `?`
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text index it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, star_t)) = backtrack {
+            p = star + 1;
+            t = star_t + 1;
+            backtrack = Some((star, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+impl CredentialPolicy {
+    /// Whether the original (reverse-mapped) model may be used
+    pub fn allows_model(&self, original_model: &str) -> bool {
+        if self
+            .denied_models
+            .iter()
+            .any(|pattern| glob_match(pattern, original_model))
+        {
+            return false;
+        }
+        self.allowed_models.is_empty()
+            || self
+                .allowed_models
+                .iter()
+                .any(|pattern| glob_match(pattern, original_model))
+    }
+
+    pub fn check_model(
+        &self,
+        requested_model: &str,
+        original_model: &str,
+    ) -> Result<(), PolicyViolation> {
+        if self.allows_model(original_model) {
+            return Ok(());
+        }
+        Err(PolicyViolation {
+            forbidden: true,
+            param: "model",
+            message: format!(
+                "This API key is not allowed to use the model `{}`",
+                requested_model
+            ),
+        })
+    }
+
+    /// Fill in defaults, then reject parameters outside the allowed ranges
+    pub fn apply_to_request(
+        &self,
+        request: &mut ChatCompletionRequest,
+    ) -> Result<(), PolicyViolation> {
+        let defaults = &self.defaults;
+        if request.temperature.is_none() {
+            request.temperature = defaults.temperature;
+        }
+        if request.top_p.is_none() {
+            request.top_p = defaults.top_p;
+        }
+        if request.reasoning_effort.is_none() {
+            request.reasoning_effort = defaults.reasoning_effort.clone();
+        }
+        if request.output_token_limit().is_none() {
+            request.max_tokens = defaults.max_tokens.or(self.max_tokens);
+        }
+
+        let ranges = [
+            ("temperature", request.temperature, self.temperature),
+            ("top_p", request.top_p, self.top_p),
+        ];
+        for (param, value, range) in ranges {
+            if let Some((value, range)) = value
+                .zip(range)
+                .filter(|(value, range)| !range.contains(*value))
+            {
+                return Err(PolicyViolation::invalid(
+                    param,
+                    format!(
+                        "{} {} is outside the range allowed for this API key ({} to {})",
+                        param,
+                        value,
+                        range.min.map_or("-".to_string(), |v| v.to_string()),
+                        range.max.map_or("-".to_string(), |v| v.to_string()),
+                    ),
+                ));
+            }
+        }
+
+        if let Some((limit, max)) = request
+            .output_token_limit()
+            .zip(self.max_tokens)
+            .filter(|(limit, max)| limit > max)
+        {
+            let param = if request.max_completion_tokens.is_some() {
+                "max_completion_tokens"
+            } else {
+                "max_tokens"
+            };
+            return Err(PolicyViolation::invalid(
+                param,
+                format!(
+                    "{} {} exceeds the maximum allowed for this API key ({})",
+                    param, limit, max
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn open_policies_tree() -> Result<sled::Tree, String> {
+    get_persistent_db()
+        .open_tree(POLICIES_TREE)
+        .map_err(|e| format!("Unable to open policies tree: {}", e))
+}
+
+/// Policy of a credential, by the hash of its bearer token
+pub fn load_policy(credential: &str) -> Option<CredentialPolicy> {
+    let tree = match open_policies_tree() {
+        Ok(tree) => tree,
+        Err(e) => {
+            error!("❌ {}", e);
+            return None;
+        }
+    };
+    match tree.get(credential.as_bytes()) {
+        Ok(Some(bytes)) => serde_json::from_slice(&bytes)
+            .inspect_err(|e| warn!("⚠️ Unreadable policy for {}: {}", &credential[..12], e))
+            .ok(),
+        Ok(None) => None,
+        Err(e) => {
+            error!("❌ Failed to read policy: {}", e);
+            None
+        }
+    }
+}
+
+pub fn save_policy(credential: &str, policy: &CredentialPolicy) -> Result<(), String> {
+    let tree = open_policies_tree()?;
+    let bytes =
+        serde_json::to_vec(policy).map_err(|e| format!("Failed to serialize policy: {}", e))?;
+    tree.insert(credential.as_bytes(), bytes)
+        .map_err(|e| format!("Failed to save policy: {}", e))?;
+    get_persistent_db().flush().ok();
+    info!("📜 Policy saved | Credential: {}", &credential[..12]);
+    Ok(())
+}
+
+/// Remove a policy, returns false when the credential had none
+pub fn delete_policy(credential: &str) -> Result<bool, String> {
+    let tree = open_policies_tree()?;
+    let removed = tree
+        .remove(credential.as_bytes())
+        .map_err(|e| format!("Failed to delete policy: {}", e))?
+        .is_some();
+    get_persistent_db().flush().ok();
+    debug!(
+        "🗑️ Policy delete | Credential: {} | Removed: {}",
+        &credential[..12],
+        removed
+    );
+    Ok(removed)
+}
+
+/// All policies with their credential hashes
+pub fn list_policies() -> Vec<(String, CredentialPolicy)> {
+    let tree = match open_policies_tree() {
+        Ok(tree) => tree,
+        Err(e) => {
+            error!("❌ {}", e);
+            return Vec::new();
+        }
+    };
+    tree.iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|(key, value)| {
+            let policy = serde_json::from_slice(&value).ok()?;
+            Some((String::from_utf8_lossy(&key).to_string(), policy))
+        })
+        .collect()
+}
+
+/// Credential hashes are hex SHA-256 digests
+pub fn is_credential_hash(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn policy_filters_models_and_enforces_params() {
+        let policy: CredentialPolicy = serde_json::from_value(json!({
+            "allowed_models": ["gpt-4o*", "claude-*-haiku"],
+            "denied_models": ["gpt-4o-search"],
+            "temperature": { "min": 0.0, "max": 1.0 },
+            "max_tokens": 1024,
+            "defaults": { "temperature": 0.2 }
+        }))
+        .unwrap();
+        assert!(policy.allows_model("GPT-4o-mini"));
+        assert!(policy.allows_model("claude-3.5-haiku"));
+        assert!(!policy.allows_model("gpt-4o-search"));
+        assert!(!policy.allows_model("claude-opus-4"));
+
+        let mut request: ChatCompletionRequest =
+            serde_json::from_value(json!({ "model": "gpt-4o", "messages": [] })).unwrap();
+        policy.apply_to_request(&mut request).unwrap();
+        assert_eq!(request.temperature, Some(0.2));
+        assert_eq!(request.max_tokens, Some(1024));
+
+        request.temperature = Some(1.5);
+        let violation = policy.apply_to_request(&mut request).unwrap_err();
+        assert_eq!(violation.param, "temperature");
+
+        request.temperature = None;
+        request.max_completion_tokens = Some(4096);
+        let violation = policy.apply_to_request(&mut request).unwrap_err();
+        assert_eq!(violation.param, "max_completion_tokens");
+    }
+}
@@ -0,0 +1,237 @@
+use crate::types::{Config, PoolStrategy, TokenPoolConfig};
+use crate::utils::hash_token;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Access key that routes a request through the upstream token pool (proxy keys without a Poe token)
+pub const POOL_ACCESS_KEY: &str = "@pool";
+
+const DEFAULT_COOLDOWN_SECONDS: u64 = 3600;
+
+// Runtime state of one pool token, keyed by token hash so it survives config reloads
+#[derive(Default)]
+struct TokenState {
+    // Smooth weighted round-robin accumulator
+    current_weight: i64,
+    cooldown_until: Option<Instant>,
+    requests: u64,
+    exhaustions: u64,
+}
+
+#[derive(Default)]
+struct PoolState {
+    next_index: usize,
+    tokens: HashMap<String, TokenState>,
+}
+
+static POOL_STATE: OnceLock<Mutex<PoolState>> = OnceLock::new();
+
+fn pool_state() -> &'static Mutex<PoolState> {
+    POOL_STATE.get_or_init(|| Mutex::new(PoolState::default()))
+}
+
+/// Pool token selected for a request
+#[derive(Debug, Clone)]
+pub struct PoolToken {
+    pub name: String,
+    pub token: String,
+    pub hash: String,
+}
+
+fn pool_config(config: &Config) -> Option<&TokenPoolConfig> {
+    config
+        .token_pool
+        .as_ref()
+        .filter(|pool| !pool.tokens.is_empty())
+}
+
+// Pool tokens that are not cooling down, with their weights
+fn available_tokens(pool: &TokenPoolConfig, state: &PoolState) -> Vec<(PoolToken, i64)> {
+    let now = Instant::now();
+    pool.tokens
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let token = PoolToken {
+                name: entry
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("token-{}", i + 1)),
+                token: entry.token.clone(),
+                hash: hash_token(&entry.token),
+            };
+            (token, i64::from(entry.weight.unwrap_or(1).max(1)))
+        })
+        .filter(|(token, _)| {
+            let cooling = state
+                .tokens
+                .get(&token.hash)
+                .and_then(|s| s.cooldown_until)
+                .is_some_and(|until| until > now);
+            if cooling {
+                debug!("🧊 Pool token cooling down, skipped | Name: {}", token.name);
+            }
+            !cooling
+        })
+        .collect()
+}
+
+/// First available pool token, without advancing the rotation (used for file uploads)
+pub fn upload_token(config: &Config) -> Option<String> {
+    let pool = pool_config(config)?;
+    let state = pool_state().lock().unwrap();
+    available_tokens(pool, &state)
+        .into_iter()
+        .next()
+        .map(|(token, _)| token.token)
+}
+
+/// Tokens to try for one request, in order. Tokens that are cooling down are left out.
+pub fn candidates(config: &Config) -> Vec<PoolToken> {
+    let Some(pool) = pool_config(config) else {
+        return Vec::new();
+    };
+    let mut state = pool_state().lock().unwrap();
+    let mut available = available_tokens(pool, &state);
+    if available.is_empty() {
+        return Vec::new();
+    }
+
+    let first = match pool.strategy.unwrap_or_default() {
+        PoolStrategy::RoundRobin => {
+            let index = state.next_index % available.len();
+            state.next_index = state.next_index.wrapping_add(1);
+            index
+        }
+        PoolStrategy::Weighted => {
+            // Smooth weighted round-robin: spreads picks proportionally without randomness
+            let total: i64 = available.iter().map(|(_, weight)| weight).sum();
+            let mut best = 0;
+            let mut best_weight = i64::MIN;
+            for (i, (token, weight)) in available.iter().enumerate() {
+                let entry = state.tokens.entry(token.hash.clone()).or_default();
+                entry.current_weight += weight;
+                if entry.current_weight > best_weight {
+                    best_weight = entry.current_weight;
+                    best = i;
+                }
+            }
+            if let Some(entry) = state.tokens.get_mut(&available[best].0.hash) {
+                entry.current_weight -= total;
+            }
+            best
+        }
+    };
+    // The selected token goes first, the rest are failover candidates
+    available.rotate_left(first);
+    available.into_iter().map(|(token, _)| token).collect()
+}
+
+/// Record that a request was sent with a pool token
+pub fn record_use(token: &PoolToken) {
+    let mut state = pool_state().lock().unwrap();
+    state.tokens.entry(token.hash.clone()).or_default().requests += 1;
+}
+
+/// Put a token that ran out of points into cooldown
+pub fn mark_exhausted(config: &Config, token: &PoolToken) {
+    let cooldown = Duration::from_secs(
+        pool_config(config)
+            .and_then(|pool| pool.cooldown_seconds)
+            .unwrap_or(DEFAULT_COOLDOWN_SECONDS),
+    );
+    let mut state = pool_state().lock().unwrap();
+    let entry = state.tokens.entry(token.hash.clone()).or_default();
+    entry.cooldown_until = Some(Instant::now() + cooldown);
+    entry.exhaustions += 1;
+    warn!(
+        "🧊 Pool token out of points, cooling down | Name: {} | Cooldown: {:?}",
+        token.name, cooldown
+    );
+}
+
+/// Pool health for the admin API
+pub fn health(config: &Config) -> serde_json::Value {
+    let Some(pool) = pool_config(config) else {
+        return json!({ "enabled": false, "tokens": [] });
+    };
+    let now = Instant::now();
+    let state = pool_state().lock().unwrap();
+    let tokens: Vec<serde_json::Value> = pool
+        .tokens
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let hash = hash_token(&entry.token);
+            let token_state = state.tokens.get(&hash);
+            let cooldown_remaining = token_state
+                .and_then(|s| s.cooldown_until)
+                .filter(|until| *until > now)
+                .map(|until| until.duration_since(now).as_secs());
+            json!({
+                "name": entry.name.clone().unwrap_or_else(|| format!("token-{}", i + 1)),
+                "token_hash": &hash[..12],
+                "weight": entry.weight.unwrap_or(1),
+                "status": if cooldown_remaining.is_some() { "cooling_down" } else { "available" },
+                "cooldown_remaining_seconds": cooldown_remaining,
+                "requests": token_state.map_or(0, |s| s.requests),
+                "exhaustions": token_state.map_or(0, |s| s.exhaustions),
+            })
+        })
+        .collect();
+    info!("🩺 Pool health requested | Tokens: {}", tokens.len());
+    json!({
+        "enabled": true,
+        "strategy": pool.strategy.unwrap_or_default(),
+        "cooldown_seconds": pool.cooldown_seconds.unwrap_or(DEFAULT_COOLDOWN_SECONDS),
+        "tokens": tokens,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::PoolTokenConfig;
+
+    fn config(strategy: PoolStrategy, tokens: &[(&str, u32)]) -> Config {
+        Config {
+            token_pool: Some(TokenPoolConfig {
+                strategy: Some(strategy),
+                cooldown_seconds: Some(60),
+                tokens: tokens
+                    .iter()
+                    .map(|(token, weight)| PoolTokenConfig {
+                        name: Some(token.to_string()),
+                        token: token.to_string(),
+                        weight: Some(*weight),
+                    })
+                    .collect(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn weighted_pool_spreads_picks_and_skips_exhausted_tokens() {
+        let config = config(
+            PoolStrategy::Weighted,
+            &[("pool-test-heavy", 3), ("pool-test-light", 1)],
+        );
+        let picks: Vec<String> = (0..4)
+            .map(|_| candidates(&config)[0].name.clone())
+            .collect();
+        assert_eq!(picks.iter().filter(|n| *n == "pool-test-heavy").count(), 3);
+
+        let heavy = candidates(&config)
+            .into_iter()
+            .find(|t| t.name == "pool-test-heavy")
+            .unwrap();
+        mark_exhausted(&config, &heavy);
+        let remaining = candidates(&config);
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].name, "pool-test-light");
+    }
+}
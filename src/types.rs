@@ -1,8 +1,9 @@
+use crate::admin_tokens::AdminScope;
 use poe_api_process::types::{ChatTool, ChatToolCall};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Default, Deserialize, Serialize, Clone)]
 pub struct ChatCompletionRequest {
     pub model: String,
     pub messages: Vec<Message>,
@@ -17,6 +18,8 @@ pub struct ChatCompletionRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub n: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub seed: Option<u64>,
@@ -60,30 +63,37 @@ pub struct ChatCompletionRequest {
     pub extra_body: Option<ExtraBody>,
 }
 
-#[derive(Deserialize, Serialize)]
+impl ChatCompletionRequest {
+    // max_completion_tokens is the newer name for max_tokens and wins when both are set
+    pub fn output_token_limit(&self) -> Option<u32> {
+        self.max_completion_tokens.or(self.max_tokens)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct StreamOptions {
     pub include_usage: Option<bool>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct ThinkingConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub budget_tokens: Option<i32>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct ExtraBody {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub google: Option<GoogleConfig>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct GoogleConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thinking_config: Option<GoogleThinkingConfig>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct GoogleThinkingConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thinking_budget: Option<i32>,
@@ -180,7 +190,7 @@ pub struct ImageUrlContent {
 }
 
 // Update Message structure to use new OpenAiContent
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Default, Deserialize, Serialize, Clone)]
 pub struct Message {
     pub role: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -250,11 +260,30 @@ pub struct Delta {
     pub content: Option<String>,
     pub refusal: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tool_calls: Option<Vec<ChunkToolCall>>,
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning_content: Option<String>,
 }
 
+// Streamed tool call fragment: the first delta of a call carries id, type and name,
+// the following ones only append to function.arguments
+#[derive(Serialize, Clone, Debug)]
+pub struct ToolCallDelta {
+    pub index: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<String>,
+    pub function: FunctionCallDelta,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FunctionCallDelta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub arguments: String,
+}
+
 #[derive(Serialize, Clone, Debug)]
 pub struct ChunkToolCall {
     pub index: u32,
@@ -275,6 +304,706 @@ pub struct OpenAIError {
     pub param: Option<String>,
 }
 
+// Parsed tool_choice semantics
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolChoice {
+    Auto,
+    None,
+    Required,
+    Function(String),
+}
+
+// Structured output mode requested via response_format
+#[derive(Debug, Clone)]
+pub enum ResponseFormat {
+    JsonObject,
+    JsonSchema {
+        name: String,
+        schema: serde_json::Value,
+    },
+}
+
+// Anthropic Messages API (/v1/messages) request format
+#[derive(Deserialize, Serialize)]
+pub struct AnthropicMessagesRequest {
+    pub model: String,
+    pub messages: Vec<AnthropicMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<AnthropicContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<AnthropicTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<AnthropicThinking>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AnthropicMessage {
+    pub role: String,
+    pub content: AnthropicContent,
+}
+
+// Anthropic content is either a plain string or a list of typed blocks
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum AnthropicContent {
+    Text(String),
+    Blocks(Vec<AnthropicContentBlock>),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnthropicContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: AnthropicSource,
+    },
+    Document {
+        source: AnthropicSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        #[serde(default)]
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content: Option<AnthropicContent>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        is_error: Option<bool>,
+    },
+    Thinking {
+        thinking: String,
+        #[serde(default)]
+        signature: String,
+    },
+    RedactedThinking {
+        #[serde(default)]
+        data: String,
+    },
+    #[serde(other)]
+    Unsupported,
+}
+
+// Source of an image/document block: base64 payload or remote URL
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AnthropicSource {
+    pub r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AnthropicTool {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_schema: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AnthropicThinking {
+    pub r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget_tokens: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct AnthropicMessagesResponse {
+    pub id: String,
+    pub r#type: String,
+    pub role: String,
+    pub model: String,
+    pub content: Vec<AnthropicContentBlock>,
+    pub stop_reason: Option<String>,
+    pub stop_sequence: Option<String>,
+    pub usage: AnthropicUsage,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AnthropicUsage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AnthropicErrorResponse {
+    pub r#type: String,
+    pub error: AnthropicError,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AnthropicError {
+    pub r#type: String,
+    pub message: String,
+}
+
+// Legacy text completions (/v1/completions) request format
+#[derive(Deserialize, Serialize)]
+pub struct CompletionRequest {
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<CompletionPrompt>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<StopSequences>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub echo: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+// Prompt is a string or a batch of strings, token arrays are not supported
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum CompletionPrompt {
+    Text(String),
+    Batch(Vec<String>),
+    Tokens(serde_json::Value),
+}
+
+// stop accepts a single string or an array of strings
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum StopSequences {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl StopSequences {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            StopSequences::Single(stop) => vec![stop],
+            StopSequences::Multiple(stops) => stops,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TextCompletionResponse {
+    pub id: String,
+    pub object: String,
+    pub created: i64,
+    pub model: String,
+    pub choices: Vec<TextCompletionChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+pub struct TextCompletionChoice {
+    pub text: String,
+    pub index: u32,
+    pub logprobs: Option<serde_json::Value>,
+    pub finish_reason: Option<String>,
+}
+
+// Image generation (/v1/images/generations) request format
+#[derive(Deserialize, Serialize)]
+pub struct ImageGenerationRequest {
+    pub prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ImageGenerationResponse {
+    pub created: i64,
+    pub data: Vec<ImageData>,
+}
+
+#[derive(Serialize)]
+pub struct ImageData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub b64_json: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revised_prompt: Option<String>,
+}
+
+// Text-to-speech (/v1/audio/speech) request format
+#[derive(Deserialize, Serialize)]
+pub struct SpeechRequest {
+    pub input: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voice: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<String>,
+}
+
+// Google Gemini generateContent request format
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiRequest {
+    #[serde(default)]
+    pub contents: Vec<GeminiContent>,
+    #[serde(alias = "system_instruction", skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<GeminiContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<GeminiTool>>,
+    #[serde(alias = "tool_config", skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<serde_json::Value>,
+    #[serde(alias = "generation_config", skip_serializing_if = "Option::is_none")]
+    pub generation_config: Option<GeminiGenerationConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct GeminiContent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub parts: Vec<GeminiPart>,
+}
+
+// A part holds exactly one of the optional fields
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiPart {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thought: Option<bool>,
+    #[serde(alias = "inline_data", skip_serializing_if = "Option::is_none")]
+    pub inline_data: Option<GeminiBlob>,
+    #[serde(alias = "file_data", skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<GeminiFileData>,
+    #[serde(alias = "function_call", skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<GeminiFunctionCall>,
+    #[serde(alias = "function_response", skip_serializing_if = "Option::is_none")]
+    pub function_response: Option<GeminiFunctionResponse>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiBlob {
+    #[serde(alias = "mime_type")]
+    pub mime_type: String,
+    pub data: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiFileData {
+    #[serde(alias = "mime_type", skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(alias = "file_uri")]
+    pub file_uri: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct GeminiFunctionCall {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct GeminiFunctionResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub response: serde_json::Value,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiTool {
+    #[serde(
+        alias = "function_declarations",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub function_declarations: Option<Vec<GeminiFunctionDeclaration>>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiFunctionDeclaration {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+    #[serde(
+        alias = "parameters_json_schema",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub parameters_json_schema: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiGenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(alias = "top_p", skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(alias = "max_output_tokens", skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(alias = "stop_sequences", skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(alias = "response_mime_type", skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<String>,
+    #[serde(alias = "response_schema", skip_serializing_if = "Option::is_none")]
+    pub response_schema: Option<serde_json::Value>,
+    #[serde(alias = "thinking_config", skip_serializing_if = "Option::is_none")]
+    pub thinking_config: Option<GeminiThinkingConfig>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiThinkingConfig {
+    #[serde(alias = "thinking_budget", skip_serializing_if = "Option::is_none")]
+    pub thinking_budget: Option<i32>,
+    #[serde(alias = "include_thoughts", skip_serializing_if = "Option::is_none")]
+    pub include_thoughts: Option<bool>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiResponse {
+    pub candidates: Vec<GeminiCandidate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_metadata: Option<GeminiUsageMetadata>,
+    pub model_version: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiCandidate {
+    pub content: GeminiContent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    pub index: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiUsageMetadata {
+    pub prompt_token_count: u32,
+    pub candidates_token_count: u32,
+    pub total_token_count: u32,
+}
+
+// Ollama /api/chat and /api/generate request format
+#[derive(Deserialize, Serialize)]
+pub struct OllamaChatRequest {
+    pub model: String,
+    #[serde(default)]
+    pub messages: Vec<OllamaMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<OllamaOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub think: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct OllamaMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<OllamaToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_name: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct OllamaToolCall {
+    pub function: OllamaFunction,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct OllamaFunction {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: serde_json::Value,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct OllamaGenerateRequest {
+    pub model: String,
+    #[serde(default)]
+    pub prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<OllamaOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub think: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct OllamaShowRequest {
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+// OpenAI Responses API (/v1/responses) request format
+#[derive(Deserialize, Serialize)]
+pub struct ResponsesRequest {
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<ResponsesInput>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_response_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+// Responses input is either a plain string or a list of input items
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum ResponsesInput {
+    Text(String),
+    Items(Vec<ResponsesInputItem>),
+}
+
+// Items with an explicit type are tried first, bare {role, content} objects are messages
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum ResponsesInputItem {
+    Typed(ResponsesTypedItem),
+    Message(ResponsesMessage),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponsesTypedItem {
+    Message(ResponsesMessage),
+    FunctionCall {
+        call_id: String,
+        name: String,
+        #[serde(default)]
+        arguments: String,
+    },
+    FunctionCallOutput {
+        call_id: String,
+        output: serde_json::Value,
+    },
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ResponsesMessage {
+    pub role: String,
+    pub content: ResponsesContent,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum ResponsesContent {
+    Text(String),
+    Parts(Vec<ResponsesContentPart>),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponsesContentPart {
+    InputText {
+        text: String,
+    },
+    OutputText {
+        text: String,
+    },
+    InputImage {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        image_url: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file_id: Option<String>,
+    },
+    InputFile {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file_data: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filename: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file_id: Option<String>,
+    },
+    Refusal {
+        refusal: String,
+    },
+    #[serde(other)]
+    Unsupported,
+}
+
+// A stored Responses API result, used to rebuild history for previous_response_id
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StoredResponse {
+    pub owner: String,
+    pub response: serde_json::Value,
+    pub messages: Vec<Message>,
+}
+
+// Admin API request to issue a proxy API key
+#[derive(Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    // Keys without a Poe token are served from the token pool
+    #[serde(default)]
+    pub poe_token: Option<String>,
+    // Unix timestamp, takes precedence over expires_in_days
+    pub expires_at: Option<i64>,
+    pub expires_in_days: Option<u32>,
+    #[serde(default)]
+    pub limits: KeyLimits,
+    #[serde(default)]
+    pub budget: KeyBudget,
+}
+
+// Admin API request to issue a scoped admin token
+#[derive(Deserialize)]
+pub struct CreateAdminTokenRequest {
+    pub name: String,
+    pub scopes: Vec<AdminScope>,
+    // Unix timestamp, takes precedence over expires_in_days
+    pub expires_at: Option<i64>,
+    pub expires_in_days: Option<u32>,
+}
+
+// Admin API request to change the limits or budget of an existing key
+#[derive(Deserialize)]
+pub struct UpdateApiKeyRequest {
+    pub limits: Option<KeyLimits>,
+    pub budget: Option<KeyBudget>,
+}
+
+// Per-key rate limits, unset fields fall back to RATE_LIMIT_RPM / RATE_LIMIT_TPM
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct KeyLimits {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpm: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tpm: Option<u32>,
+}
+
+// Per-key usage budgets, counted per UTC day and calendar month
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct KeyBudget {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_requests: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly_requests: Option<u64>,
+}
+
+impl KeyBudget {
+    pub fn is_unlimited(&self) -> bool {
+        self.daily_tokens.is_none()
+            && self.daily_requests.is_none()
+            && self.monthly_tokens.is_none()
+            && self.monthly_requests.is_none()
+    }
+}
+
 #[derive(Default, Serialize, Deserialize)]
 pub(crate) struct Config {
     pub(crate) enable: Option<bool>,
@@ -285,6 +1014,59 @@ pub(crate) struct Config {
     pub(crate) api_token: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) use_v1_api: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) token_pool: Option<TokenPoolConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) cors: Option<CorsConfig>,
+}
+
+// Cross-origin access for browser clients, no origin is allowed unless listed
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub(crate) struct CorsConfig {
+    // Exact origins or `*` patterns, e.g. `https://*.example.com`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) allowed_origins: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) allowed_methods: Option<Vec<String>>,
+    // Header names or `*` patterns, e.g. `x-stainless-*`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) allowed_headers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_age: Option<u64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) allow_credentials: bool,
+    // Reflect every Origin, the pre-allowlist behaviour
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) permissive: bool,
+}
+
+// Upstream Poe tokens shared by proxy API keys that have no token of their own
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct TokenPoolConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) strategy: Option<PoolStrategy>,
+    // How long a token that ran out of points is skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) cooldown_seconds: Option<u64>,
+    #[serde(default)]
+    pub(crate) tokens: Vec<PoolTokenConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PoolStrategy {
+    #[default]
+    RoundRobin,
+    Weighted,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct PoolTokenConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+    pub(crate) token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) weight: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -302,6 +1084,11 @@ pub(crate) struct ModelConfig {
     pub(crate) replace_response: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) enable: Option<bool>,
+    // Per-client requests / tokens per minute for this model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) rpm: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tpm: Option<u32>,
 }
 
 #[cfg(test)]
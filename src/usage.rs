@@ -0,0 +1,254 @@
+use crate::cache::get_persistent_db;
+use crate::types::KeyBudget;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::{debug, error};
+
+const USAGE_TREE: &str = "usage";
+
+/// Usage of one client and model within one period
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct UsageCounter {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl UsageCounter {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    fn add(&mut self, other: &UsageCounter) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// Usage of one client within a day or month, with a per-model breakdown
+#[derive(Serialize, Default, Debug)]
+pub struct PeriodUsage {
+    pub period: String,
+    #[serde(flatten)]
+    pub total: UsageCounter,
+    pub models: BTreeMap<String, UsageCounter>,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct ClientUsage {
+    pub daily: PeriodUsage,
+    pub monthly: PeriodUsage,
+}
+
+// Budgets reset on UTC day and calendar month boundaries
+fn current_periods(now: DateTime<Utc>) -> (String, String) {
+    (
+        now.format("%Y-%m-%d").to_string(),
+        now.format("%Y-%m").to_string(),
+    )
+}
+
+// Entries are keyed "client|period|model" so a client's period totals are one prefix scan
+fn usage_key(client: &str, period: &str, model: &str) -> String {
+    format!("{}|{}|{}", client, period, model)
+}
+
+fn open_usage_tree() -> Result<sled::Tree, String> {
+    get_persistent_db()
+        .open_tree(USAGE_TREE)
+        .map_err(|e| format!("Unable to open usage tree: {}", e))
+}
+
+fn add_usage(client: &str, model: &str, delta: UsageCounter) {
+    let tree = match open_usage_tree() {
+        Ok(tree) => tree,
+        Err(e) => {
+            error!("❌ {}", e);
+            return;
+        }
+    };
+    let (day, month) = current_periods(Utc::now());
+    for period in [day, month] {
+        let key = usage_key(client, &period, model);
+        let result = tree.update_and_fetch(key.as_bytes(), |old| {
+            let mut counter: UsageCounter = old
+                .and_then(|bytes| serde_json::from_slice(bytes).ok())
+                .unwrap_or_default();
+            counter.add(&delta);
+            serde_json::to_vec(&counter).ok()
+        });
+        if let Err(e) = result {
+            error!("❌ Failed to record usage | Key: {} | Error: {}", key, e);
+        }
+    }
+}
+
+/// Add the tokens of one reply, counting the request itself with its first reply
+pub fn record_reply(
+    client: &str,
+    model: &str,
+    count_request: bool,
+    prompt_tokens: u32,
+    completion_tokens: u32,
+) {
+    add_usage(
+        client,
+        model,
+        UsageCounter {
+            requests: u64::from(count_request),
+            prompt_tokens: u64::from(prompt_tokens),
+            completion_tokens: u64::from(completion_tokens),
+        },
+    );
+    debug!(
+        "📒 Usage recorded | Client: {} | Model: {} | Prompt: {} | Completion: {}",
+        client, model, prompt_tokens, completion_tokens
+    );
+}
+
+fn period_total(tree: &sled::Tree, client: &str, period: &str) -> UsageCounter {
+    let mut total = UsageCounter::default();
+    tree.scan_prefix(format!("{}|{}|", client, period).as_bytes())
+        .values()
+        .filter_map(|value| value.ok())
+        .filter_map(|bytes| serde_json::from_slice::<UsageCounter>(&bytes).ok())
+        .for_each(|counter| total.add(&counter));
+    total
+}
+
+// First budget that the usage has reached
+fn exhausted_budget(
+    budget: &KeyBudget,
+    daily: &UsageCounter,
+    monthly: &UsageCounter,
+) -> Option<String> {
+    [
+        ("daily token", budget.daily_tokens, daily.total_tokens()),
+        ("daily request", budget.daily_requests, daily.requests),
+        (
+            "monthly token",
+            budget.monthly_tokens,
+            monthly.total_tokens(),
+        ),
+        ("monthly request", budget.monthly_requests, monthly.requests),
+    ]
+    .into_iter()
+    .find_map(|(name, limit, used)| {
+        limit
+            .filter(|limit| used >= *limit)
+            .map(|limit| format!("{} budget exhausted ({} of {} used)", name, used, limit))
+    })
+}
+
+/// Check the client's budgets, returns the reason when one is exhausted
+pub fn check_budget(client: &str, budget: &KeyBudget) -> Result<(), String> {
+    if budget.is_unlimited() {
+        return Ok(());
+    }
+    // A store failure should not lock clients out
+    let tree = match open_usage_tree() {
+        Ok(tree) => tree,
+        Err(e) => {
+            error!("❌ {}", e);
+            return Ok(());
+        }
+    };
+    let (day, month) = current_periods(Utc::now());
+    let daily = period_total(&tree, client, &day);
+    let monthly = period_total(&tree, client, &month);
+    match exhausted_budget(budget, &daily, &monthly) {
+        Some(reason) => Err(reason),
+        None => Ok(()),
+    }
+}
+
+/// Current day and month usage of every client, for the admin API
+pub fn usage_report() -> BTreeMap<String, ClientUsage> {
+    let mut report: BTreeMap<String, ClientUsage> = BTreeMap::new();
+    let tree = match open_usage_tree() {
+        Ok(tree) => tree,
+        Err(e) => {
+            error!("❌ {}", e);
+            return report;
+        }
+    };
+    let (day, month) = current_periods(Utc::now());
+    for (key, value) in tree.iter().filter_map(|entry| entry.ok()) {
+        let key = String::from_utf8_lossy(&key);
+        let mut parts = key.splitn(3, '|');
+        let (Some(client), Some(period), Some(model)) = (parts.next(), parts.next(), parts.next())
+        else {
+            continue;
+        };
+        let Ok(counter) = serde_json::from_slice::<UsageCounter>(&value) else {
+            continue;
+        };
+        let usage = report.entry(client.to_string()).or_default();
+        let period_usage = if period == day {
+            &mut usage.daily
+        } else if period == month {
+            &mut usage.monthly
+        } else {
+            continue;
+        };
+        period_usage.total.add(&counter);
+        period_usage.models.insert(model.to_string(), counter);
+    }
+    for usage in report.values_mut() {
+        usage.daily.period = day.clone();
+        usage.monthly.period = month.clone();
+    }
+    // Clients that only have usage in earlier periods are left out
+    report.retain(|_, usage| !usage.daily.models.is_empty() || !usage.monthly.models.is_empty());
+    report
+}
+
+/// Empty usage of the current periods, for clients that have not sent requests yet
+pub fn empty_usage() -> ClientUsage {
+    let (day, month) = current_periods(Utc::now());
+    ClientUsage {
+        daily: PeriodUsage {
+            period: day,
+            ..Default::default()
+        },
+        monthly: PeriodUsage {
+            period: month,
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exhausted_budget_reports_first_reached_limit() {
+        let budget = KeyBudget {
+            daily_tokens: Some(1_000),
+            monthly_requests: Some(50),
+            ..Default::default()
+        };
+        let daily = UsageCounter {
+            requests: 3,
+            prompt_tokens: 400,
+            completion_tokens: 500,
+        };
+        let monthly = UsageCounter {
+            requests: 49,
+            ..daily
+        };
+        assert_eq!(exhausted_budget(&budget, &daily, &monthly), None);
+
+        let daily = UsageCounter {
+            completion_tokens: 600,
+            ..daily
+        };
+        assert_eq!(
+            exhausted_budget(&budget, &daily, &monthly).as_deref(),
+            Some("daily token budget exhausted (1000 of 1000 used)")
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use crate::poe_client::PoeClientWrapper;
-use crate::types::{Config, ImageUrlContent, Message, OpenAiContent, OpenAiContentItem};
+use crate::types::{
+    Config, ImageUrlContent, Message, OpenAiContent, OpenAiContentItem, ResponseFormat, ToolChoice,
+};
 use crate::types::{OpenAIError, OpenAIErrorResponse};
 use base64::prelude::*;
 use nanoid::nanoid;
@@ -10,14 +12,30 @@ use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
+use std::sync::OnceLock;
 use tiktoken_rs::o200k_base;
 use tracing::{debug, error, info, warn};
 
+/// Content part that cannot be forwarded to Poe, reported to the client as a 400 error
+#[derive(Debug)]
+pub struct UnsupportedContentError(pub String);
+
+impl std::fmt::Display for UnsupportedContentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedContentError {}
+
 // Process files/images in messages
 pub async fn process_message_images(
     poe_client: &PoeClientWrapper,
     messages: &mut [Message],
 ) -> Result<(), Box<dyn std::error::Error>> {
+    // input_audio and file parts become image_url items so they share the upload path below
+    normalize_attachment_parts(messages)?;
+
     // Collect URLs that need processing
     let mut external_urls = Vec::new();
     let mut data_urls = Vec::new();
@@ -384,6 +402,128 @@ pub async fn process_message_images(
     Ok(())
 }
 
+/// Convert input_audio and file content parts into image_url items (data URLs or remote URLs)
+pub fn normalize_attachment_parts(messages: &mut [Message]) -> Result<(), UnsupportedContentError> {
+    for message in messages.iter_mut() {
+        let Some(OpenAiContent::Multi(items)) = &mut message.content else {
+            continue;
+        };
+        for item in items.iter_mut() {
+            let url = match item {
+                OpenAiContentItem::InputAudio { audio, .. } => {
+                    Some(input_audio_to_data_url(audio)?)
+                }
+                OpenAiContentItem::Other(value) => {
+                    match value.get("type").and_then(|t| t.as_str()) {
+                        Some("input_audio") => Some(input_audio_to_data_url(
+                            value.get("input_audio").unwrap_or(&Value::Null),
+                        )?),
+                        Some("file") => {
+                            Some(file_part_to_url(value.get("file").unwrap_or(&Value::Null))?)
+                        }
+                        // Refusals in assistant history carry no content to forward
+                        Some("refusal") => None,
+                        other => {
+                            return Err(UnsupportedContentError(format!(
+                                "Unsupported content part type '{}' in {} message",
+                                other.unwrap_or("unknown"),
+                                message.role
+                            )));
+                        }
+                    }
+                }
+                _ => None,
+            };
+            if let Some(url) = url {
+                debug!("📎 Converted content part into attachment");
+                *item = OpenAiContentItem::ImageUrl {
+                    r#type: Some("image_url".to_string()),
+                    image_url: ImageUrlContent {
+                        url,
+                        extra: HashMap::new(),
+                    },
+                    extra: HashMap::new(),
+                };
+            }
+        }
+    }
+    Ok(())
+}
+
+// {"data": "<base64>", "format": "wav"} -> data:audio/wav;base64,...
+fn input_audio_to_data_url(audio: &Value) -> Result<String, UnsupportedContentError> {
+    let data = audio
+        .get("data")
+        .and_then(|d| d.as_str())
+        .filter(|d| !d.is_empty())
+        .ok_or_else(|| {
+            UnsupportedContentError("input_audio content part requires base64 'data'".to_string())
+        })?;
+    if data.starts_with("data:") {
+        return Ok(data.to_string());
+    }
+    let mime_type = match audio
+        .get("format")
+        .and_then(|f| f.as_str())
+        .unwrap_or("wav")
+    {
+        "mp3" => "audio/mpeg".to_string(),
+        format => format!("audio/{}", format),
+    };
+    Ok(format!("data:{};base64,{}", mime_type, data))
+}
+
+// {"file_data": "data:...;base64,...", "filename": "...", "file_id": "..."} -> attachment URL
+fn file_part_to_url(file: &Value) -> Result<String, UnsupportedContentError> {
+    if let Some(file_data) = file.get("file_data").and_then(|d| d.as_str()) {
+        if file_data.starts_with("data:") {
+            return Ok(file_data.to_string());
+        }
+        // Raw base64, derive the MIME type from the file name
+        let filename = file.get("filename").and_then(|f| f.as_str()).unwrap_or("");
+        return Ok(format!(
+            "data:{};base64,{}",
+            mime_type_from_file_name(filename),
+            file_data
+        ));
+    }
+    match file.get("file_id").and_then(|f| f.as_str()) {
+        Some(file_id) if file_id.starts_with("http://") || file_id.starts_with("https://") => {
+            Ok(file_id.to_string())
+        }
+        Some(file_id) => Err(UnsupportedContentError(format!(
+            "file_id '{}' cannot be resolved because this proxy has no Files API, send the file inline as file_data",
+            file_id
+        ))),
+        None => Err(UnsupportedContentError(
+            "file content part requires file_data or file_id".to_string(),
+        )),
+    }
+}
+
+fn mime_type_from_file_name(filename: &str) -> &'static str {
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "pdf" => "application/pdf",
+        "txt" | "md" => "text/plain",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "m4a" => "audio/mp4",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
 // Get pure text content from OpenAIContent
 pub fn get_text_from_openai_content(content: &Option<OpenAiContent>) -> String {
     match content {
@@ -427,9 +567,9 @@ pub fn get_text_from_openai_content(content: &Option<OpenAiContent>) -> String {
     }
 }
 
-// Check if URL is a Poe CDN link
+// Check if URL is a Poe CDN link, the trailing slash keeps look-alike hosts out
 pub fn is_poe_cdn_url(url: &str) -> bool {
-    url.starts_with("https://pfst.cf2.poecdn.net")
+    url.starts_with("https://pfst.cf2.poecdn.net/")
 }
 
 // Extract Poe CDN links from message
@@ -550,9 +690,14 @@ fn mime_type_to_extension(mime_type: &str) -> Option<&str> {
         "application/zip" => Some("zip"),
         "application/x-tar" => Some("tar"),
         "application/x-gzip" => Some("gz"),
-        "audio/mpeg" => Some("mp3"),
-        "audio/wav" => Some("wav"),
+        "audio/mpeg" | "audio/mp3" => Some("mp3"),
+        "audio/wav" | "audio/x-wav" | "audio/wave" => Some("wav"),
         "audio/ogg" => Some("ogg"),
+        "audio/mp4" | "audio/m4a" | "audio/x-m4a" => Some("m4a"),
+        "audio/webm" => Some("webm"),
+        "audio/flac" | "audio/x-flac" => Some("flac"),
+        "audio/aac" => Some("aac"),
+        "audio/opus" => Some("opus"),
         "video/mp4" => Some("mp4"),
         "video/mpeg" => Some("mpeg"),
         "video/quicktime" => Some("mov"),
@@ -660,8 +805,7 @@ pub fn load_config_from_yaml() -> Result<Config, String> {
             enable: Some(false),
             models: std::collections::HashMap::new(),
             custom_models: None,
-            api_token: None,
-            use_v1_api: None,
+            ..Default::default()
         })
     }
 }
@@ -679,6 +823,30 @@ pub fn count_tokens(text: &str) -> u32 {
     tokens.len() as u32
 }
 
+/// Cut text down to at most max_tokens tokens
+pub fn truncate_to_tokens(text: &str, max_tokens: u32) -> String {
+    let bpe = match o200k_base() {
+        Ok(bpe) => bpe,
+        Err(e) => {
+            error!("❌ Failed to initialize BPE encoder: {}", e);
+            return text.to_string();
+        }
+    };
+    let tokens = bpe.encode_with_special_tokens(text);
+    if tokens.len() <= max_tokens as usize {
+        return text.to_string();
+    }
+    // Step back when the cut lands inside a multi-byte character
+    let mut end = max_tokens as usize;
+    while end > 0 {
+        if let Ok(decoded) = bpe.decode(tokens[..end].to_vec()) {
+            return decoded;
+        }
+        end -= 1;
+    }
+    String::new()
+}
+
 /// Calculate token count for message list
 pub fn count_message_tokens(messages: &[Message]) -> u32 {
     let mut total_tokens = 0;
@@ -737,6 +905,13 @@ pub fn hash_base64_content(base64_str: &str) -> String {
     hash
 }
 
+/// Calculate SHA256 hash of a bearer token, used to identify clients without storing secrets
+pub fn hash_token(token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(token.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
 /// Process message content, add appropriate suffix based on request parameters
 pub fn process_message_content_with_suffixes(
     content: &str,
@@ -805,6 +980,311 @@ pub fn process_message_content_with_suffixes(
     processed_content
 }
 
+/// Convert OpenAI image size/quality parameters into Poe image bot suffix flags
+pub fn image_generation_suffixes(size: Option<&str>, quality: Option<&str>) -> String {
+    let mut suffixes = String::new();
+
+    // size is "WIDTHxHEIGHT", Poe image bots take an aspect ratio
+    if let Some((width, height)) = size
+        .and_then(|s| s.split_once('x'))
+        .and_then(|(w, h)| Some((w.trim().parse::<u32>().ok()?, h.trim().parse::<u32>().ok()?)))
+        .filter(|(w, h)| *w > 0 && *h > 0)
+    {
+        let (mut a, mut b) = (width, height);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        let suffix = format!(" --aspect {}:{}", width / a, height / a);
+        debug!("🖼️ Adding aspect suffix: {}", suffix);
+        suffixes.push_str(&suffix);
+    } else if let Some(size) = size.filter(|s| *s != "auto") {
+        warn!("⚠️ Invalid image size value: {}", size);
+    }
+
+    // dall-e-3 uses standard/hd, gpt-image-1 uses low/medium/high
+    let quality = match quality {
+        Some("hd") => Some("high"),
+        Some("standard") => Some("medium"),
+        Some(q @ ("low" | "medium" | "high")) => Some(q),
+        Some("auto") | None => None,
+        Some(other) => {
+            warn!("⚠️ Invalid image quality value: {}", other);
+            None
+        }
+    };
+    if let Some(quality) = quality {
+        let suffix = format!(" --quality {}", quality);
+        debug!("🖼️ Adding quality suffix: {}", suffix);
+        suffixes.push_str(&suffix);
+    }
+
+    suffixes
+}
+
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+// A stalled CDN must not hold the request open forever
+fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(std::time::Duration::from_secs(10))
+        .read_timeout(std::time::Duration::from_secs(60))
+        .build()
+        .unwrap_or_else(|e| {
+            warn!("⚠️ Failed to build download client, using defaults: {}", e);
+            reqwest::Client::new()
+        })
+}
+
+/// Start fetching a remote file (e.g. a Poe CDN attachment), fails on non-success status
+pub async fn fetch_file(url: &str) -> Result<reqwest::Response, String> {
+    let client = HTTP_CLIENT.get_or_init(http_client);
+    debug!("📥 Downloading file: {}", url);
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| format!("Download request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("Download failed with status {}", response.status()));
+    }
+    Ok(response)
+}
+
+/// Download a remote file, returns the bytes and content type
+pub async fn download_file(
+    url: &str,
+    max_size: usize,
+) -> Result<(Vec<u8>, Option<String>), String> {
+    let mut response = fetch_file(url).await?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let too_large = || {
+        format!(
+            "Download exceeds the size limit of {}",
+            format_bytes_length(max_size)
+        )
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_size as u64)
+    {
+        return Err(too_large());
+    }
+    // Content-Length may be missing or wrong, so the limit is also checked while reading
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("Failed to read download body: {}", e))?
+    {
+        if bytes.len() + chunk.len() > max_size {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    debug!("✅ Downloaded {}", format_bytes_length(bytes.len()));
+    Ok((bytes, content_type))
+}
+
+/// Remove a surrounding markdown code fence (```lang ... ```) from a bot reply
+pub fn strip_code_fences(text: &str) -> String {
+    let trimmed = text.trim();
+    let Some(body) = trimmed
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+    else {
+        return trimmed.to_string();
+    };
+    // Drop the optional language tag on the opening fence line
+    let body = match body.split_once('\n') {
+        Some((_, content)) => content,
+        None => body,
+    };
+    body.trim().to_string()
+}
+
+/// Parse the request's response_format, `None` for plain text output
+pub fn parse_response_format(value: Option<&Value>) -> Result<Option<ResponseFormat>, String> {
+    let Some(value) = value.filter(|v| !v.is_null()) else {
+        return Ok(None);
+    };
+    match value.get("type").and_then(|t| t.as_str()) {
+        Some("text") => Ok(None),
+        Some("json_object") => Ok(Some(ResponseFormat::JsonObject)),
+        Some("json_schema") => {
+            let json_schema = value
+                .get("json_schema")
+                .ok_or("response_format.json_schema is required for type 'json_schema'")?;
+            let schema = json_schema
+                .get("schema")
+                .cloned()
+                .unwrap_or_else(|| serde_json::json!({}));
+            jsonschema::validator_for(&schema)
+                .map_err(|e| format!("Invalid response_format.json_schema.schema: {}", e))?;
+            let name = json_schema
+                .get("name")
+                .and_then(|n| n.as_str())
+                .unwrap_or("response")
+                .to_string();
+            Ok(Some(ResponseFormat::JsonSchema { name, schema }))
+        }
+        Some(other) => Err(format!("Unsupported response_format type: {}", other)),
+        None => Err("response_format.type is required".to_string()),
+    }
+}
+
+/// Instruction text telling the bot to answer with JSON only
+pub fn response_format_instructions(format: &ResponseFormat) -> String {
+    match format {
+        ResponseFormat::JsonObject => "Respond only with a valid JSON object. Do not wrap it in code fences or add any other text.".to_string(),
+        ResponseFormat::JsonSchema { name, schema } => format!(
+            "Respond only with JSON that conforms to the following JSON Schema (\"{}\"). Do not wrap it in code fences or add any other text.\n{}",
+            name,
+            serde_json::to_string_pretty(schema).unwrap_or_default()
+        ),
+    }
+}
+
+/// Append proxy instructions (structured output, forced tool calls) to the last user message
+pub fn append_instructions_to_last_user_message(messages: &mut Vec<Message>, instructions: String) {
+    let Some(message) = messages.iter_mut().rev().find(|m| m.role == "user") else {
+        messages.push(Message {
+            role: "user".to_string(),
+            content: Some(OpenAiContent::Text(instructions)),
+            ..Default::default()
+        });
+        return;
+    };
+    message.content = Some(match message.content.take() {
+        Some(OpenAiContent::Text(text)) => {
+            OpenAiContent::Text(format!("{}\n\n{}", text, instructions))
+        }
+        Some(OpenAiContent::Multi(mut items)) => {
+            items.push(OpenAiContentItem::Text {
+                r#type: Some("text".to_string()),
+                text: instructions,
+                extra: HashMap::new(),
+            });
+            OpenAiContent::Multi(items)
+        }
+        None => OpenAiContent::Text(instructions),
+    });
+}
+
+/// Check a finished reply against response_format, returning the JSON text without code fences
+pub fn validate_structured_output(
+    content: &str,
+    format: &ResponseFormat,
+) -> Result<String, String> {
+    let text = strip_code_fences(content);
+    let value: Value =
+        serde_json::from_str(&text).map_err(|e| format!("Reply is not valid JSON: {}", e))?;
+    match format {
+        ResponseFormat::JsonObject => {
+            if !value.is_object() {
+                return Err("Reply must be a JSON object".to_string());
+            }
+        }
+        ResponseFormat::JsonSchema { schema, .. } => {
+            let validator = jsonschema::validator_for(schema)
+                .map_err(|e| format!("Invalid JSON schema: {}", e))?;
+            let errors: Vec<String> = validator
+                .iter_errors(&value)
+                .take(5)
+                .map(|e| e.to_string())
+                .collect();
+            if !errors.is_empty() {
+                return Err(format!(
+                    "Reply does not match the JSON schema: {}",
+                    errors.join("; ")
+                ));
+            }
+        }
+    }
+    Ok(text)
+}
+
+/// Parse tool_choice ("none" | "auto" | "required" | {"type": "function", "function": {"name"}})
+pub fn parse_tool_choice(
+    value: Option<&Value>,
+    tools: Option<&[poe_api_process::types::ChatTool]>,
+) -> Result<ToolChoice, String> {
+    let choice = match value {
+        None | Some(Value::Null) => return Ok(ToolChoice::Auto),
+        Some(Value::String(s)) => match s.as_str() {
+            "auto" => ToolChoice::Auto,
+            "none" => ToolChoice::None,
+            "required" => ToolChoice::Required,
+            other => return Err(format!("Unsupported tool_choice value: {}", other)),
+        },
+        Some(value) => {
+            let name = value
+                .get("function")
+                .and_then(|f| f.get("name"))
+                .and_then(|n| n.as_str())
+                .ok_or("tool_choice object must be {\"type\": \"function\", \"function\": {\"name\": ...}}")?;
+            ToolChoice::Function(name.to_string())
+        }
+    };
+
+    let tools = tools.unwrap_or_default();
+    match &choice {
+        ToolChoice::Required if tools.is_empty() => {
+            Err("tool_choice 'required' needs at least one tool".to_string())
+        }
+        ToolChoice::Function(name) if !tools.iter().any(|t| &t.function.name == name) => {
+            Err(format!(
+                "tool_choice names function '{}' which is not in tools",
+                name
+            ))
+        }
+        _ => Ok(choice),
+    }
+}
+
+/// Restrict the tool list according to tool_choice
+pub fn apply_tool_choice(
+    tools: Option<Vec<poe_api_process::types::ChatTool>>,
+    choice: &ToolChoice,
+) -> Option<Vec<poe_api_process::types::ChatTool>> {
+    match choice {
+        ToolChoice::None => {
+            debug!("🔧 tool_choice is none, dropping tools for this turn");
+            None
+        }
+        ToolChoice::Function(name) => {
+            debug!("🔧 tool_choice forces function '{}'", name);
+            tools
+                .map(|tools| {
+                    tools
+                        .into_iter()
+                        .filter(|tool| &tool.function.name == name)
+                        .collect::<Vec<_>>()
+                })
+                .filter(|tools| !tools.is_empty())
+        }
+        ToolChoice::Auto | ToolChoice::Required => tools,
+    }
+}
+
+/// Instruction text for tool_choice values that make a tool call mandatory
+pub fn tool_choice_instructions(choice: &ToolChoice) -> Option<String> {
+    match choice {
+        ToolChoice::Required => Some(
+            "You must respond by calling one of the provided tools. Do not answer in plain text."
+                .to_string(),
+        ),
+        ToolChoice::Function(name) => Some(format!(
+            "You must respond by calling the `{}` tool. Do not answer in plain text.",
+            name
+        )),
+        ToolChoice::Auto | ToolChoice::None => None,
+    }
+}
+
 /// Filter out tools that only have name fields, these tools should not be passed to poe_api_process
 pub fn filter_tools_for_poe(
     tools: &Option<Vec<poe_api_process::types::ChatTool>>,
@@ -1009,6 +1489,141 @@ mod tests {
 
         assert!(filter_tools_for_poe(&Some(vec![tool])).is_none());
     }
+
+    #[test]
+    fn normalize_converts_audio_and_file_parts() {
+        let mut messages = vec![Message {
+            role: "user".to_string(),
+            content: Some(OpenAiContent::Multi(vec![
+                OpenAiContentItem::Other(serde_json::json!({
+                    "type": "input_audio",
+                    "input_audio": {"data": "AAAA", "format": "mp3"}
+                })),
+                OpenAiContentItem::Other(serde_json::json!({
+                    "type": "file",
+                    "file": {"file_data": "JVBERi0=", "filename": "report.pdf"}
+                })),
+            ])),
+            ..Default::default()
+        }];
+        normalize_attachment_parts(&mut messages).expect("parts are supported");
+        let Some(OpenAiContent::Multi(items)) = &messages[0].content else {
+            panic!("expected multi content");
+        };
+        assert_eq!(
+            items[0].as_image_url().map(|u| u.url.as_str()),
+            Some("data:audio/mpeg;base64,AAAA")
+        );
+        assert_eq!(
+            items[1].as_image_url().map(|u| u.url.as_str()),
+            Some("data:application/pdf;base64,JVBERi0=")
+        );
+
+        let mut unresolvable = vec![Message {
+            role: "user".to_string(),
+            content: Some(OpenAiContent::Multi(vec![OpenAiContentItem::Other(
+                serde_json::json!({"type": "file", "file": {"file_id": "file-abc"}}),
+            )])),
+            ..Default::default()
+        }];
+        assert!(normalize_attachment_parts(&mut unresolvable).is_err());
+    }
+
+    #[test]
+    fn image_suffixes_reduce_size_to_aspect_ratio() {
+        assert_eq!(
+            image_generation_suffixes(Some("1792x1024"), Some("hd")),
+            " --aspect 7:4 --quality high"
+        );
+        assert_eq!(image_generation_suffixes(Some("auto"), Some("auto")), "");
+    }
+
+    #[test]
+    fn tool_choice_constrains_tools() {
+        let tool = |name: &str| ChatTool {
+            r#type: "function".to_string(),
+            function: FunctionDefinition {
+                name: name.to_string(),
+                description: Some("tool".to_string()),
+                parameters: None,
+                returns: None,
+                strict: None,
+                extra: HashMap::new(),
+            },
+            extra: HashMap::new(),
+        };
+        let tools = vec![tool("search"), tool("fetch")];
+
+        let named = serde_json::json!({"type": "function", "function": {"name": "fetch"}});
+        let choice = parse_tool_choice(Some(&named), Some(&tools)).unwrap();
+        assert_eq!(choice, ToolChoice::Function("fetch".to_string()));
+        let constrained = apply_tool_choice(Some(tools.clone()), &choice).unwrap();
+        assert_eq!(constrained.len(), 1);
+        assert_eq!(constrained[0].function.name, "fetch");
+
+        let none = parse_tool_choice(Some(&serde_json::json!("none")), Some(&tools)).unwrap();
+        assert!(apply_tool_choice(Some(tools.clone()), &none).is_none());
+        let missing = serde_json::json!({"type": "function", "function": {"name": "nope"}});
+        assert!(parse_tool_choice(Some(&missing), Some(&tools)).is_err());
+        assert!(parse_tool_choice(Some(&serde_json::json!("required")), None).is_err());
+    }
+
+    #[test]
+    fn truncate_to_tokens_respects_limit() {
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(20);
+        let truncated = truncate_to_tokens(&text, 10);
+        assert_eq!(count_tokens(&truncated), 10);
+        assert!(text.starts_with(&truncated));
+        assert_eq!(truncate_to_tokens("short", 10), "short");
+    }
+
+    #[test]
+    fn structured_output_is_validated_against_schema() {
+        let format = parse_response_format(Some(&serde_json::json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": "person",
+                "schema": {
+                    "type": "object",
+                    "properties": {"age": {"type": "integer"}},
+                    "required": ["age"]
+                }
+            }
+        })))
+        .unwrap()
+        .expect("json_schema format");
+
+        let valid = validate_structured_output("```json\n{\"age\": 3}\n```", &format);
+        assert_eq!(valid.unwrap(), "{\"age\": 3}");
+        assert!(validate_structured_output("{\"age\": \"three\"}", &format).is_err());
+        assert!(validate_structured_output("Sure! Here it is", &format).is_err());
+    }
+
+    #[test]
+    fn response_format_instructions_go_to_last_user_message() {
+        let mut messages = vec![
+            Message {
+                role: "user".to_string(),
+                content: Some(OpenAiContent::Text("first".to_string())),
+                ..Default::default()
+            },
+            Message {
+                role: "user".to_string(),
+                content: Some(OpenAiContent::Text("second".to_string())),
+                ..Default::default()
+            },
+        ];
+        append_instructions_to_last_user_message(
+            &mut messages,
+            response_format_instructions(&ResponseFormat::JsonObject),
+        );
+        assert!(matches!(&messages[0].content, Some(OpenAiContent::Text(t)) if t == "first"));
+        assert!(matches!(
+            &messages[1].content,
+            Some(OpenAiContent::Text(t)) if t.starts_with("second\n\nRespond only with a valid JSON object")
+        ));
+        assert!(parse_response_format(Some(&serde_json::json!({"type": "xml"}))).is_err());
+    }
 }
 
 /// Redact sensitive JSON fields (token, password, *cookie* - case insensitive)